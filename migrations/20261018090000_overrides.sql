@@ -0,0 +1,14 @@
+CREATE TABLE user_overrides (
+    chat_id    INTEGER NOT NULL,
+    user_id    INTEGER NOT NULL,
+    prompt     TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT current_timestamp,
+
+    PRIMARY KEY (chat_id, user_id)
+);
+
+CREATE TABLE system_prompts (
+    chat_id    INTEGER NOT NULL PRIMARY KEY,
+    prompt     TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1 @@
+ALTER TABLE user_overrides ADD COLUMN expires_at DATETIME;
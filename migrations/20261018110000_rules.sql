@@ -0,0 +1,20 @@
+CREATE TABLE rules (
+    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    -- NULL matches every chat, user or command family
+    chat_id     INTEGER,
+    user_id     INTEGER,
+    family      TEXT,
+    probability REAL,
+    -- time of day window in UTC, formatted as HH:MM
+    starts_at   TEXT,
+    ends_at     TEXT,
+    action      TEXT NOT NULL,
+    argument    TEXT NOT NULL,
+    created_at  DATETIME NOT NULL DEFAULT current_timestamp
+);
+
+-- the pranks that used to be hardcoded
+INSERT INTO rules (user_id, family, action, argument) VALUES
+    (172179034, 'image', 'replace', 'man dressed as clown stabbing tire'),
+    (172179034, 'llm', 'replace', 'What are the hazards of driving on a flat tire?'),
+    (172179034, 'tts', 'replace', 'What are the hazards of driving on a flat tire?');
@@ -0,0 +1,8 @@
+CREATE TABLE roles (
+    chat_id    INTEGER NOT NULL,
+    user_id    INTEGER NOT NULL,
+    role       TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT current_timestamp,
+
+    PRIMARY KEY (chat_id, user_id)
+);
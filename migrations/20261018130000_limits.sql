@@ -0,0 +1,21 @@
+CREATE TABLE rate_limit_buckets (
+    -- 'user' or 'chat'
+    scope      TEXT NOT NULL,
+    id         INTEGER NOT NULL,
+    kind       TEXT NOT NULL,
+    tokens     REAL NOT NULL,
+    updated_at DATETIME NOT NULL,
+
+    PRIMARY KEY (scope, id, kind)
+);
+
+CREATE TABLE daily_usage (
+    scope TEXT NOT NULL,
+    id    INTEGER NOT NULL,
+    kind  TEXT NOT NULL,
+    -- UTC date
+    day   DATE NOT NULL,
+    count INTEGER NOT NULL DEFAULT 0,
+
+    PRIMARY KEY (scope, id, kind, day)
+);
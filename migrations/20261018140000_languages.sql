@@ -0,0 +1,5 @@
+CREATE TABLE chat_languages (
+    chat_id    INTEGER NOT NULL PRIMARY KEY,
+    language   TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,4 @@
+CREATE TABLE auto_transcription (
+    chat_id    INTEGER NOT NULL PRIMARY KEY,
+    created_at DATETIME NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,11 @@
+-- messages of /ask conversations, a reply continues the thread of its parent
+CREATE TABLE llm_threads (
+    chat_id    INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    parent_id  INTEGER,
+    role       TEXT NOT NULL,
+    content    TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT current_timestamp,
+
+    PRIMARY KEY (chat_id, message_id)
+);
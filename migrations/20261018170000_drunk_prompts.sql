@@ -0,0 +1,2 @@
+-- drunk chats follow the configured drunk prompt, also after it changes
+ALTER TABLE system_prompts ADD COLUMN drunk BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,141 @@
+//! Sending LLM answers, with their markdown as Telegram formatting
+
+use teloxide::{
+    payloads::{SendDocumentSetters, SendMessageSetters},
+    requests::{Request as RequestExt, Requester},
+    types::{ChatId, InlineKeyboardMarkup, InputFile, Message, MessageId, ParseMode},
+    Bot,
+};
+
+use crate::utils::markdown;
+
+/// UTF-16 code units per message, Telegram refuses more than 4096
+const MAX_LENGTH: usize = 4000;
+
+/// Sends answers, long answers in parts or as a document
+#[derive(Clone)]
+pub struct Answers {
+    bot: Bot,
+    /// Answers in more parts are sent as a markdown document, 0 always sends the parts
+    max_parts: usize,
+}
+
+impl Answers {
+    pub fn new(bot: Bot, max_parts: usize) -> Self {
+        Self { bot, max_parts }
+    }
+
+    /// Reply with the answer, every part of a long answer replies to the previous one
+    ///
+    /// The markup goes under the last message.
+    pub async fn send(
+        &self,
+        chat_id: ChatId,
+        reply_to: MessageId,
+        answer: &str,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<Vec<Message>, teloxide::RequestError> {
+        let mut parts = markdown::split(answer, MAX_LENGTH);
+        if parts.is_empty() {
+            parts.push(answer.to_string());
+        }
+
+        if self.max_parts > 0 && parts.len() > self.max_parts {
+            return self
+                .send_document(chat_id, reply_to, &parts[0], answer, markup)
+                .await;
+        }
+
+        let last = parts.len() - 1;
+        let mut sent: Vec<Message> = Vec::with_capacity(parts.len());
+
+        for (index, part) in parts.iter().enumerate() {
+            let reply_to = sent.last().map_or(reply_to, |previous| previous.id);
+            let markup = markup.clone().filter(|_| index == last);
+
+            sent.push(self.send_part(chat_id, reply_to, part, markup).await?);
+        }
+
+        Ok(sent)
+    }
+
+    /// The first part as a message, the whole answer as a document
+    async fn send_document(
+        &self,
+        chat_id: ChatId,
+        reply_to: MessageId,
+        first: &str,
+        answer: &str,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<Vec<Message>, teloxide::RequestError> {
+        let first = self.send_part(chat_id, reply_to, first, None).await?;
+
+        let mut request = self
+            .bot
+            .send_document(
+                chat_id,
+                InputFile::memory(answer.to_string()).file_name("answer.md"),
+            )
+            .reply_to_message_id(first.id);
+
+        if let Some(markup) = markup {
+            request = request.reply_markup(markup);
+        }
+
+        let document = request.send().await?;
+
+        Ok(vec![first, document])
+    }
+
+    /// A part as HTML, or as it is when Telegram refuses the formatting
+    async fn send_part(
+        &self,
+        chat_id: ChatId,
+        reply_to: MessageId,
+        part: &str,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, teloxide::RequestError> {
+        let html = markdown::to_html(part);
+
+        let formatted = self
+            .send_once(
+                chat_id,
+                reply_to,
+                &html,
+                Some(ParseMode::Html),
+                markup.clone(),
+            )
+            .await;
+
+        match formatted {
+            Ok(message) => Ok(message),
+            Err(err) => {
+                log::warn!("failed to send formatted answer, sending it unformatted: {err}");
+                self.send_once(chat_id, reply_to, part, None, markup).await
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        chat_id: ChatId,
+        reply_to: MessageId,
+        text: &str,
+        parse_mode: Option<ParseMode>,
+        markup: Option<InlineKeyboardMarkup>,
+    ) -> Result<Message, teloxide::RequestError> {
+        let mut request = self
+            .bot
+            .send_message(chat_id, text)
+            .reply_to_message_id(reply_to);
+
+        if let Some(parse_mode) = parse_mode {
+            request = request.parse_mode(parse_mode);
+        }
+        if let Some(markup) = markup {
+            request = request.reply_markup(markup);
+        }
+
+        request.send().await
+    }
+}
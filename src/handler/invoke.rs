@@ -1,4 +1,8 @@
-use std::{collections::HashMap, num::NonZeroUsize};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    time::{Duration, Instant},
+};
 
 use serde::Deserialize;
 use teloxide::{
@@ -7,12 +11,19 @@ use teloxide::{
     types::{ChatId, InputFile, MessageId, UserId},
     Bot,
 };
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
-
-use crate::invoke_ai::{
-    self,
-    client::InvokeAI,
-    models::{BatchId, Enqueue},
+use tokio::sync::mpsc::Receiver;
+
+use crate::{
+    health::{Health, Status},
+    i18n::{Language, Text},
+    invoke_ai::{
+        self,
+        client::InvokeAI,
+        models::{BatchId, Enqueue},
+    },
+    logging::{self, CorrelationId},
+    metrics,
+    scheduler::{Backend, Permit, Priority, Scheduler},
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -27,10 +38,14 @@ pub enum Error {
     NotInQueue,
 }
 
+/// Batches that take longer are failed, InvokeAI may have lost them
+const BATCH_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
 pub struct Config {
     pub invoke_ai_url: String,
-    pub max_in_progress: Option<NonZeroUsize>,
+    /// Maximum amount of queued updates
+    pub capacity: NonZeroUsize,
 }
 
 #[derive(Debug)]
@@ -40,12 +55,17 @@ pub enum Update {
         chat_id: ChatId,
         user_id: UserId,
         message_id: MessageId,
+        correlation_id: CorrelationId,
+        language: Language,
+        priority: Priority,
     },
     Started {
         id: BatchId,
         chat_id: ChatId,
-        user_id: UserId,
         message_id: MessageId,
+        correlation_id: CorrelationId,
+        language: Language,
+        permit: Permit,
     },
     Progress {
         id: BatchId,
@@ -58,6 +78,25 @@ pub enum Update {
         batch_id: BatchId,
         reason: String,
     },
+    /// The socket lost its connection, the updates of running batches are lost with it
+    Disconnected,
+}
+
+impl Update {
+    /// Correlation id of the request, progress updates are looked up in the queue
+    fn correlation_id(&self, queue: &Queue) -> Option<CorrelationId> {
+        match self {
+            Update::Requested { correlation_id, .. } | Update::Started { correlation_id, .. } => {
+                Some(*correlation_id)
+            }
+            Update::Progress { id }
+            | Update::Finished { batch_id: id, .. }
+            | Update::Failed { batch_id: id, .. } => {
+                queue.get(*id).map(|entry| entry.correlation_id)
+            }
+            Update::Disconnected => None,
+        }
+    }
 }
 
 enum Response {
@@ -70,72 +109,39 @@ enum Response {
 }
 
 /// Handle for sending state-change notifications
-#[derive(Clone)]
-pub struct Notifier {
-    inner: UnboundedSender<Update>,
-}
-
-impl From<UnboundedSender<Update>> for Notifier {
-    fn from(inner: UnboundedSender<Update>) -> Self {
-        Self { inner }
-    }
-}
-
-impl Notifier {
-    /// Notify the handler about a state change
-    ///
-    /// Panics when the receiver is down
-    pub fn notify(&self, update: Update) {
-        self.inner
-            .send(update)
-            .expect("Failed to send update, this is bad");
-    }
-}
+pub type Notifier = super::notifier::Notifier<Update>;
 
 pub struct Handler {
     client: InvokeAI,
     bot: Bot,
-    receiver: UnboundedReceiver<Update>,
+    receiver: Receiver<Update>,
     notifier: Notifier,
-    /// Maximum number of queries in progress per user
-    max_in_progress: NonZeroUsize,
+    scheduler: Scheduler,
+    health: Health,
 }
 
 impl Handler {
-    /// Initiate the telegram bot and start listening for updates
-    pub async fn try_new(
+    /// Create the handler, the connection to InvokeAI is established in the background once started
+    pub fn new(
         config: Config,
         bot: Bot,
         http_client: reqwest::Client,
-    ) -> Result<Self, Error> {
+        scheduler: Scheduler,
+    ) -> Self {
         let Config {
             invoke_ai_url,
-            max_in_progress,
+            capacity,
         } = config;
 
-        let (sender, receiver) = mpsc::unbounded_channel::<Update>();
-        let notifier = Notifier::from(sender);
+        let (notifier, receiver) = Notifier::channel("invoke-ai", capacity);
 
-        let client = InvokeAI::connect(invoke_ai_url, notifier.clone(), http_client).await?;
-
-        Ok(Self {
-            client,
-            bot: bot.clone(),
+        Self {
+            client: InvokeAI::new(invoke_ai_url, http_client),
+            bot,
             receiver,
             notifier,
-            max_in_progress: max_in_progress.unwrap_or(NonZeroUsize::new(3).unwrap()),
-        })
-    }
-
-    /// Try to connect indefinitely to invoke-ai, only returns a handler once the connection is established
-    pub async fn new(config: Config, bot: Bot, http_client: reqwest::Client) -> Self {
-        loop {
-            match Self::try_new(config.clone(), bot.clone(), http_client.clone()).await {
-                Ok(handler) => return handler,
-                Err(err) => log::error!("failed to connect to invoke-ai: {err}"),
-            }
-
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            scheduler,
+            health: Health::with_connection(Backend::InvokeAi.as_str()),
         }
     }
 
@@ -143,33 +149,78 @@ impl Handler {
         self.notifier.clone()
     }
 
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+
     /// Start handling new requests and InvokeAI progress updates
     pub async fn start(mut self) {
         log::info!("Starting invoke handler");
+
+        let supervisor = self
+            .client
+            .clone()
+            .supervise(self.notifier(), self.health.clone());
+        tokio::task::spawn(supervisor);
         let mut queue = Queue::default();
+        let mut sweep = tokio::time::interval(Duration::from_secs(60));
 
-        while let Some(update) = self.receiver.recv().await {
-            let res = match self.handle(update, &mut queue).await {
-                Ok(Response::None) => continue,
-                Ok(Response::Message {
-                    chat_id,
-                    message_id,
-                    message,
-                }) => {
-                    self.bot
-                        .send_message(chat_id, message)
-                        .reply_to_message_id(message_id)
-                        .await
-                }
-                Err(error) => {
-                    log::warn!("failed to generate image: {error}");
+        loop {
+            let update = tokio::select! {
+                update = self.receiver.recv() => match update {
+                    Some(update) => update,
+                    None => break,
+                },
+                _ = sweep.tick() => {
+                    self.fail_stale(&mut queue).await;
                     continue;
                 }
             };
 
-            if let Err(error) = res {
-                log::error!("failed to send telegram message: {error}");
+            let correlation_id = update.correlation_id(&queue);
+            logging::scope(correlation_id, self.process(update, &mut queue)).await;
+        }
+    }
+
+    /// Fail the batches that have been running for too long, so their permits are released
+    async fn fail_stale(&self, queue: &mut Queue) {
+        for batch_id in queue.stale(BATCH_TIMEOUT) {
+            let update = Update::Failed {
+                batch_id,
+                reason: String::from("timed out"),
+            };
+
+            let correlation_id = update.correlation_id(queue);
+            logging::scope(correlation_id, self.process(update, queue)).await;
+        }
+    }
+
+    /// Handle a single update and send the reply, if any
+    async fn process(&self, update: Update, queue: &mut Queue) {
+        let res = match self.handle(update, queue).await {
+            Ok(Response::None) => return,
+            Ok(Response::Message {
+                chat_id,
+                message_id,
+                message,
+            }) => {
+                self.bot
+                    .send_message(chat_id, message)
+                    .reply_to_message_id(message_id)
+                    .await
+            }
+            Err(error) => {
+                log::warn!("failed to generate image: {error}");
+                if matches!(error, Error::TelegramApi(_) | Error::TelegramRequest(_)) {
+                    metrics::TELEGRAM_ERRORS.inc(Backend::InvokeAi.as_str());
+                }
+                return;
             }
+        };
+
+        if let Err(error) = res {
+            log::error!("failed to send telegram message: {error}");
+            metrics::TELEGRAM_ERRORS.inc(Backend::InvokeAi.as_str());
         }
     }
 
@@ -180,41 +231,105 @@ impl Handler {
                 chat_id,
                 user_id,
                 message_id,
+                correlation_id,
+                language,
+                priority,
             } => {
                 log::info!(
                     "Received request, Prompt({}), ChatId({chat_id}), UserId({user_id})",
                     enqueue.prompt()
                 );
 
-                let in_progress = queue.increment_user_count(user_id);
-
-                if in_progress > self.max_in_progress.get() {
-                    queue.decrement_user_count(user_id);
+                if self.health.status() == Status::Down {
                     return Ok(Response::Message {
                         chat_id,
                         message_id,
-                        message: "You already have too many images in progress".into(),
+                        message: Text::ImageGeneratorDown.translate(language),
                     });
                 }
 
-                self.client
-                    .enqueue_text_to_image(enqueue, chat_id, user_id, message_id)
-                    .await?;
+                let Ok(ticket) = self.scheduler.submit(Backend::InvokeAi, user_id, priority) else {
+                    return Ok(Response::Message {
+                        chat_id,
+                        message_id,
+                        message: Text::TooManyImages.translate(language),
+                    });
+                };
+
+                let position = ticket.position();
+                let client = self.client.clone();
+                let notifier = self.notifier();
+                let bot = self.bot.clone();
+                let health = self.health.clone();
+
+                logging::spawn(async move {
+                    let permit = ticket.ready().await;
+
+                    match client.enqueue_text_to_image(enqueue).await {
+                        Ok(enqueued) => {
+                            notifier
+                                .notify(Update::Started {
+                                    id: enqueued.id(),
+                                    chat_id,
+                                    message_id,
+                                    correlation_id,
+                                    language,
+                                    permit,
+                                })
+                                .await
+                        }
+                        Err(error) => {
+                            log::warn!("failed to enqueue image: {error}");
+                            metrics::JOBS_FAILED.inc(Backend::InvokeAi.as_str());
+
+                            let message = match health.status() {
+                                Status::Down => Text::ImageGeneratorDown,
+                                _ => Text::ImageFailed {
+                                    reason: String::from("InvokeAI didn't accept the request"),
+                                    error: correlation_id,
+                                },
+                            };
+
+                            let res = bot
+                                .send_message(chat_id, message.translate(language))
+                                .reply_to_message_id(message_id)
+                                .await;
+
+                            if let Err(error) = res {
+                                log::error!("failed to send telegram message: {error}");
+                                metrics::TELEGRAM_ERRORS.inc(Backend::InvokeAi.as_str());
+                            }
+                        }
+                    }
+                });
+
+                if let Some(position) = position {
+                    return Ok(Response::Message {
+                        chat_id,
+                        message_id,
+                        message: Text::Queued(position).translate(language),
+                    });
+                }
             }
 
             Update::Started {
                 id,
                 chat_id,
-                user_id,
                 message_id,
+                correlation_id,
+                language,
+                permit,
             } => {
                 log::info!("started processing {id:?}");
                 queue.insert(
                     id,
                     QueueEntry {
                         chat_id,
-                        user_id,
                         message_id,
+                        correlation_id,
+                        language,
+                        started_at: Instant::now(),
+                        _permit: permit,
                     },
                 );
             }
@@ -231,7 +346,9 @@ impl Handler {
 
                 let entry = queue.remove(batch_id).ok_or(Error::NotInQueue)?;
 
-                queue.decrement_user_count(entry.user_id);
+                let duration = entry.started_at.elapsed().as_secs_f64();
+                metrics::INVOKE_AI_BATCH_DURATION.observe("", duration);
+                metrics::JOBS_COMPLETED.inc(Backend::InvokeAi.as_str());
 
                 let bytes = self.client.download_image(image_url).await?;
 
@@ -245,32 +362,70 @@ impl Handler {
             Update::Failed { batch_id, reason } => {
                 log::error!("Failed to finish {batch_id:?}, error: {reason}");
                 let entry = queue.remove(batch_id).ok_or(Error::NotInQueue)?;
-
-                queue.decrement_user_count(entry.user_id);
+                metrics::JOBS_FAILED.inc(Backend::InvokeAi.as_str());
 
                 return Ok(Response::Message {
                     chat_id: entry.chat_id,
                     message_id: entry.message_id,
-                    message: format!("Failed to generate image: {reason}"),
+                    message: entry.failure(reason),
                 });
             }
+
+            Update::Disconnected => {
+                let pending = queue.drain();
+                if !pending.is_empty() {
+                    log::warn!(
+                        "Lost the connection to InvokeAI, failing {} batches",
+                        pending.len()
+                    );
+                }
+
+                for entry in pending {
+                    metrics::JOBS_FAILED.inc(Backend::InvokeAi.as_str());
+
+                    let message = entry.failure(String::from("lost the connection to InvokeAI"));
+                    self.bot
+                        .send_message(entry.chat_id, message)
+                        .reply_to_message_id(entry.message_id)
+                        .await
+                        .inspect_err(|error| {
+                            log::error!("failed to send telegram message: {error}");
+                            metrics::TELEGRAM_ERRORS.inc(Backend::InvokeAi.as_str());
+                        })
+                        .ok();
+                }
+            }
         }
 
         Ok(Response::None)
     }
 }
 
-#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+#[derive(Debug)]
 struct QueueEntry {
     chat_id: ChatId,
     message_id: MessageId,
-    user_id: UserId,
+    correlation_id: CorrelationId,
+    language: Language,
+    started_at: Instant,
+    /// Keeps the scheduler slot until the image is finished
+    _permit: Permit,
+}
+
+impl QueueEntry {
+    /// Reply for the user when their image failed
+    fn failure(&self, reason: String) -> String {
+        Text::ImageFailed {
+            reason,
+            error: self.correlation_id,
+        }
+        .translate(self.language)
+    }
 }
 
 #[derive(Default)]
 struct Queue {
     queue: HashMap<BatchId, QueueEntry>,
-    users: HashMap<UserId, usize>,
 }
 
 impl Queue {
@@ -278,65 +433,25 @@ impl Queue {
         self.queue.insert(id, entry);
     }
 
-    fn remove(&mut self, id: BatchId) -> Option<QueueEntry> {
-        self.queue.remove(&id)
-    }
-
-    fn increment_user_count(&mut self, user_id: UserId) -> usize {
-        log::debug!("Incrementing user {user_id}");
-
-        *self
-            .users
-            .entry(user_id)
-            .and_modify(|counter| *counter += 1)
-            .or_insert(1)
-    }
-
-    fn decrement_user_count(&mut self, user_id: UserId) -> usize {
-        log::debug!("Decrementing user {user_id}");
-
-        *self
-            .users
-            .entry(user_id)
-            .and_modify(|counter| *counter -= 1)
-            .or_insert(1)
+    fn get(&self, id: BatchId) -> Option<&QueueEntry> {
+        self.queue.get(&id)
     }
-}
-
-#[allow(unused)]
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    #[derive(Default)]
-    struct Bar {
-        users: HashMap<UserId, usize>,
+    fn remove(&mut self, id: BatchId) -> Option<QueueEntry> {
+        self.queue.remove(&id)
     }
 
-    impl Bar {
-        fn increment_user_count(&mut self, user_id: UserId) -> usize {
-            *self
-                .users
-                .entry(user_id)
-                .and_modify(|counter| *counter += 1)
-                .or_insert(1)
-        }
-
-        fn decrement_user_count(&mut self, user_id: UserId) -> usize {
-            *self
-                .users
-                .entry(user_id)
-                .and_modify(|counter| *counter -= 1)
-                .or_insert(1)
-        }
+    /// Remove every batch
+    fn drain(&mut self) -> Vec<QueueEntry> {
+        self.queue.drain().map(|(_, entry)| entry).collect()
     }
 
-    #[test]
-    fn foo() {
-        let mut bar = Bar::default();
-
-        assert_eq!(bar.increment_user_count(UserId(1)), 1);
-        assert_eq!(bar.increment_user_count(UserId(1)), 2);
-        assert_eq!(bar.increment_user_count(UserId(1)), 3);
+    /// Batches that started longer than `timeout` ago
+    fn stale(&self, timeout: Duration) -> Vec<BatchId> {
+        self.queue
+            .iter()
+            .filter(|(_, entry)| entry.started_at.elapsed() > timeout)
+            .map(|(id, _)| *id)
+            .collect()
     }
 }
@@ -0,0 +1,345 @@
+use std::{fmt, num::NonZeroUsize, sync::Arc, time::Duration};
+
+use moka::future::Cache;
+use serde::Deserialize;
+use teloxide::{
+    payloads::SendMessageSetters,
+    requests::Requester,
+    types::{ChatId, InlineKeyboardButton, InlineKeyboardMarkup, Message, MessageId, UserId},
+    Bot,
+};
+use tokio::sync::mpsc::Receiver;
+
+use crate::{
+    i18n::{Language, Text},
+    llm::{Provider, Router},
+    logging::{self, CorrelationId},
+    metrics,
+    ollama::citations,
+    scheduler::{Backend, Priority, Scheduler},
+    utils::searxng::SearchResult,
+};
+
+use super::answer::Answers;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Telegram API Error {0}")]
+    TelegramApi(#[from] teloxide::ApiError),
+    #[error("Telegram Request Error {0}")]
+    TelegramRequest(#[from] teloxide::RequestError),
+    #[error("Received an update for a batch that's not in our queue")]
+    NotInQueue,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    /// Maximum amount of queued updates
+    pub capacity: NonZeroUsize,
+    /// Answers in more messages are sent as a document
+    pub max_parts: usize,
+}
+
+/// Identifier used to identify unique requests
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Identifier {
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub message_id: MessageId,
+    pub correlation_id: CorrelationId,
+    /// Language of the replies
+    pub language: Language,
+}
+
+impl fmt::Debug for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Identifier({}-{}-{}, {})",
+            self.chat_id, self.user_id, self.message_id, self.correlation_id
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum Update {
+    Requested {
+        identifier: Identifier,
+        prompt: String,
+        priority: Priority,
+        /// Search results the prompt cites by number
+        sources: Vec<SearchResult>,
+    },
+    Finished {
+        identifier: Identifier,
+        response: String,
+        reasoning: Option<String>,
+        sources: Vec<SearchResult>,
+    },
+    Failed {
+        identifier: Identifier,
+        reason: String,
+    },
+}
+
+impl Update {
+    fn identifier(&self) -> Identifier {
+        match self {
+            Update::Requested { identifier, .. }
+            | Update::Finished { identifier, .. }
+            | Update::Failed { identifier, .. } => *identifier,
+        }
+    }
+}
+
+enum Response {
+    Message {
+        chat_id: ChatId,
+        message_id: MessageId,
+        message: String,
+    },
+    None,
+}
+
+impl Response {
+    /// Reply to the request, in the language of the request
+    fn text(identifier: Identifier, text: Text) -> Self {
+        Response::Message {
+            chat_id: identifier.chat_id,
+            message_id: identifier.message_id,
+            message: text.translate(identifier.language),
+        }
+    }
+}
+
+/// Handle for sending state-change notifications
+pub type Notifier = super::notifier::Notifier<Update>;
+
+/// Reasoning left out of the answers, for the button that shows it
+#[derive(Clone)]
+pub struct Reasoning {
+    cache: Cache<(ChatId, MessageId), Arc<String>>,
+}
+
+impl Reasoning {
+    /// Callback data of the button
+    pub const BUTTON: &'static str = "reasoning";
+    /// The button stops working after a day
+    const TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    /// Reasoning behind an answer
+    pub async fn get(&self, chat_id: ChatId, message_id: MessageId) -> Option<Arc<String>> {
+        self.cache.get(&(chat_id, message_id)).await
+    }
+
+    async fn insert(&self, answer: &Message, reasoning: String) {
+        self.cache
+            .insert((answer.chat.id, answer.id), Arc::new(reasoning))
+            .await;
+    }
+}
+
+impl Default for Reasoning {
+    fn default() -> Self {
+        Self {
+            cache: Cache::builder()
+                .max_capacity(1_000)
+                .time_to_live(Self::TTL)
+                .build(),
+        }
+    }
+}
+
+/// Answers the prompts of `/hey`, `/tldr`, `/summary` and `/deepsearch` through the LLM providers
+pub struct Handler {
+    router: Router,
+    bot: Bot,
+    answers: Answers,
+    receiver: Receiver<Update>,
+    notifier: Notifier,
+    scheduler: Scheduler,
+    /// Only when the answers offer their reasoning
+    reasoning: Option<Reasoning>,
+}
+
+impl Handler {
+    pub fn try_new(
+        config: Config,
+        bot: Bot,
+        router: Router,
+        scheduler: Scheduler,
+    ) -> Result<Self, Error> {
+        let Config {
+            capacity,
+            max_parts,
+        } = config;
+
+        let (notifier, receiver) = Notifier::channel("llm", capacity);
+        let reasoning = router.shows_reasoning().then(Reasoning::default);
+
+        Ok(Self {
+            router,
+            answers: Answers::new(bot.clone(), max_parts),
+            bot,
+            receiver,
+            notifier,
+            scheduler,
+            reasoning,
+        })
+    }
+
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
+    pub fn reasoning(&self) -> Option<Reasoning> {
+        self.reasoning.clone()
+    }
+
+    /// Start handling new requests and LLM answers
+    pub async fn start(mut self) {
+        log::info!(
+            "Starting LLM handler with {}",
+            self.router.names().join(", ")
+        );
+        while let Some(update) = self.receiver.recv().await {
+            let correlation_id = update.identifier().correlation_id;
+            logging::scope(Some(correlation_id), self.process(update)).await;
+        }
+    }
+
+    /// Handle a single update and send the reply, if any
+    async fn process(&self, update: Update) {
+        let res = match self.handle(update).await {
+            Ok(Response::None) => return,
+            Ok(Response::Message {
+                chat_id,
+                message_id,
+                message,
+            }) => {
+                self.bot
+                    .send_message(chat_id, message)
+                    .reply_to_message_id(message_id)
+                    .await
+            }
+            Err(error) => {
+                log::warn!("failed to generate text: {error}");
+                if matches!(error, Error::TelegramApi(_) | Error::TelegramRequest(_)) {
+                    metrics::TELEGRAM_ERRORS.inc(Backend::Llm.as_str());
+                }
+                return;
+            }
+        };
+
+        if let Err(error) = res {
+            log::error!("failed to send telegram message: {error}");
+            metrics::TELEGRAM_ERRORS.inc(Backend::Llm.as_str());
+        }
+    }
+
+    async fn handle(&self, update: Update) -> Result<Response, Error> {
+        match update {
+            Update::Requested {
+                identifier,
+                prompt,
+                priority,
+                sources,
+            } => {
+                log::info!(
+                    "Received request, Prompt({prompt}), ChatId({}), UserId({})",
+                    identifier.chat_id,
+                    identifier.user_id
+                );
+
+                let Ok(ticket) = self
+                    .scheduler
+                    .submit(Backend::Llm, identifier.user_id, priority)
+                else {
+                    return Ok(Response::text(identifier, Text::TooManyPrompts));
+                };
+
+                let position = ticket.position();
+                let router = self.router.clone();
+                let notifier = self.notifier();
+
+                logging::spawn(async move {
+                    let _permit = ticket.ready().await;
+
+                    match router.generate(&prompt).await {
+                        Ok(completion) => {
+                            notifier
+                                .notify(Update::Finished {
+                                    identifier,
+                                    response: completion.text,
+                                    reasoning: completion.reasoning,
+                                    sources,
+                                })
+                                .await;
+                        }
+                        Err(error) => {
+                            notifier
+                                .notify(Update::Failed {
+                                    identifier,
+                                    reason: error.to_string(),
+                                })
+                                .await
+                        }
+                    };
+                });
+
+                if let Some(position) = position {
+                    return Ok(Response::text(identifier, Text::Queued(position)));
+                }
+            }
+
+            Update::Finished {
+                identifier,
+                response,
+                reasoning,
+                sources,
+            } => {
+                log::info!("processing finished {identifier:?}, reponse: {response}");
+                metrics::JOBS_COMPLETED.inc(Backend::Llm.as_str());
+
+                let response = if sources.is_empty() {
+                    response
+                } else {
+                    let heading = Text::Sources.translate(identifier.language);
+                    citations::link(&response, &sources, &heading)
+                };
+
+                let reasoning = reasoning.filter(|_| self.reasoning.is_some());
+                let markup = reasoning.as_ref().map(|_| {
+                    InlineKeyboardMarkup::new([[InlineKeyboardButton::callback(
+                        Text::ShowReasoning.translate(identifier.language),
+                        Reasoning::BUTTON,
+                    )]])
+                });
+
+                let sent = self
+                    .answers
+                    .send(identifier.chat_id, identifier.message_id, &response, markup)
+                    .await?;
+
+                // the button is under the last message
+                if let (Some(store), Some(reasoning), Some(last)) =
+                    (&self.reasoning, reasoning, sent.last())
+                {
+                    store.insert(last, reasoning).await;
+                }
+            }
+
+            Update::Failed { identifier, reason } => {
+                log::error!("Failed to finish {identifier:?}, error: {reason}");
+                metrics::JOBS_FAILED.inc(Backend::Llm.as_str());
+
+                return Ok(Response::text(
+                    identifier,
+                    Text::TextFailed(identifier.correlation_id),
+                ));
+            }
+        }
+
+        Ok(Response::None)
+    }
+}
@@ -1,16 +1,25 @@
-use std::{collections::HashMap, fmt, num::NonZeroUsize};
+use std::{fmt, num::NonZeroUsize};
 
-use lingua::Language;
 use serde::Deserialize;
 use teloxide::{
-    payloads::{SendAudioSetters, SendMessageSetters},
+    payloads::{SendMessageSetters, SendVoiceSetters},
     requests::{Request as RequestExt, Requester},
     types::{ChatId, InputFile, MessageId, UserId},
     Bot,
 };
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::Receiver;
+
+use crate::{
+    i18n::{self, Text},
+    local_ai::{self, conversation::Failure, LocalAI, Prompts, Recording, Speech, Voice},
+    logging::{self, CorrelationId},
+    metrics,
+    scheduler::{Backend, Priority, Scheduler, Ticket},
+    store::Store,
+    utils::audio::VoiceNote,
+};
 
-use crate::local_ai::{self, LocalAI, Prompts};
+use super::answer::Answers;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -25,7 +34,16 @@ pub enum Error {
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 pub struct Config {
     pub local_ai_url: String,
-    pub max_in_progress: Option<NonZeroUsize>,
+    /// Maximum amount of queued updates
+    pub capacity: NonZeroUsize,
+    /// Whisper model for speech to text
+    pub whisper_model: String,
+    /// Model for embeddings through the LLM provider
+    pub embedding_model: String,
+    /// Reasoning and template tokens to remove from the answers
+    pub output: crate::llm::output::Config,
+    /// Answers in more messages are sent as a document
+    pub max_parts: usize,
 }
 
 /// Identifier used to identify unique requests
@@ -34,14 +52,17 @@ pub struct Identifier {
     pub chat_id: ChatId,
     pub user_id: UserId,
     pub message_id: MessageId,
+    pub correlation_id: CorrelationId,
+    /// Language of the replies
+    pub language: i18n::Language,
 }
 
 impl fmt::Debug for Identifier {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Identifier({}-{}-{})",
-            self.chat_id, self.user_id, self.message_id
+            "Identifier({}-{}-{}, {})",
+            self.chat_id, self.user_id, self.message_id, self.correlation_id
         )
     }
 }
@@ -51,7 +72,26 @@ pub enum ResponseVariant {
     #[default]
     None,
     Text(String),
-    Audio(bytes::Bytes),
+    Voice(VoiceNote),
+    /// Transcript of the recording in a message
+    Transcript {
+        message_id: MessageId,
+        user_id: UserId,
+        text: String,
+    },
+    /// Answer in a thread, the prompt replies to `parent`
+    Answer {
+        prompt: String,
+        parent: Option<MessageId>,
+        text: String,
+    },
+    /// Spoken answer to a voice message
+    Conversation {
+        message_id: MessageId,
+        user_id: UserId,
+        transcript: String,
+        note: VoiceNote,
+    },
 }
 
 #[derive(Debug)]
@@ -60,15 +100,35 @@ pub enum Update {
         identifier: Identifier,
         prompt: String,
         model: crate::local_ai::Model,
+        priority: Priority,
     },
     TtsRequest {
         identifier: Identifier,
         prompt: String,
-        language: Language,
+        voice: Voice,
+        priority: Priority,
     },
     RawRequest {
         identifier: Identifier,
         request: local_ai::Request,
+        priority: Priority,
+    },
+    Ask {
+        identifier: Identifier,
+        request: local_ai::Request,
+        /// The message of the thread the prompt replies to
+        parent: Option<MessageId>,
+        priority: Priority,
+    },
+    TranscriptionRequest {
+        identifier: Identifier,
+        recording: Recording,
+        priority: Priority,
+    },
+    ConversationRequest {
+        identifier: Identifier,
+        recording: Recording,
+        priority: Priority,
     },
     Finished {
         identifier: Identifier,
@@ -78,6 +138,26 @@ pub enum Update {
         identifier: Identifier,
         reason: String,
     },
+    ConversationFailed {
+        identifier: Identifier,
+        failure: Failure,
+    },
+}
+
+impl Update {
+    fn identifier(&self) -> Identifier {
+        match self {
+            Update::Requested { identifier, .. }
+            | Update::TtsRequest { identifier, .. }
+            | Update::RawRequest { identifier, .. }
+            | Update::Ask { identifier, .. }
+            | Update::TranscriptionRequest { identifier, .. }
+            | Update::ConversationRequest { identifier, .. }
+            | Update::Finished { identifier, .. }
+            | Update::Failed { identifier, .. }
+            | Update::ConversationFailed { identifier, .. } => *identifier,
+        }
+    }
 }
 
 enum Response {
@@ -89,36 +169,28 @@ enum Response {
     None,
 }
 
-/// Handle for sending state-change notifications
-#[derive(Clone)]
-pub struct Notifier {
-    inner: UnboundedSender<Update>,
-}
-
-impl From<UnboundedSender<Update>> for Notifier {
-    fn from(inner: UnboundedSender<Update>) -> Self {
-        Self { inner }
+impl Response {
+    /// Reply to the request, in the language of the request
+    fn text(identifier: Identifier, text: Text) -> Self {
+        Response::Message {
+            chat_id: identifier.chat_id,
+            message_id: identifier.message_id,
+            message: text.translate(identifier.language),
+        }
     }
 }
 
-impl Notifier {
-    /// Notify the handler about a state change
-    ///
-    /// Panics when the receiver is down
-    pub fn notify(&self, update: Update) {
-        self.inner
-            .send(update)
-            .expect("Failed to send update, this is bad");
-    }
-}
+/// Handle for sending state-change notifications
+pub type Notifier = super::notifier::Notifier<Update>;
 
 pub struct Handler {
     client: LocalAI,
     bot: Bot,
-    receiver: UnboundedReceiver<Update>,
+    answers: Answers,
+    receiver: Receiver<Update>,
     notifier: Notifier,
-    /// Maximum number of queries in progress per user
-    max_in_progress: NonZeroUsize,
+    scheduler: Scheduler,
+    store: Store,
 }
 
 impl Handler {
@@ -128,23 +200,40 @@ impl Handler {
         bot: Bot,
         http_client: reqwest::Client,
         prompts: Prompts,
+        scheduler: Scheduler,
+        store: Store,
+        speech: Speech,
     ) -> Result<Self, Error> {
         let Config {
             local_ai_url,
-            max_in_progress,
+            capacity,
+            whisper_model,
+            embedding_model,
+            output,
+            max_parts,
         } = config;
 
-        let (sender, receiver) = mpsc::unbounded_channel::<Update>();
-        let notifier = Notifier::from(sender);
+        let (notifier, receiver) = Notifier::channel("local-ai", capacity);
 
-        let client = LocalAI::new(local_ai_url, notifier.clone(), http_client, prompts);
+        let client = LocalAI::new(
+            local_ai_url,
+            notifier.clone(),
+            http_client,
+            prompts,
+            whisper_model,
+            embedding_model,
+            speech,
+        )
+        .with_output(output);
 
         Ok(Self {
             client,
+            answers: Answers::new(bot.clone(), max_parts),
             bot: bot.clone(),
             receiver,
             notifier,
-            max_in_progress: max_in_progress.unwrap_or(NonZeroUsize::new(3).unwrap()),
+            scheduler,
+            store,
         })
     }
 
@@ -152,42 +241,119 @@ impl Handler {
         self.notifier.clone()
     }
 
+    /// The LocalAI client, to use it as an LLM provider
+    pub fn client(&self) -> LocalAI {
+        self.client.clone()
+    }
+
     /// Start handling new requests and LocalAI progress updates
     pub async fn start(mut self) {
         log::info!("Starting local-ai handler");
-        let mut queue = Queue::default();
-
         while let Some(update) = self.receiver.recv().await {
-            let res = match self.handle(update, &mut queue).await {
-                Ok(Response::None) => continue,
-                Ok(Response::Message {
-                    chat_id,
-                    message_id,
-                    message,
-                }) => {
-                    self.bot
-                        .send_message(chat_id, message)
-                        .reply_to_message_id(message_id)
-                        .await
-                }
-                Err(error) => {
-                    log::warn!("failed to generate text: {error}");
-                    continue;
-                }
-            };
+            let correlation_id = update.identifier().correlation_id;
+            logging::scope(Some(correlation_id), self.process(update)).await;
+        }
+    }
 
-            if let Err(error) = res {
-                log::error!("failed to send telegram message: {error}");
+    /// Handle a single update and send the reply, if any
+    async fn process(&self, update: Update) {
+        let res = match self.handle(update).await {
+            Ok(Response::None) => return,
+            Ok(Response::Message {
+                chat_id,
+                message_id,
+                message,
+            }) => {
+                self.bot
+                    .send_message(chat_id, message)
+                    .reply_to_message_id(message_id)
+                    .await
+            }
+            Err(error) => {
+                log::warn!("failed to generate text: {error}");
+                if matches!(error, Error::TelegramApi(_) | Error::TelegramRequest(_)) {
+                    metrics::TELEGRAM_ERRORS.inc(Backend::LocalAi.as_str());
+                }
+                return;
             }
+        };
+
+        if let Err(error) = res {
+            log::error!("failed to send telegram message: {error}");
+            metrics::TELEGRAM_ERRORS.inc(Backend::LocalAi.as_str());
+        }
+    }
+
+    /// Submit a request to the scheduler, replies when the user has too many in progress
+    fn submit(&self, identifier: Identifier, priority: Priority) -> Result<Ticket, Response> {
+        self.scheduler
+            .submit(Backend::LocalAi, identifier.user_id, priority)
+            .map_err(|_| Response::text(identifier, Text::TooManyPrompts))
+    }
+
+    /// Tell the user their position when the request has to wait
+    fn feedback(identifier: Identifier, ticket: &Ticket) -> Response {
+        match ticket.position() {
+            Some(position) => Response::text(identifier, Text::Queued(position)),
+            None => Response::None,
+        }
+    }
+
+    /// Store the transcript of a recording as the text of its message
+    async fn store_transcript(
+        &self,
+        identifier: Identifier,
+        user_id: UserId,
+        message_id: MessageId,
+        transcript: &str,
+    ) {
+        self.store
+            .store_transcript(identifier.chat_id, user_id, message_id, transcript)
+            .await
+            .inspect_err(|err| log::error!("failed to store transcript: {err}"))
+            .ok();
+    }
+
+    /// Remember the prompt and its answer, replies to any part of the answer continue the thread
+    async fn add_to_thread(
+        &self,
+        identifier: Identifier,
+        parent: Option<MessageId>,
+        prompt: String,
+        parts: &[MessageId],
+        answer: String,
+    ) {
+        let prompt = local_ai::Message {
+            role: local_ai::Role::User,
+            content: prompt,
+        };
+        let answer = local_ai::Message {
+            role: local_ai::Role::Assistant,
+            content: answer,
+        };
+
+        let messages = std::iter::once((identifier.message_id, parent, &prompt)).chain(
+            parts
+                .iter()
+                .map(|part| (*part, Some(identifier.message_id), &answer)),
+        );
+
+        for (message_id, parent, message) in messages {
+            self.store
+                .add_to_thread(identifier.chat_id, message_id, parent, message)
+                .await
+                .inspect_err(|err| log::error!("failed to store thread: {err}"))
+                .ok();
         }
     }
 
-    async fn handle(&self, update: Update, queue: &mut Queue) -> Result<Response, Error> {
+    async fn handle(&self, update: Update) -> Result<Response, Error> {
         match update {
             Update::Requested {
                 identifier,
                 prompt,
                 model,
+                priority,
             } => {
                 log::info!(
                     "Received request, Prompt({prompt}), ChatId({}), UserId({})",
@@ -195,63 +361,130 @@ impl Handler {
                     identifier.user_id
                 );
 
-                let in_progress = queue.increment_user_count(identifier.user_id);
+                let ticket = match self.submit(identifier, priority) {
+                    Ok(ticket) => ticket,
+                    Err(response) => return Ok(response),
+                };
+                let feedback = Self::feedback(identifier, &ticket);
 
-                if in_progress > self.max_in_progress.get() {
-                    queue.decrement_user_count(identifier.user_id);
-                    return Ok(Response::Message {
-                        chat_id: identifier.chat_id,
-                        message_id: identifier.message_id,
-                        message: "You already have too many prompts in progress".into(),
-                    });
-                }
+                self.client
+                    .enqueue_request(identifier, prompt, model, ticket)
+                    .await;
 
-                self.client.enqueue_request(identifier, prompt, model).await;
+                return Ok(feedback);
             }
 
             Update::TtsRequest {
                 identifier,
                 prompt,
-                language,
+                voice,
+                priority,
             } => {
                 log::info!(
-                    "Received TTS request, Prompt({prompt}), ChatId({}), UserId({})",
+                    "Received TTS request, Prompt({prompt}), Voice({}), ChatId({}), UserId({})",
+                    voice.name,
                     identifier.chat_id,
                     identifier.user_id
                 );
 
-                let in_progress = queue.increment_user_count(identifier.user_id);
-
-                if in_progress > self.max_in_progress.get() {
-                    queue.decrement_user_count(identifier.user_id);
-                    return Ok(Response::Message {
-                        chat_id: identifier.chat_id,
-                        message_id: identifier.message_id,
-                        message: "You already have too many prompts in progress".into(),
-                    });
-                }
+                let ticket = match self.submit(identifier, priority) {
+                    Ok(ticket) => ticket,
+                    Err(response) => return Ok(response),
+                };
+                let feedback = Self::feedback(identifier, &ticket);
 
                 self.client
-                    .enqueue_tts_request(identifier, prompt, language)
+                    .enqueue_tts_request(identifier, prompt, voice, ticket)
                     .await;
+
+                return Ok(feedback);
             }
 
             Update::RawRequest {
                 identifier,
                 request,
+                priority,
             } => {
-                let in_progress = queue.increment_user_count(identifier.user_id);
-
-                if in_progress > self.max_in_progress.get() {
-                    queue.decrement_user_count(identifier.user_id);
-                    return Ok(Response::Message {
-                        chat_id: identifier.chat_id,
-                        message_id: identifier.message_id,
-                        message: "You already have too many prompts in progress".into(),
-                    });
-                }
+                let ticket = match self.submit(identifier, priority) {
+                    Ok(ticket) => ticket,
+                    Err(response) => return Ok(response),
+                };
+                let feedback = Self::feedback(identifier, &ticket);
+
+                self.client.enqueue_raw_request(identifier, request, ticket);
+
+                return Ok(feedback);
+            }
+
+            Update::Ask {
+                identifier,
+                request,
+                parent,
+                priority,
+            } => {
+                log::info!(
+                    "Received ask request, Model({}), Temperature({}), ChatId({}), UserId({})",
+                    request.model,
+                    request.temperature,
+                    identifier.chat_id,
+                    identifier.user_id
+                );
+
+                let ticket = match self.submit(identifier, priority) {
+                    Ok(ticket) => ticket,
+                    Err(response) => return Ok(response),
+                };
+                let feedback = Self::feedback(identifier, &ticket);
+
+                self.client.enqueue_ask(identifier, request, parent, ticket);
+
+                return Ok(feedback);
+            }
+
+            Update::TranscriptionRequest {
+                identifier,
+                recording,
+                priority,
+            } => {
+                log::info!(
+                    "Received transcription request, {recording:?}, ChatId({}), UserId({})",
+                    identifier.chat_id,
+                    identifier.user_id
+                );
+
+                let ticket = match self.submit(identifier, priority) {
+                    Ok(ticket) => ticket,
+                    Err(response) => return Ok(response),
+                };
+                let feedback = Self::feedback(identifier, &ticket);
+
+                self.client
+                    .enqueue_transcription(identifier, recording, ticket);
+
+                return Ok(feedback);
+            }
+
+            Update::ConversationRequest {
+                identifier,
+                recording,
+                priority,
+            } => {
+                log::info!(
+                    "Received conversation, {recording:?}, ChatId({}), UserId({})",
+                    identifier.chat_id,
+                    identifier.user_id
+                );
+
+                let ticket = match self.submit(identifier, priority) {
+                    Ok(ticket) => ticket,
+                    Err(response) => return Ok(response),
+                };
+                let feedback = Self::feedback(identifier, &ticket);
 
-                self.client.enqueue_raw_request(identifier, request);
+                self.client
+                    .enqueue_conversation(identifier, recording, ticket);
+
+                return Ok(feedback);
             }
 
             Update::Finished {
@@ -259,30 +492,86 @@ impl Handler {
                 response,
             } => {
                 log::info!("processing finished {identifier:?}, reponse: {response:?}");
-
-                queue.decrement_user_count(identifier.user_id);
+                metrics::JOBS_COMPLETED.inc(Backend::LocalAi.as_str());
 
                 match response {
                     ResponseVariant::None => {
                         self.bot
                             .send_message(
                                 identifier.chat_id,
-                                String::from("Failed to generate a response"),
+                                Text::NoResponse.translate(identifier.language),
                             )
                             .reply_to_message_id(identifier.message_id)
                             .send()
                             .await?
                     }
                     ResponseVariant::Text(text) => {
+                        self.answers
+                            .send(identifier.chat_id, identifier.message_id, &text, None)
+                            .await?;
+
+                        return Ok(Response::None);
+                    }
+                    ResponseVariant::Voice(note) => {
                         self.bot
-                            .send_message(identifier.chat_id, text)
+                            .send_voice(
+                                identifier.chat_id,
+                                InputFile::memory(note.ogg).file_name("voice.ogg"),
+                            )
+                            .duration(note.seconds())
                             .reply_to_message_id(identifier.message_id)
                             .send()
                             .await?
                     }
-                    ResponseVariant::Audio(audio) => {
+                    ResponseVariant::Transcript { text, .. } if text.is_empty() => {
+                        return Ok(Response::text(identifier, Text::NoSpeech));
+                    }
+                    ResponseVariant::Transcript {
+                        message_id,
+                        user_id,
+                        text,
+                    } => {
+                        self.store_transcript(identifier, user_id, message_id, &text)
+                            .await;
+
+                        // long recordings make transcripts longer than a message
+                        self.answers
+                            .send(identifier.chat_id, identifier.message_id, &text, None)
+                            .await?;
+
+                        return Ok(Response::None);
+                    }
+                    ResponseVariant::Answer {
+                        prompt,
+                        parent,
+                        text,
+                    } => {
+                        let sent = self
+                            .answers
+                            .send(identifier.chat_id, identifier.message_id, &text, None)
+                            .await?;
+                        let parts: Vec<MessageId> = sent.iter().map(|message| message.id).collect();
+
+                        self.add_to_thread(identifier, parent, prompt, &parts, text)
+                            .await;
+
+                        return Ok(Response::None);
+                    }
+                    ResponseVariant::Conversation {
+                        message_id,
+                        user_id,
+                        transcript,
+                        note,
+                    } => {
+                        self.store_transcript(identifier, user_id, message_id, &transcript)
+                            .await;
+
                         self.bot
-                            .send_audio(identifier.chat_id, InputFile::memory(audio))
+                            .send_voice(
+                                identifier.chat_id,
+                                InputFile::memory(note.ogg).file_name("voice.ogg"),
+                            )
+                            .duration(note.seconds())
                             .reply_to_message_id(identifier.message_id)
                             .send()
                             .await?
@@ -292,13 +581,47 @@ impl Handler {
 
             Update::Failed { identifier, reason } => {
                 log::error!("Failed to finish {identifier:?}, error: {reason}");
+                metrics::JOBS_FAILED.inc(Backend::LocalAi.as_str());
+
+                return Ok(Response::text(
+                    identifier,
+                    Text::TextFailed(identifier.correlation_id),
+                ));
+            }
+
+            Update::ConversationFailed {
+                identifier,
+                failure,
+            } => {
+                log::error!(
+                    "Conversation failed at the {} stage {identifier:?}, error: {}",
+                    failure.stage,
+                    failure.reason
+                );
+                metrics::JOBS_FAILED.inc(Backend::LocalAi.as_str());
+
+                let mut message = Text::ConversationFailed {
+                    stage: failure.stage,
+                    error: identifier.correlation_id,
+                }
+                .translate(identifier.language);
+
+                // the answer is still worth reading when only reading it out loud failed
+                if let Some(answer) = failure.answer {
+                    message.push_str("\n\n");
+                    message.push_str(&answer);
 
-                queue.decrement_user_count(identifier.user_id);
+                    self.answers
+                        .send(identifier.chat_id, identifier.message_id, &message, None)
+                        .await?;
+
+                    return Ok(Response::None);
+                }
 
                 return Ok(Response::Message {
                     chat_id: identifier.chat_id,
                     message_id: identifier.message_id,
-                    message: format!("Failed to generate text prompt, send this code to the developer: {identifier:?}"),
+                    message,
                 });
             }
         }
@@ -306,30 +629,3 @@ impl Handler {
         Ok(Response::None)
     }
 }
-
-#[derive(Default)]
-struct Queue {
-    users: HashMap<UserId, usize>,
-}
-
-impl Queue {
-    fn increment_user_count(&mut self, user_id: UserId) -> usize {
-        log::debug!("Incrementing user {user_id}");
-
-        *self
-            .users
-            .entry(user_id)
-            .and_modify(|counter| *counter += 1)
-            .or_insert(1)
-    }
-
-    fn decrement_user_count(&mut self, user_id: UserId) -> usize {
-        log::debug!("Decrementing user {user_id}");
-
-        *self
-            .users
-            .entry(user_id)
-            .and_modify(|counter| *counter -= 1)
-            .or_insert(1)
-    }
-}
@@ -1,13 +1,26 @@
+use std::{num::NonZeroUsize, sync::Arc};
+
 use teloxide::Bot;
 
+pub mod answer;
 pub mod invoke;
+pub mod llm;
 pub mod local;
-pub mod ollama;
+pub mod notifier;
 pub mod store;
 
 // pub use store::Store;
 
-use crate::local_ai::Prompts;
+use crate::health::{Backend, Health, Kind, Monitor};
+use crate::i18n::Locales;
+use crate::invoke_ai::presets::Presets;
+use crate::limits::Limiter;
+use crate::llm::{OpenAi, Provider, Router};
+use crate::local_ai::{AutoTranscription, Prompts, Speech, Voices};
+use crate::metrics::{Exporter, QueueDepth, Queues};
+use crate::roles::{self, Permissions, Roles};
+use crate::scheduler::{self, Scheduler};
+use crate::settings::Reloader;
 use crate::utils::languages::LanguageDetector;
 use crate::utils::SearXng;
 use crate::AppConfig;
@@ -28,79 +41,303 @@ impl Handler {
             ollama_model,
             searxng_url,
             fact_check_path,
+            auto_grant_chat_admins,
+            min_role_image,
+            min_role_llm,
+            min_role_tts,
+            min_role_admin,
+            limits,
+            prompts,
+            presets,
+            tts,
+            ask,
+            llm,
+            whisper_model,
+            scheduler,
+            channel_capacity,
+            metrics_address,
+            log_format: _,
+            language,
         } = config;
 
         let bot = Bot::new(teloxide_token);
 
         let http_client = http_client();
 
-        let invoke = invoke::Handler::new(
-            invoke::Config {
-                invoke_ai_url,
-                max_in_progress,
-            },
-            bot.clone(),
-            http_client.clone(),
-        )
-        .await;
+        let capacity = channel_capacity.unwrap_or(NonZeroUsize::new(64).unwrap());
 
-        let prompts = Prompts::default();
+        let scheduler = Scheduler::new(
+            scheduler,
+            max_in_progress.unwrap_or(NonZeroUsize::new(3).unwrap()),
+        );
 
-        let local = local::Handler::try_new(
-            local::Config {
-                local_ai_url,
-                max_in_progress,
-            },
-            bot.clone(),
-            http_client.clone(),
+        let invoke = invoke_ai_url.clone().map(|invoke_ai_url| {
+            invoke::Handler::new(
+                invoke::Config {
+                    invoke_ai_url,
+                    capacity,
+                },
+                bot.clone(),
+                http_client.clone(),
+                scheduler.clone(),
+            )
+        });
+
+        let store =
+            crate::store::Store::new(&sqlite_path, bot.clone(), ollama_model.clone()).await?;
+
+        let prompts = Prompts::load(store.clone(), prompts).await?;
+        let overrides = crate::telegram::admin::Overrides::load(store.clone()).await?;
+        let rules = crate::rules::Rules::load(store.clone()).await?;
+        let locales = Locales::load(store.clone(), language).await?;
+        let transcription = AutoTranscription::load(store.clone(), local_ai_url.is_some()).await?;
+
+        let limits = Limiter::new(limits, store.clone());
+        let presets = Presets::new(presets);
+        let voices = Voices::new(tts);
+        let language_detector = LanguageDetector::new(enable_french_detection);
+        let reloader = Reloader::new(
+            limits.clone(),
             prompts.clone(),
-        )?;
+            presets.clone(),
+            voices.clone(),
+        );
 
-        let ollama = ollama::Handler::try_new(
-            ollama::Config {
-                api_uri: ollama_url,
-                max_in_progress,
-                model: ollama_model.clone(),
-            },
+        let default_permissions = Permissions::default();
+        let roles = Roles::load(
+            store.clone(),
             bot.clone(),
+            roles::Config {
+                owner: telegram_admin_user_id,
+                auto_grant_chat_admins,
+                permissions: Permissions {
+                    image: min_role_image.unwrap_or(default_permissions.image),
+                    llm: min_role_llm.unwrap_or(default_permissions.llm),
+                    tts: min_role_tts.unwrap_or(default_permissions.tts),
+                    admin: min_role_admin.unwrap_or(default_permissions.admin),
+                },
+            },
+        )
+        .await?;
+
+        let local = match local_ai_url.clone() {
+            Some(local_ai_url) => Some(local::Handler::try_new(
+                local::Config {
+                    local_ai_url,
+                    capacity,
+                    whisper_model: whisper_model.unwrap_or_else(|| String::from("whisper-1")),
+                    embedding_model: llm.embedding_model.clone(),
+                    output: llm.output.clone(),
+                    max_parts: llm.max_parts,
+                },
+                bot.clone(),
+                http_client.clone(),
+                prompts.clone(),
+                scheduler.clone(),
+                store.clone(),
+                Speech {
+                    voices: voices.clone(),
+                    language: language_detector.clone(),
+                },
+            )?),
+            None => None,
+        };
+
+        let router = router(
+            &llm,
+            ollama_url.clone().map(|ollama_url| {
+                crate::ollama::Ollama::new(
+                    http_client.clone(),
+                    ollama_url,
+                    ollama_model,
+                    llm.embedding_model.clone(),
+                )
+            }),
+            local.as_ref().map(local::Handler::client),
+            &http_client,
+        );
+
+        let llm_handler = if router.is_empty() {
+            None
+        } else {
+            Some(llm::Handler::try_new(
+                llm::Config {
+                    capacity,
+                    max_parts: llm.max_parts,
+                },
+                bot.clone(),
+                router,
+                scheduler.clone(),
+            )?)
+        };
+
+        let mut backends = Vec::new();
+        let mut queues = Vec::new();
+
+        if let (Some(invoke), Some(url)) = (&invoke, &invoke_ai_url) {
+            backends.push(Backend::new(
+                invoke.health(),
+                format!("{url}/api/v1/app/version"),
+                Kind::Plain,
+            ));
+            queues.push(queue_depth(scheduler::Backend::InvokeAi, invoke.notifier()));
+        }
+        if let (Some(local), Some(url)) = (&local, &local_ai_url) {
+            backends.push(Backend::new(
+                Health::new(scheduler::Backend::LocalAi.as_str()),
+                format!("{url}/readyz"),
+                Kind::Plain,
+            ));
+            queues.push(queue_depth(scheduler::Backend::LocalAi, local.notifier()));
+        }
+        if let Some(url) = &ollama_url {
+            backends.push(Backend::new(
+                Health::new("ollama"),
+                format!("{url}/api/ps"),
+                Kind::OllamaModels,
+            ));
+        }
+        if let Some(llm_handler) = &llm_handler {
+            queues.push(queue_depth(scheduler::Backend::Llm, llm_handler.notifier()));
+        }
+        if let Some(searxng_url) = &searxng_url {
+            backends.push(Backend::new(
+                Health::new("searxng"),
+                format!("{searxng_url}/healthz"),
+                Kind::Plain,
+            ));
+        }
+
+        let monitor = Monitor::new(
+            backends,
             http_client.clone(),
-        )?;
+            bot.clone(),
+            telegram_admin_user_id,
+            locales.clone(),
+        );
 
-        let store = crate::store::Store::new(&sqlite_path, bot.clone(), ollama_model).await?;
+        let queues: Queues = Arc::from(queues);
 
-        let searxng = SearXng::new(http_client.clone(), searxng_url);
+        let exporter = Exporter::new(scheduler.clone(), queues.clone(), store.clone());
+        let metrics = async move {
+            if let Some(address) = metrics_address {
+                exporter.serve(address).await;
+            }
+        };
 
-        let fact_check_engine = crate::telegram::fact_check::Engine::new(fact_check_path).await?;
+        let searxng = searxng_url.map(|searxng_url| SearXng::new(http_client.clone(), searxng_url));
 
-        let mut telegram = crate::telegram::handler(crate::telegram::Context {
-            cfg: crate::telegram::Config {
-                admin_id: telegram_admin_user_id,
+        let fact_check_engine = match fact_check_path {
+            Some(path) => match crate::telegram::fact_check::Engine::new(path).await {
+                Ok(engine) => Some(engine),
+                Err(err) => {
+                    log::error!("fact checks are disabled, failed to load the overlays: {err}");
+                    None
+                }
             },
-            bot,
-            store,
-            invoke_notifier: invoke.notifier(),
-            local_notifier: local.notifier(),
-            ollama_notifier: ollama.notifier(),
-            language: LanguageDetector::new(enable_french_detection),
-            prompts,
-            http_client,
-            searxng,
-            fact_check_engine,
-        });
+            None => None,
+        };
+
+        let expiry = crate::telegram::admin::expire_overrides(
+            overrides.clone(),
+            bot.clone(),
+            locales.clone(),
+        );
+
+        let mut telegram = crate::telegram::handler(
+            crate::telegram::Context {
+                bot,
+                store,
+                language: language_detector,
+                prompts,
+                http_client,
+                searxng,
+                rules,
+                roles,
+                limits,
+                scheduler,
+                monitor: monitor.clone(),
+                queues,
+                presets,
+                voices,
+                reloader: reloader.clone(),
+                locales,
+                transcription,
+                ask: Arc::new(ask),
+            },
+            overrides,
+            crate::telegram::Backends {
+                invoke: invoke.as_ref().map(invoke::Handler::notifier),
+                local: local.as_ref().map(local::Handler::notifier),
+                llm: llm_handler.as_ref().map(llm::Handler::notifier),
+                reasoning: llm_handler.as_ref().and_then(llm::Handler::reasoning),
+                fact_check: fact_check_engine,
+            },
+        );
 
         log::info!("Starting all handlers...");
-        futures::future::join4(
-            invoke.start(),
-            local.start(),
-            ollama.start(),
+        tokio::join!(
+            start(invoke.map(invoke::Handler::start)),
+            start(local.map(local::Handler::start)),
+            start(llm_handler.map(llm::Handler::start)),
             telegram.dispatch(),
-        )
-        .await;
+            expiry,
+            monitor.start(),
+            metrics,
+            reloader.on_hangup(),
+        );
 
         Ok(())
     }
 }
 
+/// Run a handler, if its backend is configured
+async fn start(handler: Option<impl std::future::Future<Output = ()>>) {
+    if let Some(handler) = handler {
+        handler.await;
+    }
+}
+
+/// The LLM providers that are configured, in the order of `llm.providers`
+fn router(
+    config: &crate::llm::Config,
+    ollama: Option<crate::ollama::Ollama>,
+    local_ai: Option<crate::local_ai::LocalAI>,
+    http_client: &reqwest::Client,
+) -> Router {
+    let openai = config.openai_url.clone().map(|openai_url| {
+        OpenAi::new(
+            "open_ai",
+            http_client.clone(),
+            openai_url,
+            config.openai_api_key.clone(),
+            config.openai_model.clone(),
+            config.embedding_model.clone(),
+        )
+    });
+
+    let providers: Vec<Arc<dyn Provider>> = config
+        .providers
+        .iter()
+        .filter_map(|kind| -> Option<Arc<dyn Provider>> {
+            match kind {
+                crate::llm::Kind::Ollama => Some(Arc::new(ollama.clone()?)),
+                crate::llm::Kind::LocalAi => Some(Arc::new(local_ai.clone()?)),
+                crate::llm::Kind::OpenAi => Some(Arc::new(openai.clone()?)),
+            }
+        })
+        .collect();
+
+    Router::new(providers, config.output.clone())
+}
+
+fn queue_depth<U: Send + 'static>(
+    backend: scheduler::Backend,
+    notifier: notifier::Notifier<U>,
+) -> (scheduler::Backend, QueueDepth) {
+    (backend, Box::new(move || notifier.depth()))
+}
+
 fn http_client() -> reqwest::Client {
     use reqwest::header::{
         self, HeaderValue, ACCEPT, ACCEPT_ENCODING, UPGRADE_INSECURE_REQUESTS, USER_AGENT,
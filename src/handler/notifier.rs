@@ -0,0 +1,82 @@
+use std::num::NonZeroUsize;
+
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("The {0} handler queue is full")]
+    Busy(&'static str),
+    #[error("The {0} handler is not running")]
+    Closed(&'static str),
+}
+
+/// Handle for sending state-change notifications to a handler over a bounded channel
+#[derive(Debug)]
+pub struct Notifier<U> {
+    inner: Sender<U>,
+    name: &'static str,
+}
+
+impl<U> Clone for Notifier<U> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            name: self.name,
+        }
+    }
+}
+
+impl<U> Notifier<U> {
+    pub fn channel(name: &'static str, capacity: NonZeroUsize) -> (Self, Receiver<U>) {
+        let (inner, receiver) = mpsc::channel(capacity.get());
+
+        (Self { inner, name }, receiver)
+    }
+
+    /// Hand a new request to the handler, fails right away when the handler can't keep up
+    pub fn try_notify(&self, update: U) -> Result<(), Error> {
+        self.inner.try_send(update).map_err(|err| match err {
+            TrySendError::Full(_) => {
+                log::warn!("{} queue is full ({} updates)", self.name, self.depth());
+                Error::Busy(self.name)
+            }
+            TrySendError::Closed(_) => Error::Closed(self.name),
+        })
+    }
+
+    /// Notify the handler about a state change of a request it's already handling
+    ///
+    /// Waits for room in the queue, those updates should never be dropped.
+    pub async fn notify(&self, update: U) {
+        if self.inner.send(update).await.is_err() {
+            log::error!("{}", Error::Closed(self.name));
+        }
+    }
+
+    /// Amount of updates waiting to be handled
+    pub fn depth(&self) -> usize {
+        self.inner.max_capacity() - self.inner.capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backpressure() {
+        let (notifier, mut receiver) = Notifier::channel("test", NonZeroUsize::new(2).unwrap());
+
+        assert_eq!(notifier.try_notify(1), Ok(()));
+        assert_eq!(notifier.try_notify(2), Ok(()));
+        assert_eq!(notifier.depth(), 2);
+        assert_eq!(notifier.try_notify(3), Err(Error::Busy("test")));
+
+        assert_eq!(receiver.recv().await, Some(1));
+        assert_eq!(notifier.depth(), 1);
+        assert_eq!(notifier.try_notify(3), Ok(()));
+
+        drop(receiver);
+        assert_eq!(notifier.try_notify(4), Err(Error::Closed("test")));
+    }
+}
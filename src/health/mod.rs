@@ -0,0 +1,360 @@
+//! Health of the backends the bot depends on
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use serde::Deserialize;
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, UserId},
+    Bot,
+};
+use tokio::sync::watch;
+
+use crate::i18n::{Locales, Text};
+
+/// Status of a backend, from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Up,
+    /// Reachable, but not everything works
+    Degraded,
+    Down,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Status::Up => "up",
+            Status::Degraded => "degraded",
+            Status::Down => "down",
+        };
+
+        f.write_str(status)
+    }
+}
+
+/// Latest known state of a backend
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    /// Result of the last probe
+    probe: Status,
+    /// State of a long-lived connection, for backends that keep one open
+    connection: Option<Status>,
+    pub latency: Option<Duration>,
+    /// Loaded models, for backends that report them
+    pub models: Option<Vec<String>>,
+}
+
+impl Report {
+    pub fn status(&self) -> Status {
+        self.connection
+            .map_or(self.probe, |connection| connection.max(self.probe))
+    }
+}
+
+/// Shared health state of a single backend
+#[derive(Clone)]
+pub struct Health {
+    name: &'static str,
+    sender: Arc<watch::Sender<Report>>,
+}
+
+impl Health {
+    pub fn new(name: &'static str) -> Self {
+        Self::with_report(
+            name,
+            Report {
+                probe: Status::Up,
+                connection: None,
+                latency: None,
+                models: None,
+            },
+        )
+    }
+
+    /// Health of a backend that keeps a connection open, down until it's connected
+    pub fn with_connection(name: &'static str) -> Self {
+        Self::with_report(
+            name,
+            Report {
+                probe: Status::Up,
+                connection: Some(Status::Down),
+                latency: None,
+                models: None,
+            },
+        )
+    }
+
+    fn with_report(name: &'static str, report: Report) -> Self {
+        let (sender, _) = watch::channel(report);
+
+        Self {
+            name,
+            sender: Arc::new(sender),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn status(&self) -> Status {
+        self.sender.borrow().status()
+    }
+
+    pub fn report(&self) -> Report {
+        self.sender.borrow().clone()
+    }
+
+    /// Update the state of the connection
+    pub fn set(&self, status: Status) {
+        self.update(|report| report.connection = Some(status));
+    }
+
+    fn probed(&self, status: Status, latency: Option<Duration>, models: Option<Vec<String>>) {
+        self.update(|report| {
+            report.probe = status;
+            report.latency = latency;
+            report.models = models;
+        });
+    }
+
+    fn update(&self, modify: impl FnOnce(&mut Report)) {
+        let previous = self.status();
+
+        self.sender.send_modify(modify);
+
+        let status = self.status();
+        if previous != status {
+            log::info!("{} went from {previous} to {status}", self.name);
+        }
+    }
+}
+
+/// How a backend is probed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Any successful response will do
+    Plain,
+    /// Ollama's running models endpoint, reports the loaded models
+    OllamaModels,
+}
+
+/// A backend that is probed periodically
+pub struct Backend {
+    pub health: Health,
+    url: String,
+    kind: Kind,
+}
+
+impl Backend {
+    pub fn new(health: Health, url: String, kind: Kind) -> Self {
+        Self { health, url, kind }
+    }
+
+    async fn probe(&self, http_client: &reqwest::Client) {
+        let started = Instant::now();
+
+        let response = http_client
+            .get(&self.url)
+            .timeout(Monitor::TIMEOUT)
+            .send()
+            .await;
+
+        let latency = started.elapsed();
+
+        let response = match response {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                log::warn!("{} responded with {}", self.health.name, response.status());
+                self.health.probed(Status::Degraded, Some(latency), None);
+                return;
+            }
+            Err(err) => {
+                log::warn!("{} is unreachable: {err}", self.health.name);
+                self.health.probed(Status::Down, None, None);
+                return;
+            }
+        };
+
+        let models = match self.kind {
+            Kind::Plain => None,
+            Kind::OllamaModels => match response.json::<RunningModels>().await {
+                Ok(running) => Some(running.names()),
+                Err(err) => {
+                    log::warn!("failed to parse the running ollama models: {err}");
+                    None
+                }
+            },
+        };
+
+        self.health.probed(Status::Up, Some(latency), models);
+    }
+}
+
+#[derive(Deserialize)]
+struct RunningModels {
+    models: Vec<RunningModel>,
+}
+
+#[derive(Deserialize)]
+struct RunningModel {
+    name: String,
+}
+
+impl RunningModels {
+    fn names(self) -> Vec<String> {
+        self.models.into_iter().map(|model| model.name).collect()
+    }
+}
+
+/// Probes the backends and alerts the owner when one goes down
+#[derive(Clone)]
+pub struct Monitor {
+    backends: Arc<Vec<Backend>>,
+    http_client: reqwest::Client,
+    bot: Bot,
+    owner: Option<UserId>,
+    locales: Locales,
+    started_at: Instant,
+}
+
+impl Monitor {
+    const INTERVAL: Duration = Duration::from_secs(30);
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(
+        backends: Vec<Backend>,
+        http_client: reqwest::Client,
+        bot: Bot,
+        owner: Option<UserId>,
+        locales: Locales,
+    ) -> Self {
+        Self {
+            backends: Arc::new(backends),
+            http_client,
+            bot,
+            owner,
+            locales,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn backends(&self) -> &[Backend] {
+        &self.backends
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// Probe every backend periodically
+    pub async fn start(self) {
+        for backend in self.backends.iter() {
+            tokio::task::spawn(self.clone().alert(backend.health.clone()));
+        }
+
+        let mut interval = tokio::time::interval(Self::INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            futures::future::join_all(
+                self.backends
+                    .iter()
+                    .map(|backend| backend.probe(&self.http_client)),
+            )
+            .await;
+        }
+    }
+
+    /// Message the owner whenever the backend goes down or comes back up, in the language
+    /// of their private chat
+    async fn alert(self, health: Health) {
+        let Some(owner) = self.owner else {
+            return;
+        };
+
+        let mut receiver = health.sender.subscribe();
+        let mut previous = receiver.borrow_and_update().status();
+
+        while receiver.changed().await.is_ok() {
+            let status = receiver.borrow_and_update().status();
+
+            let language = self.locales.chat(ChatId::from(owner)).await;
+
+            let message = match (previous, status) {
+                (previous, Status::Down) if previous != Status::Down => {
+                    Text::BackendDown(health.name)
+                }
+                (Status::Down, status) if status != Status::Down => Text::BackendBack {
+                    name: health.name,
+                    status: Text::BackendStatus(status).translate(language),
+                },
+                _ => {
+                    previous = status;
+                    continue;
+                }
+            };
+
+            previous = status;
+
+            let res = self
+                .bot
+                .send_message(ChatId::from(owner), message.translate(language))
+                .await;
+
+            if let Err(err) = res {
+                log::error!("failed to alert the owner: {err}");
+            }
+        }
+    }
+}
+
+/// Human readable duration, like `2d 3h 15m`
+pub fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (60 * 24), minutes / 60 % 24, minutes % 60);
+
+    match (days, hours) {
+        (0, 0) => format!("{minutes}m"),
+        (0, hours) => format!("{hours}h {minutes}m"),
+        (days, hours) => format!("{days}d {hours}h {minutes}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let health = Health::with_connection("test");
+        assert_eq!(health.status(), Status::Down);
+
+        health.set(Status::Up);
+        assert_eq!(health.status(), Status::Up);
+
+        // the worst of the connection and the probe wins
+        health.probed(Status::Degraded, None, None);
+        assert_eq!(health.status(), Status::Degraded);
+
+        health.probed(Status::Up, Some(Duration::from_millis(5)), None);
+        health.set(Status::Down);
+        assert_eq!(health.status(), Status::Down);
+    }
+
+    #[test]
+    fn test_format_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(59)), "0m");
+        assert_eq!(format_uptime(Duration::from_secs(60 * 61)), "1h 1m");
+        assert_eq!(
+            format_uptime(Duration::from_secs(60 * 60 * 24 * 2 + 60 * 5)),
+            "2d 0h 5m"
+        );
+    }
+}
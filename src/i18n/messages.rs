@@ -0,0 +1,712 @@
+use crate::{
+    health::Status,
+    limits::{Exceeded, Kind, Limiter, Remaining, Scope},
+    local_ai::Stage,
+    logging::CorrelationId,
+    roles::Role,
+};
+
+use super::Language;
+
+/// Every reply the bot sends on its own, translated when it's sent
+#[derive(Debug)]
+pub enum Text {
+    // general
+    Busy,
+    SomethingWentWrong(Option<CorrelationId>),
+    Banned,
+    MinimumRole(Role),
+    Exceeded(Exceeded),
+    Remaining(Remaining),
+    Queued(usize),
+    Wablieft,
+
+    // images
+    ImageGeneratorDown,
+    TooManyImages,
+    ImageFailed {
+        reason: String,
+        error: CorrelationId,
+    },
+
+    // LLM and speech
+    TooManyPrompts,
+    TextFailed(CorrelationId),
+    NoResponse,
+    MissingPrompt,
+    NoChatHistory,
+    MissingUrl,
+    InvalidUrl,
+    NoHacking,
+    SearchUnavailable,
+    UnknownVoice {
+        voice: String,
+        voices: String,
+    },
+    UnknownModel {
+        model: String,
+        models: String,
+    },
+    InvalidTemperature(String),
+    NotARecording,
+    RecordingTooLarge,
+    NoSpeech,
+    ConversationFailed {
+        stage: Stage,
+        error: CorrelationId,
+    },
+    ShowReasoning,
+    ReasoningExpired,
+    Sources,
+
+    // fact checks
+    FactCheckWhat,
+    FactCheckBroken(String),
+
+    // admin
+    Clowned {
+        username: String,
+        until: Option<String>,
+    },
+    ClownFailed,
+    Unclowned(String),
+    UnclownFailed,
+    SentenceServed(String),
+    NobodyClowned,
+    ClownedEntry {
+        username: String,
+        prompt: String,
+        since: String,
+    },
+    Until(String),
+    DrunkSince(String),
+    CustomPromptSince {
+        prompt: String,
+        since: String,
+    },
+    DefaultPrompt,
+    PromptResetFailed,
+    PromptSetFailed,
+    DrunkFailed,
+    OwnerOnlyReload,
+    Reloaded,
+    InvalidRule(String),
+    OwnerOnlyRuleScope,
+    RuleAdded(String),
+    RuleAddFailed,
+    NoRules,
+    MissingRuleNumber,
+    RuleRemoved(i64),
+    UnknownRule(i64),
+    RuleRemoveFailed,
+    ReplyToGrant,
+    UnknownRole(String),
+    NotAllowed(Role),
+    GrantFailed,
+    Granted {
+        username: String,
+        role: Role,
+    },
+    NoRoles,
+    LanguageSet(Language),
+    LanguageFollowsUsers,
+    LanguageFailed,
+    UnknownLanguage(String),
+    TranscriptionUnavailable,
+    UnknownToggle(String),
+    AutoTranscribeOn,
+    AutoTranscribeOff,
+    AutoTranscribeFailed,
+
+    // status
+    Uptime(String),
+    BackendStatus(Status),
+    Latency(u128),
+    Load {
+        running: usize,
+        waiting: usize,
+    },
+    QueuedUpdates(usize),
+    NoModelLoaded,
+    ModelsLoaded(String),
+    BackendDown(&'static str),
+    BackendBack {
+        name: &'static str,
+        status: String,
+    },
+
+    // help
+    AdminCommands,
+    HelpMore,
+    Usage(String),
+    Example(String),
+    UnknownCommand(String),
+}
+
+impl Text {
+    pub fn translate(&self, language: Language) -> String {
+        // only the text of the language is formatted
+        macro_rules! text {
+            ($nl:literal, $en:literal, $fr:literal $(,)?) => {
+                match language {
+                    Language::Nl => format!($nl),
+                    Language::En => format!($en),
+                    Language::Fr => format!($fr),
+                }
+            };
+        }
+
+        let reset = Limiter::RESET_TIME;
+
+        match self {
+            Text::Busy => text!(
+                "De bot is druk bezig, probeer later opnieuw",
+                "The bot is busy, try again later",
+                "Le bot est occupé, réessaie plus tard",
+            ),
+            Text::SomethingWentWrong(Some(id)) => text!(
+                "Er is iets misgelopen, probeer later opnieuw (fout {id})",
+                "Something went wrong, try again later (error {id})",
+                "Quelque chose s'est mal passé, réessaie plus tard (erreur {id})",
+            ),
+            Text::SomethingWentWrong(None) => text!(
+                "Er is iets misgelopen, probeer later opnieuw",
+                "Something went wrong, try again later",
+                "Quelque chose s'est mal passé, réessaie plus tard",
+            ),
+            Text::Banned => text!(
+                "Je bent verbannen van deze bot",
+                "You have been banned from using this bot",
+                "Tu as été banni de ce bot",
+            ),
+            Text::MinimumRole(role) => text!(
+                "Dit commando is enkel beschikbaar vanaf de rol {role}",
+                "This command is only available to the {role} role and up",
+                "Cette commande est réservée au rôle {role} et plus",
+            ),
+            Text::Exceeded(Exceeded::RateLimited {
+                retry_after_seconds: seconds,
+                ..
+            }) => text!(
+                "Rustig aan, probeer opnieuw over {seconds} seconden",
+                "Slow down, try again in {seconds} seconds",
+                "Doucement, réessaie dans {seconds} secondes",
+            ),
+            Text::Exceeded(Exceeded::Quota {
+                kind,
+                scope: Scope::User(_),
+            }) => {
+                let noun = noun(*kind, language);
+                text!(
+                    "Je hebt vandaag geen {noun} meer, je quotum wordt om {reset} hersteld",
+                    "You have no {noun} left today, your quota resets at {reset}",
+                    "Tu n'as plus de {noun} aujourd'hui, ton quota est réinitialisé à {reset}",
+                )
+            }
+            Text::Exceeded(Exceeded::Quota {
+                kind,
+                scope: Scope::Chat(_),
+            }) => {
+                let noun = noun(*kind, language);
+                text!(
+                    "Deze chat heeft vandaag geen {noun} meer, het quotum wordt om {reset} hersteld",
+                    "This chat has no {noun} left today, the quota resets at {reset}",
+                    "Ce chat n'a plus de {noun} aujourd'hui, le quota est réinitialisé à {reset}",
+                )
+            }
+            Text::Remaining(remaining) => {
+                let (today, noun) = (remaining.today, noun(remaining.kind, language));
+                text!(
+                    "Je hebt vandaag nog {today} {noun}, om {reset} komen er nieuwe",
+                    "You have {today} {noun} left today, resets at {reset}",
+                    "Il te reste {today} {noun} aujourd'hui, réinitialisation à {reset}",
+                )
+            }
+            Text::Queued(position) => text!(
+                "Iedereen is bezig, je staat #{position} in de rij",
+                "Everyone is busy, you're #{position} in the queue",
+                "Tout le monde est occupé, tu es #{position} dans la file",
+            ),
+            Text::Wablieft => text!("wablieft?", "pardon?", "hein?"),
+
+            Text::ImageGeneratorDown => text!(
+                "De afbeeldingsgenerator ligt plat, probeer later opnieuw",
+                "The image generator is down, try again later",
+                "Le générateur d'images est en panne, réessaie plus tard",
+            ),
+            Text::TooManyImages => text!(
+                "Je hebt al te veel afbeeldingen in de maak",
+                "You already have too many images in progress",
+                "Tu as déjà trop d'images en cours",
+            ),
+            Text::ImageFailed { reason, error } => text!(
+                "Afbeelding maken mislukt: {reason} (fout {error})",
+                "Failed to generate image: {reason} (error {error})",
+                "Échec de la génération de l'image : {reason} (erreur {error})",
+            ),
+
+            Text::TooManyPrompts => text!(
+                "Je hebt al te veel prompts in behandeling",
+                "You already have too many prompts in progress",
+                "Tu as déjà trop de prompts en cours",
+            ),
+            Text::TextFailed(error) => text!(
+                "Antwoord genereren mislukt, vermeld fout {error} als je dit meldt",
+                "Failed to generate text prompt, mention error {error} when reporting this",
+                "Échec de la génération du texte, mentionne l'erreur {error} en le signalant",
+            ),
+            Text::NoResponse => text!(
+                "Er kon geen antwoord gegenereerd worden",
+                "Failed to generate a response",
+                "Impossible de générer une réponse",
+            ),
+            Text::MissingPrompt => text!(
+                "Je moet een geldige prompt meegeven",
+                "You need to provide a valid prompt",
+                "Tu dois fournir un prompt valide",
+            ),
+            Text::NoChatHistory => text!(
+                "Geen berichten gevonden. Laat me langer meelezen of pas mijn rechten aan.",
+                "No chat content found. Please let me learn longer or adjust my permissions.",
+                "Aucun message trouvé. Laisse-moi lire plus longtemps ou ajuste mes permissions.",
+            ),
+            Text::MissingUrl => text!(
+                "Geef de URL van een pagina om samen te vatten",
+                "Please provide the URL of a page to summarize",
+                "Donne l'URL d'une page à résumer",
+            ),
+            Text::InvalidUrl => text!(
+                "Geen geldige URL",
+                "Not a valid URL",
+                "URL invalide",
+            ),
+            Text::NoHacking => text!(
+                "don't hack me hé klet",
+                "don't hack me, you clown",
+                "ne me hacke pas, espèce de clown",
+            ),
+            Text::SearchUnavailable => text!(
+                "Het web doorzoeken is niet beschikbaar",
+                "Searching the web isn't available",
+                "La recherche sur le web n'est pas disponible",
+            ),
+            Text::UnknownVoice { voice, voices } => text!(
+                "Onbekende stem '{voice}', kies uit {voices}",
+                "Unknown voice '{voice}', pick one of {voices}",
+                "Voix inconnue '{voice}', choisis parmi {voices}",
+            ),
+            Text::UnknownModel { model, models } => text!(
+                "Onbekend model '{model}', kies uit {models}",
+                "Unknown model '{model}', pick one of {models}",
+                "Modèle inconnu '{model}', choisis parmi {models}",
+            ),
+            Text::InvalidTemperature(temperature) => text!(
+                "Ongeldige temperatuur '{temperature}', kies een getal van 0 tot 2",
+                "Invalid temperature '{temperature}', pick a number from 0 to 2",
+                "Température invalide '{temperature}', choisis un nombre de 0 à 2",
+            ),
+            Text::NotARecording => text!(
+                "Antwoord op een spraakbericht, audiobestand of videobericht",
+                "Reply to a voice message, audio file or video note",
+                "Réponds à un message vocal, un fichier audio ou une note vidéo",
+            ),
+            Text::RecordingTooLarge => text!(
+                "Die opname is te groot, ik kan maximaal 20 MB downloaden",
+                "That recording is too large, I can download 20 MB at most",
+                "Cet enregistrement est trop gros, je peux télécharger 20 Mo au maximum",
+            ),
+            Text::NoSpeech => text!(
+                "Ik hoor niemand praten",
+                "I can't hear anyone speaking",
+                "Je n'entends personne parler",
+            ),
+            Text::ConversationFailed { stage, error } => match stage {
+                Stage::Transcription => text!(
+                    "Ik kon je spraakbericht niet verstaan, vermeld fout {error} als je dit meldt",
+                    "I couldn't make out your voice message, mention error {error} when reporting this",
+                    "Je n'ai pas compris ton message vocal, mentionne l'erreur {error} en le signalant",
+                ),
+                Stage::Answer => text!(
+                    "Ik heb je verstaan, maar kon geen antwoord bedenken, vermeld fout {error} als je dit meldt",
+                    "I understood you, but couldn't come up with an answer, mention error {error} when reporting this",
+                    "Je t'ai compris, mais je n'ai pas trouvé de réponse, mentionne l'erreur {error} en le signalant",
+                ),
+                Stage::Speech => text!(
+                    "Voorlezen is mislukt (fout {error}), dit is mijn antwoord:",
+                    "Reading it out loud failed (error {error}), this is my answer:",
+                    "La lecture à voix haute a échoué (erreur {error}), voici ma réponse :",
+                ),
+            },
+            Text::ShowReasoning => text!(
+                "Toon redenering",
+                "Show reasoning",
+                "Afficher le raisonnement",
+            ),
+            Text::ReasoningExpired => text!(
+                "Die redenering is niet meer beschikbaar",
+                "That reasoning is no longer available",
+                "Ce raisonnement n'est plus disponible",
+            ),
+            Text::Sources => text!("Bronnen", "Sources", "Sources"),
+
+            Text::FactCheckWhat => text!(
+                "Wat wil je met 100% zekerheid laten checken? (antwoord op het bericht dat je wilt laten checken)",
+                "What do you want me to fact check with 100% accuracy? (you have to reply to the message you want fact checked)",
+                "Que veux-tu que je vérifie avec 100% de précision ? (réponds au message à vérifier)",
+            ),
+            Text::FactCheckBroken(err) => text!(
+                "er is iets kapot: {err}",
+                "something is broken: {err}",
+                "quelque chose est cassé : {err}",
+            ),
+
+            Text::Clowned {
+                username,
+                until: Some(until),
+            } => text!(
+                "{username} is een clown tot {until}",
+                "{username} has been clowned until {until}",
+                "{username} est un clown jusqu'au {until}",
+            ),
+            Text::Clowned {
+                username,
+                until: None,
+            } => text!(
+                "{username} is een clown",
+                "{username} has been clowned",
+                "{username} est un clown",
+            ),
+            Text::ClownFailed => text!(
+                "clownen mislukt, de clowns staken",
+                "failed to clown, the clowns are on strike",
+                "échec, les clowns sont en grève",
+            ),
+            Text::Unclowned(username) => text!(
+                "{username} is geen clown meer",
+                "{username} has been unclowned",
+                "{username} n'est plus un clown",
+            ),
+            Text::UnclownFailed => text!(
+                "ontclownen mislukt",
+                "failed to unclown",
+                "impossible de retirer le clown",
+            ),
+            Text::SentenceServed(username) => text!(
+                "{username} heeft de straf uitgezeten en is geen clown meer",
+                "{username} has served their sentence and is no longer a clown",
+                "{username} a purgé sa peine et n'est plus un clown",
+            ),
+            Text::NobodyClowned => text!(
+                "Niemand is een clown",
+                "Nobody has been clowned",
+                "Personne n'est un clown",
+            ),
+            Text::ClownedEntry {
+                username,
+                prompt,
+                since,
+            } => text!(
+                "{username}: \"{prompt}\" sinds {since}",
+                "{username}: \"{prompt}\" since {since}",
+                "{username} : « {prompt} » depuis le {since}",
+            ),
+            Text::Until(until) => text!(" tot {until}", " until {until}", " jusqu'au {until}"),
+            Text::DrunkSince(since) => text!(
+                "LLM: dronken sinds {since}",
+                "LLM: drunk since {since}",
+                "LLM : ivre depuis le {since}",
+            ),
+            Text::CustomPromptSince { prompt, since } => text!(
+                "LLM: \"{prompt}\" sinds {since}",
+                "LLM: \"{prompt}\" since {since}",
+                "LLM : « {prompt} » depuis le {since}",
+            ),
+            Text::DefaultPrompt => text!(
+                "LLM: standaard prompt",
+                "LLM: default prompt",
+                "LLM : prompt par défaut",
+            ),
+            Text::PromptResetFailed => text!(
+                "de LLM prompt herstellen is mislukt",
+                "failed to reset the LLM prompt",
+                "impossible de réinitialiser le prompt du LLM",
+            ),
+            Text::PromptSetFailed => text!(
+                "de LLM prompt instellen is mislukt",
+                "failed to set the LLM prompt",
+                "impossible de définir le prompt du LLM",
+            ),
+            Text::DrunkFailed => text!(
+                "de LLM dronken krijgen is mislukt",
+                "failed to get the LLM drunk",
+                "impossible de saouler le LLM",
+            ),
+            Text::OwnerOnlyReload => text!(
+                "enkel de eigenaar kan de instellingen herladen",
+                "only the owner can reload the settings",
+                "seul le propriétaire peut recharger les paramètres",
+            ),
+            Text::OwnerOnlyRuleScope => text!(
+                "enkel de eigenaar kan regels voor andere chats maken",
+                "only the owner can add rules for other chats",
+                "seul le propriétaire peut ajouter des règles pour d'autres chats",
+            ),
+            Text::Reloaded => text!(
+                "De limieten, prompts, presets en stemmen zijn herladen, andere instellingen vereisen een herstart",
+                "Reloaded the limits, prompts, presets and voices, other settings need a restart",
+                "Les limites, prompts, presets et voix ont été rechargés, les autres paramètres nécessitent un redémarrage",
+            ),
+            Text::InvalidRule(err) => text!(
+                "Ongeldige regel: {err}",
+                "Invalid rule: {err}",
+                "Règle invalide : {err}",
+            ),
+            Text::RuleAdded(rule) => text!(
+                "Regel {rule} toegevoegd",
+                "Added rule {rule}",
+                "Règle {rule} ajoutée",
+            ),
+            Text::RuleAddFailed => text!(
+                "de regel toevoegen is mislukt",
+                "failed to add the rule",
+                "impossible d'ajouter la règle",
+            ),
+            Text::NoRules => text!(
+                "Er gelden geen regels in deze chat",
+                "No rules apply in this chat",
+                "Aucune règle ne s'applique dans ce chat",
+            ),
+            Text::MissingRuleNumber => text!(
+                "Geef het nummer van de regel die weg moet",
+                "Provide the number of the rule to remove",
+                "Indique le numéro de la règle à supprimer",
+            ),
+            Text::RuleRemoved(id) => text!(
+                "Regel #{id} verwijderd",
+                "Removed rule #{id}",
+                "Règle #{id} supprimée",
+            ),
+            Text::UnknownRule(id) => text!(
+                "Regel #{id} bestaat niet",
+                "Rule #{id} doesn't exist",
+                "La règle #{id} n'existe pas",
+            ),
+            Text::RuleRemoveFailed => text!(
+                "de regel verwijderen is mislukt",
+                "failed to remove the rule",
+                "impossible de supprimer la règle",
+            ),
+            Text::ReplyToGrant => text!(
+                "Antwoord op de gebruiker die een rol moet krijgen",
+                "Reply to the user you want to grant a role",
+                "Réponds à l'utilisateur à qui tu veux donner un rôle",
+            ),
+            Text::UnknownRole(role) => text!(
+                "onbekende rol '{role}', gebruik banned, user, moderator, admin of owner",
+                "unknown role '{role}', use banned, user, moderator, admin or owner",
+                "rôle inconnu '{role}', utilise banned, user, moderator, admin ou owner",
+            ),
+            Text::NotAllowed(role) => text!(
+                "een {role} mag dat niet",
+                "a {role} can't do that",
+                "un {role} ne peut pas faire ça",
+            ),
+            Text::GrantFailed => text!(
+                "de rol toekennen is mislukt",
+                "failed to grant the role",
+                "impossible d'attribuer le rôle",
+            ),
+            Text::Granted { username, role } => text!(
+                "{username} is nu {role}",
+                "{username} is now a {role}",
+                "{username} est maintenant {role}",
+            ),
+            Text::NoRoles => text!(
+                "Er zijn geen rollen toegekend in deze chat",
+                "No roles have been granted in this chat",
+                "Aucun rôle n'a été attribué dans ce chat",
+            ),
+            Text::LanguageSet(chosen) => {
+                let chosen = name(*chosen, language);
+                text!(
+                    "Ik antwoord in deze chat voortaan in het {chosen}",
+                    "From now on I'll reply in {chosen} in this chat",
+                    "Désormais je réponds en {chosen} dans ce chat",
+                )
+            }
+            Text::LanguageFollowsUsers => text!(
+                "Ik antwoord iedereen in de taal van hun Telegram",
+                "I'll reply to everyone in the language of their Telegram",
+                "Je réponds à chacun dans la langue de son Telegram",
+            ),
+            Text::LanguageFailed => text!(
+                "de taal instellen is mislukt",
+                "failed to set the language",
+                "impossible de définir la langue",
+            ),
+            Text::UnknownLanguage(language) => text!(
+                "onbekende taal '{language}', gebruik nl, en, fr of auto",
+                "unknown language '{language}', use nl, en, fr or auto",
+                "langue inconnue '{language}', utilise nl, en, fr ou auto",
+            ),
+            Text::TranscriptionUnavailable => text!(
+                "spraakherkenning is niet beschikbaar",
+                "speech to text isn't available",
+                "la reconnaissance vocale n'est pas disponible",
+            ),
+            Text::UnknownToggle(toggle) => text!(
+                "onbekende waarde '{toggle}', gebruik on of off",
+                "unknown value '{toggle}', use on or off",
+                "valeur inconnue '{toggle}', utilise on ou off",
+            ),
+            Text::AutoTranscribeOn => text!(
+                "Ik schrijf voortaan elk spraakbericht in deze chat uit",
+                "From now on I'll write out every voice message in this chat",
+                "Désormais je retranscris chaque message vocal de ce chat",
+            ),
+            Text::AutoTranscribeOff => text!(
+                "Ik schrijf spraakberichten enkel nog uit met /transcribe",
+                "I'll only write out voice messages on /transcribe",
+                "Je ne retranscris les messages vocaux qu'avec /transcribe",
+            ),
+            Text::AutoTranscribeFailed => text!(
+                "automatisch uitschrijven instellen is mislukt",
+                "failed to set automatic transcription",
+                "impossible de régler la transcription automatique",
+            ),
+
+            Text::Uptime(uptime) => text!(
+                "Actief sinds: {uptime}",
+                "Uptime: {uptime}",
+                "En service depuis : {uptime}",
+            ),
+            Text::BackendStatus(Status::Up) => text!("online", "up", "en ligne"),
+            Text::BackendStatus(Status::Degraded) => text!("verminderd", "degraded", "dégradé"),
+            Text::BackendStatus(Status::Down) => text!("offline", "down", "hors ligne"),
+            Text::Latency(millis) => text!("{millis} ms", "{millis} ms", "{millis} ms"),
+            Text::Load { running, waiting } => text!(
+                "{running} bezig, {waiting} wachtend",
+                "{running} running, {waiting} waiting",
+                "{running} en cours, {waiting} en attente",
+            ),
+            Text::QueuedUpdates(depth) => text!(
+                "{depth} updates in de rij",
+                "{depth} queued updates",
+                "{depth} mises à jour en file",
+            ),
+            Text::NoModelLoaded => text!(
+                "geen model geladen",
+                "no model loaded",
+                "aucun modèle chargé",
+            ),
+            Text::ModelsLoaded(models) => text!(
+                "{models} geladen",
+                "loaded {models}",
+                "{models} chargé",
+            ),
+            Text::BackendDown(name) => text!(
+                "⚠️ {name} is offline",
+                "⚠️ {name} is down",
+                "⚠️ {name} est hors ligne",
+            ),
+            Text::BackendBack { name, status } => text!(
+                "{name} is weer {status}",
+                "{name} is {status} again",
+                "{name} est de nouveau {status}",
+            ),
+
+            Text::AdminCommands => text!("Admin commando's:", "Admin commands:", "Commandes admin :"),
+            Text::HelpMore => text!(
+                "Gebruik /help <commando> voor meer uitleg",
+                "Use /help <command> for more",
+                "Utilise /help <commande> pour en savoir plus",
+            ),
+            Text::Usage(usage) => text!("Gebruik: {usage}", "Usage: {usage}", "Utilisation : {usage}"),
+            Text::Example(example) => text!(
+                "Voorbeeld: {example}",
+                "Example: {example}",
+                "Exemple : {example}",
+            ),
+            Text::UnknownCommand(command) => text!(
+                "Onbekend commando '{command}', zie /help",
+                "Unknown command '{command}', see /help",
+                "Commande inconnue '{command}', voir /help",
+            ),
+        }
+    }
+}
+
+/// What the user gets from a limited kind of work
+fn noun(kind: Kind, language: Language) -> &'static str {
+    match (kind, language) {
+        (Kind::Image, Language::Nl) => "afbeeldingen",
+        (Kind::Image, Language::En) => "images",
+        (Kind::Image, Language::Fr) => "images",
+        (Kind::Llm, Language::Nl) => "prompts",
+        (Kind::Llm, Language::En) => "prompts",
+        (Kind::Llm, Language::Fr) => "prompts",
+        (Kind::Tts, Language::Nl) => "spraakberichten",
+        (Kind::Tts, Language::En) => "voice messages",
+        (Kind::Tts, Language::Fr) => "messages vocaux",
+        (Kind::Web, Language::Nl) => "opzoekingen",
+        (Kind::Web, Language::En) => "web lookups",
+        (Kind::Web, Language::Fr) => "recherches web",
+    }
+}
+
+/// Name of a language, as written in another language
+fn name(language: Language, written_in: Language) -> &'static str {
+    match (language, written_in) {
+        (Language::Nl, Language::Nl) => "Nederlands",
+        (Language::Nl, Language::En) => "Dutch",
+        (Language::Nl, Language::Fr) => "néerlandais",
+        (Language::En, Language::Nl) => "Engels",
+        (Language::En, Language::En) => "English",
+        (Language::En, Language::Fr) => "anglais",
+        (Language::Fr, Language::Nl) => "Frans",
+        (Language::Fr, Language::En) => "French",
+        (Language::Fr, Language::Fr) => "français",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::UserId;
+
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(
+            Text::Queued(2).translate(Language::En),
+            "Everyone is busy, you're #2 in the queue"
+        );
+        assert_eq!(
+            Text::Queued(2).translate(Language::Nl),
+            "Iedereen is bezig, je staat #2 in de rij"
+        );
+
+        let exceeded = Exceeded::Quota {
+            kind: Kind::Tts,
+            scope: Scope::User(UserId(1)),
+        };
+        assert_eq!(
+            Text::Exceeded(exceeded).translate(Language::Fr),
+            "Tu n'as plus de messages vocaux aujourd'hui, ton quota est réinitialisé à 00:00 UTC"
+        );
+
+        let back = Text::BackendBack {
+            name: "ollama",
+            status: Text::BackendStatus(Status::Degraded).translate(Language::Fr),
+        };
+        assert_eq!(
+            back.translate(Language::Fr),
+            "ollama est de nouveau dégradé"
+        );
+
+        assert_eq!(
+            Text::LanguageSet(Language::Fr).translate(Language::Nl),
+            "Ik antwoord in deze chat voortaan in het Frans"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Translated replies, in the language of the chat or of the user
+
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc};
+
+use serde::Deserialize;
+use teloxide::types::{ChatId, User};
+use tokio::sync::RwLock;
+
+use crate::store::Store;
+
+mod messages;
+
+pub use messages::Text;
+
+/// Languages the bot replies in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Nl,
+    #[default]
+    En,
+    Fr,
+}
+
+impl Language {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Language::Nl => "nl",
+            Language::En => "en",
+            Language::Fr => "fr",
+        }
+    }
+
+    /// Match a Telegram language code, such as `nl` or `fr-BE`
+    pub fn from_code(code: &str) -> Option<Self> {
+        let primary = code.split(['-', '_']).next()?;
+        primary.to_lowercase().parse().ok()
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nl" => Ok(Language::Nl),
+            "en" => Ok(Language::En),
+            "fr" => Ok(Language::Fr),
+            other => Err(format!("unknown language '{other}', use nl, en or fr")),
+        }
+    }
+}
+
+/// Language per chat, persisted in the store
+#[derive(Clone)]
+pub struct Locales {
+    chats: Arc<RwLock<HashMap<ChatId, Language>>>,
+    store: Store,
+    default: Language,
+}
+
+impl Locales {
+    /// Load the persisted languages
+    pub async fn load(store: Store, default: Language) -> Result<Self, anyhow::Error> {
+        let chats = store.chat_languages().await?.into_iter().collect();
+
+        Ok(Self {
+            chats: Arc::new(RwLock::new(chats)),
+            store,
+            default,
+        })
+    }
+
+    /// The language to reply in
+    ///
+    /// The language set for the chat wins, followed by the Telegram language of the user
+    /// and finally the configured default.
+    pub async fn language(&self, chat_id: ChatId, user: Option<&User>) -> Language {
+        if let Some(language) = self.chats.read().await.get(&chat_id) {
+            return *language;
+        }
+
+        user.and_then(|user| user.language_code.as_deref())
+            .and_then(Language::from_code)
+            .unwrap_or(self.default)
+    }
+
+    /// The language to use when there's no user to reply to
+    pub async fn chat(&self, chat_id: ChatId) -> Language {
+        self.language(chat_id, None).await
+    }
+
+    /// Set the language of a chat, `None` follows the language of every user again
+    pub async fn set(
+        &self,
+        chat_id: ChatId,
+        language: Option<Language>,
+    ) -> Result<(), anyhow::Error> {
+        match language {
+            Some(language) => {
+                self.store.set_chat_language(chat_id, language).await?;
+                self.chats.write().await.insert(chat_id, language);
+            }
+            None => {
+                self.store.remove_chat_language(chat_id).await?;
+                self.chats.write().await.remove(&chat_id);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_code() {
+        assert_eq!(Language::from_code("nl"), Some(Language::Nl));
+        assert_eq!(Language::from_code("fr-BE"), Some(Language::Fr));
+        assert_eq!(Language::from_code("EN_us"), Some(Language::En));
+        assert_eq!(Language::from_code("de"), None);
+        assert_eq!(Language::from_code(""), None);
+    }
+}
@@ -1,56 +1,77 @@
-use std::sync::{Arc, OnceLock};
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 
 use futures_util::FutureExt;
 use rust_socketio::asynchronous::{
     Client as SocketClient, ClientBuilder as SocketClientBuilder, ReconnectSettings,
 };
-use rust_socketio::Payload;
+use rust_socketio::{Event, Payload};
 use serde_json::json;
-use teloxide::types::{ChatId, MessageId, UserId};
 
 use crate::handler::invoke::{Notifier, Update};
+use crate::health::{Health, Status};
 use crate::invoke_ai::models::invocations::{InvocationComplete, InvocationError};
 use crate::invoke_ai::models::{Enqueue, EnqueueResult};
+use crate::logging::Correlate;
 
 use super::Error;
 
 #[derive(Clone)]
 pub struct InvokeAI {
     http: reqwest::Client,
-    socket: SocketClient,
-    notifier: Notifier,
     url: String,
 }
 
 impl InvokeAI {
-    /// Connect to the Socket.IO server of the InvokeAI instance
-    pub async fn connect(
-        url: String,
-        notifier: Notifier,
-        http_client: reqwest::Client,
-    ) -> Result<Self, Error> {
-        let socket = Self::construct_socket_io_client(url.clone(), notifier.clone()).await?;
-
-        let client = Self {
+    pub fn new(url: String, http_client: reqwest::Client) -> Self {
+        Self {
             http: http_client,
-            socket,
-            notifier,
             url,
+        }
+    }
+
+    /// Keep the Socket.IO connection to the InvokeAI instance alive
+    ///
+    /// Retries until the first connection is established, the socket reconnects on its own
+    /// afterwards and subscribes to the queue again every time it does.
+    pub async fn supervise(self, notifier: Notifier, health: Health) {
+        const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+        let _socket = loop {
+            match self.connect(notifier.clone(), health.clone()).await {
+                Ok(socket) => break socket,
+                Err(err) => {
+                    log::error!("failed to connect to invoke-ai: {err}");
+                    health.set(Status::Down);
+                }
+            }
+
+            tokio::time::sleep(RETRY_DELAY).await;
         };
 
-        client.subscribe().await?;
+        // keeps the socket alive
+        std::future::pending::<()>().await;
+    }
 
-        Ok(client)
+    /// Connect to the Socket.IO server of the InvokeAI instance
+    async fn connect(&self, notifier: Notifier, health: Health) -> Result<SocketClient, Error> {
+        // the connect handler subscribes to the queue and marks the connection as up
+        Self::construct_socket_io_client(self.url.clone(), notifier, health).await
     }
 
     /// Create a SocketIO websocket connection to the InvokeAI instance
     async fn construct_socket_io_client(
         url: String,
         notifier: Notifier,
+        health: Health,
     ) -> Result<SocketClient, Error> {
         let url = Arc::new(url);
 
         let reconnect_url = Arc::new(format!("{url}/ws/socket.io/"));
+        let failures = notifier.clone();
+        let disconnects = notifier.clone();
 
         SocketClientBuilder::new(reconnect_url.as_str())
             .namespace("/")
@@ -75,18 +96,22 @@ impl InvokeAI {
                             };
 
                             if invocation.still_in_progress() {
-                                notifier.notify(Update::Progress {
-                                    id: invocation.id(),
-                                });
+                                notifier
+                                    .notify(Update::Progress {
+                                        id: invocation.id(),
+                                    })
+                                    .await;
                                 return;
                             }
 
                             match invocation.image_path() {
                                 Some(path) => {
-                                    notifier.notify(Update::Finished {
-                                        batch_id: invocation.id(),
-                                        image_url: format!("{url}/api/v1/images/i/{path}/full"),
-                                    });
+                                    notifier
+                                        .notify(Update::Finished {
+                                            batch_id: invocation.id(),
+                                            image_url: format!("{url}/api/v1/images/i/{path}/full"),
+                                        })
+                                        .await;
                                 }
                                 None => log::debug!("missing image, unimportant update"),
                             }
@@ -96,7 +121,8 @@ impl InvokeAI {
                 }
                 .boxed()
             })
-            .on("invocation_error", |payload, _client| {
+            .on("invocation_error", move |payload, _client| {
+                let notifier = failures.clone();
                 async move {
                     let error = match payload {
                         Payload::Text(mut payload) => payload.pop(),
@@ -121,15 +147,56 @@ impl InvokeAI {
                         }
                     };
 
-                    log::error!("invoaction error: {invocation_error:?}");
+                    log::error!("invocation error: {invocation_error:?}");
+
+                    // frees the scheduler slot of the batch
+                    notifier
+                        .notify(Update::Failed {
+                            batch_id: invocation_error.id(),
+                            reason: invocation_error.reason().to_string(),
+                        })
+                        .await;
                 }
                 .boxed()
             })
+            .on(Event::Connect, {
+                let health = health.clone();
+                move |_payload, client| {
+                    let health = health.clone();
+                    async move {
+                        // subscriptions don't survive a reconnect
+                        match Self::subscribe(&client).await {
+                            Ok(()) => health.set(Status::Up),
+                            Err(err) => {
+                                log::error!("{err}");
+                                health.set(Status::Degraded);
+                            }
+                        }
+                    }
+                    .boxed()
+                }
+            })
+            .on(Event::Close, {
+                let health = health.clone();
+                move |_payload, _client| {
+                    health.set(Status::Down);
+
+                    let notifier = disconnects.clone();
+                    async move { notifier.notify(Update::Disconnected).await }.boxed()
+                }
+            })
+            .on(Event::Error, move |payload, _client| {
+                log::warn!("invoke-ai socket error: {payload:?}");
+                if health.status() == Status::Up {
+                    health.set(Status::Degraded);
+                }
+                async {}.boxed()
+            })
             .on_reconnect(move || {
                 let url = reconnect_url.clone();
                 async move {
                     let mut settings = ReconnectSettings::new();
-                    settings.address(format!("{url}/ws/socket.io/"));
+                    settings.address(url.to_string());
                     settings.auth(json!({"queue_id": "default"}));
                     settings
                 }
@@ -141,8 +208,8 @@ impl InvokeAI {
     }
 
     /// Subscribe to InvokeAI Socket.IO updates
-    async fn subscribe(&self) -> Result<(), Error> {
-        self.socket
+    async fn subscribe(socket: &SocketClient) -> Result<(), Error> {
+        socket
             .emit("subscribe_queue", json!({"queue_id": "default"}))
             .await
             .map_err(Error::Subscription)
@@ -152,9 +219,6 @@ impl InvokeAI {
     pub async fn enqueue_text_to_image(
         &self,
         enqueue: Box<Enqueue>,
-        chat_id: ChatId,
-        user_id: UserId,
-        message_id: MessageId,
     ) -> Result<EnqueueResult, Error> {
         static CELL: OnceLock<String> = OnceLock::new();
 
@@ -167,6 +231,7 @@ impl InvokeAI {
             .http
             .post(url)
             .json(&enqueue)
+            .correlate()
             .send()
             .await?
             .text()
@@ -177,13 +242,6 @@ impl InvokeAI {
             error
         })?;
 
-        self.notifier.notify(Update::Started {
-            id: enqueued.id(),
-            chat_id,
-            user_id,
-            message_id,
-        });
-
         Ok(enqueued)
     }
 
@@ -191,6 +249,7 @@ impl InvokeAI {
     pub async fn download_image(&self, url: String) -> Result<bytes::Bytes, Error> {
         self.http
             .get(url)
+            .correlate()
             .send()
             .await?
             .bytes()
@@ -2,6 +2,7 @@ use thiserror::Error;
 
 pub mod client;
 pub mod models;
+pub mod presets;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -5,6 +5,7 @@ use super::BatchId;
 #[allow(unused)]
 #[derive(Debug, Deserialize)]
 pub struct InvocationError {
+    queue_batch_id: BatchId,
     /// Offending node
     source_node_id: Option<String>,
     error_type: String,
@@ -12,6 +13,17 @@ pub struct InvocationError {
     error: String,
 }
 
+impl InvocationError {
+    pub fn id(&self) -> BatchId {
+        self.queue_batch_id
+    }
+
+    /// Short description for users, without the stack trace
+    pub fn reason(&self) -> &str {
+        &self.error_type
+    }
+}
+
 #[allow(unused)]
 #[derive(Debug, Deserialize)]
 pub struct InvocationComplete {
@@ -196,6 +196,12 @@ impl Enqueue {
         }
     }
 
+    /// Add terms to the end of the prompt
+    pub fn extend_prompt(&mut self, terms: &str) {
+        let conditioning = &mut self.batch.graph.nodes.positive_conditioning;
+        conditioning.prompt = format!("{}, {terms}", conditioning.prompt);
+    }
+
     fn set_negative_prompt(&mut self, prompt: &'static str) {
         self.batch.graph.nodes.negative_conditioning.prompt = prompt;
         self.batch.graph.nodes.core_metadata.negative_prompt = prompt;
@@ -0,0 +1,95 @@
+//! Extra prompt terms per image style, configurable and reloadable
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use serde::Deserialize;
+
+use super::models::Enqueue;
+use crate::rules::Style;
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct Preset {
+    /// Added to the end of every prompt in this style
+    pub prompt: Option<String>,
+}
+
+/// Presets by style name, like `[presets.lego]`
+pub type Config = HashMap<String, Preset>;
+
+/// Problems with the configured presets, if any
+pub fn problems(config: &Config) -> Vec<String> {
+    config
+        .keys()
+        .filter_map(|name| {
+            name.parse::<Style>()
+                .err()
+                .map(|err| format!("presets.{name}: {err}"))
+        })
+        .collect()
+}
+
+#[derive(Clone, Default)]
+pub struct Presets {
+    presets: Arc<RwLock<HashMap<Style, Preset>>>,
+}
+
+impl Presets {
+    pub fn new(config: Config) -> Self {
+        let presets = Self::default();
+        presets.set_config(config);
+        presets
+    }
+
+    /// Replace the presets, unknown styles are ignored
+    pub fn set_config(&self, config: Config) {
+        let presets = config
+            .into_iter()
+            .filter_map(|(name, preset)| Some((name.parse().ok()?, preset)))
+            .collect();
+
+        *self.presets.write().unwrap_or_else(|err| err.into_inner()) = presets;
+    }
+
+    pub fn apply(&self, style: Style, enqueue: &mut Enqueue) {
+        let presets = self.presets.read().unwrap_or_else(|err| err.into_inner());
+
+        if let Some(terms) = presets
+            .get(&style)
+            .and_then(|preset| preset.prompt.as_deref())
+        {
+            enqueue.extend_prompt(terms);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        let config = Config::from([
+            (
+                String::from("lego"),
+                Preset {
+                    prompt: Some(String::from("bright colors")),
+                },
+            ),
+            (String::from("pixel"), Preset::default()),
+        ]);
+
+        assert_eq!(problems(&config).len(), 1);
+
+        let presets = Presets::new(config);
+
+        let mut enqueue = Enqueue::from_prompt("a castle");
+        presets.apply(Style::Photo, &mut enqueue);
+        presets.apply(Style::Lego, &mut enqueue);
+
+        let json = serde_json::to_string(&enqueue).unwrap();
+        assert!(json.contains("a castle, bright colors"));
+    }
+}
@@ -0,0 +1,423 @@
+//! Token bucket rate limits and daily quotas, per user and per chat
+
+use std::{
+    fmt,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use teloxide::types::{ChatId, UserId};
+use tokio::sync::Mutex;
+
+use crate::store::Store;
+
+/// Kind of work that is limited separately
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Image,
+    Llm,
+    Tts,
+    /// Fetching web pages or search results
+    Web,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Image => "image",
+            Kind::Llm => "llm",
+            Kind::Tts => "tts",
+            Kind::Web => "web",
+        }
+    }
+
+    /// What the user gets, used in replies
+    fn noun(&self) -> &'static str {
+        match self {
+            Kind::Image => "images",
+            Kind::Llm => "prompts",
+            Kind::Tts => "voice messages",
+            Kind::Web => "web lookups",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    User(UserId),
+    Chat(ChatId),
+}
+
+impl Scope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::User(_) => "user",
+            Scope::Chat(_) => "chat",
+        }
+    }
+
+    pub fn id(&self) -> i64 {
+        match self {
+            Scope::User(user_id) => user_id.0 as i64,
+            Scope::Chat(chat_id) => chat_id.0,
+        }
+    }
+}
+
+/// Limits for a single scope, every limit is optional
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct Limit {
+    /// Requests per minute that are refilled into the bucket
+    pub per_minute: Option<f64>,
+    /// Size of the bucket, defaults to the amount of requests per minute
+    pub burst: Option<f64>,
+    /// Requests per day, resets at midnight UTC
+    pub daily: Option<u32>,
+}
+
+impl Limit {
+    fn problems(&self, name: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if let Some(per_minute) = self.per_minute {
+            if !per_minute.is_finite() || per_minute < 0.0 {
+                problems.push(format!("{name}.per_minute can't be negative"));
+            }
+        }
+
+        if let Some(burst) = self.burst {
+            if !burst.is_finite() || burst < 1.0 {
+                problems.push(format!("{name}.burst must be at least 1"));
+            }
+        }
+
+        problems
+    }
+
+    fn capacity(&self) -> Option<(f64, f64)> {
+        let per_minute = self.per_minute.filter(|rate| *rate > 0.0)?;
+        let burst = self.burst.unwrap_or(per_minute.ceil()).max(1.0);
+
+        Some((per_minute, burst))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct KindLimits {
+    #[serde(default)]
+    pub user: Limit,
+    #[serde(default)]
+    pub chat: Limit,
+}
+
+/// Limits per kind of work, nothing is limited by default
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct Config {
+    #[serde(default)]
+    pub image: KindLimits,
+    #[serde(default)]
+    pub llm: KindLimits,
+    #[serde(default)]
+    pub tts: KindLimits,
+    #[serde(default)]
+    pub web: KindLimits,
+}
+
+impl Config {
+    /// Problems with the configured limits, if any
+    pub fn problems(&self) -> Vec<String> {
+        let kinds = [
+            ("image", self.image),
+            ("llm", self.llm),
+            ("tts", self.tts),
+            ("web", self.web),
+        ];
+
+        kinds
+            .into_iter()
+            .flat_map(|(kind, limits)| {
+                let mut problems = limits.user.problems(&format!("limits.{kind}.user"));
+                problems.extend(limits.chat.problems(&format!("limits.{kind}.chat")));
+                problems
+            })
+            .collect()
+    }
+
+    fn limit(&self, kind: Kind, scope: Scope) -> Limit {
+        let limits = match kind {
+            Kind::Image => self.image,
+            Kind::Llm => self.llm,
+            Kind::Tts => self.tts,
+            Kind::Web => self.web,
+        };
+
+        match scope {
+            Scope::User(_) => limits.user,
+            Scope::Chat(_) => limits.chat,
+        }
+    }
+}
+
+/// Token bucket, refilled over time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Bucket {
+    fn full(burst: f64, now: DateTime<Utc>) -> Self {
+        Self {
+            tokens: burst,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, per_minute: f64, burst: f64, now: DateTime<Utc>) {
+        let elapsed = (now - self.updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+
+        self.tokens = (self.tokens + elapsed * per_minute / 60.0).min(burst);
+        self.updated_at = now;
+    }
+
+    /// Seconds until the next token is available, `None` when one is available now
+    fn wait_time(&self, per_minute: f64) -> Option<u64> {
+        if self.tokens >= 1.0 {
+            return None;
+        }
+
+        Some(((1.0 - self.tokens) * 60.0 / per_minute).ceil() as u64)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Exceeded(Exceeded),
+    #[error("Failed to check the limits {0}")]
+    Store(#[from] anyhow::Error),
+}
+
+/// Why a request was refused
+#[derive(Debug, PartialEq, Eq)]
+pub enum Exceeded {
+    RateLimited {
+        kind: Kind,
+        retry_after_seconds: u64,
+    },
+    Quota {
+        kind: Kind,
+        scope: Scope,
+    },
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exceeded::RateLimited {
+                retry_after_seconds,
+                ..
+            } => write!(f, "Slow down, try again in {retry_after_seconds} seconds"),
+            Exceeded::Quota {
+                kind,
+                scope: Scope::User(_),
+            } => write!(
+                f,
+                "You have no {} left today, your quota resets at {}",
+                kind.noun(),
+                Limiter::RESET_TIME
+            ),
+            Exceeded::Quota {
+                kind,
+                scope: Scope::Chat(_),
+            } => write!(
+                f,
+                "This chat has no {} left today, the quota resets at {}",
+                kind.noun(),
+                Limiter::RESET_TIME
+            ),
+        }
+    }
+}
+
+/// What's left of the daily quota after a request was allowed
+#[derive(Debug, PartialEq, Eq)]
+pub struct Remaining {
+    pub kind: Kind,
+    pub today: u32,
+}
+
+impl fmt::Display for Remaining {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "You have {} {} left today, resets at {}",
+            self.today,
+            self.kind.noun(),
+            Limiter::RESET_TIME
+        )
+    }
+}
+
+/// Checks and consumes the limits, the state is persisted in the store
+#[derive(Clone)]
+pub struct Limiter {
+    config: Arc<RwLock<Config>>,
+    store: Store,
+    /// Serializes the checks, so concurrent requests can't both take the last token
+    lock: Arc<Mutex<()>>,
+}
+
+impl Limiter {
+    pub const RESET_TIME: &'static str = "00:00 UTC";
+
+    pub fn new(config: Config, store: Store) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            store,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Replace the limits, the current usage is kept
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|err| err.into_inner()) = config;
+    }
+
+    /// Check the user and chat limits of every kind and consume them when they all allow
+    /// the request
+    ///
+    /// Returns the lowest remaining daily quota when there is one.
+    pub async fn check(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        kinds: &[Kind],
+    ) -> Result<Option<Remaining>, Error> {
+        let _guard = self.lock.lock().await;
+        let config = *self.config.read().unwrap_or_else(|err| err.into_inner());
+
+        let now = Utc::now();
+        let today = now.date_naive();
+
+        let mut buckets = Vec::with_capacity(2 * kinds.len());
+        let mut usages = Vec::with_capacity(2 * kinds.len());
+
+        for &kind in kinds {
+            for scope in [Scope::User(user_id), Scope::Chat(chat_id)] {
+                let limit = config.limit(kind, scope);
+
+                if let Some((per_minute, burst)) = limit.capacity() {
+                    let mut bucket = self
+                        .store
+                        .bucket(scope, kind)
+                        .await?
+                        .unwrap_or(Bucket::full(burst, now));
+
+                    bucket.refill(per_minute, burst, now);
+
+                    if let Some(retry_after_seconds) = bucket.wait_time(per_minute) {
+                        return Err(Error::Exceeded(Exceeded::RateLimited {
+                            kind,
+                            retry_after_seconds,
+                        }));
+                    }
+
+                    buckets.push((scope, kind, bucket));
+                }
+
+                if let Some(daily) = limit.daily {
+                    let used = self.store.daily_usage(scope, kind, today).await?;
+
+                    if used >= daily {
+                        return Err(Error::Exceeded(Exceeded::Quota { kind, scope }));
+                    }
+
+                    usages.push((scope, kind, daily - used - 1));
+                }
+            }
+        }
+
+        for (scope, kind, mut bucket) in buckets {
+            bucket.tokens -= 1.0;
+            self.store.set_bucket(scope, kind, bucket).await?;
+        }
+
+        for (scope, kind, _) in usages.iter() {
+            self.store
+                .increment_daily_usage(*scope, *kind, today)
+                .await?;
+        }
+
+        let remaining = usages
+            .into_iter()
+            .min_by_key(|(_, _, remaining)| *remaining)
+            .map(|(_, kind, today)| Remaining { kind, today });
+
+        Ok(remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        let now = Utc::now();
+        let mut bucket = Bucket::full(2.0, now);
+
+        bucket.tokens -= 1.0;
+        bucket.tokens -= 1.0;
+        assert_eq!(bucket.wait_time(6.0), Some(10));
+
+        // 6 per minute means a token every 10 seconds
+        bucket.refill(6.0, 2.0, now + chrono::Duration::seconds(5));
+        assert_eq!(bucket.wait_time(6.0), Some(5));
+
+        bucket.refill(6.0, 2.0, now + chrono::Duration::seconds(10));
+        assert_eq!(bucket.wait_time(6.0), None);
+
+        // never exceeds the burst size
+        bucket.refill(6.0, 2.0, now + chrono::Duration::hours(1));
+        assert_eq!(bucket.tokens, 2.0);
+    }
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(Limit::default().capacity(), None);
+
+        let limit = Limit {
+            per_minute: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(limit.capacity(), Some((0.5, 1.0)));
+
+        let limit = Limit {
+            per_minute: Some(2.0),
+            burst: Some(5.0),
+            daily: None,
+        };
+        assert_eq!(limit.capacity(), Some((2.0, 5.0)));
+    }
+
+    #[test]
+    fn test_problems() {
+        assert!(Config::default().problems().is_empty());
+
+        let mut config = Config::default();
+        config.llm.chat.per_minute = Some(-1.0);
+        config.web.user.burst = Some(0.5);
+        // turns the rate limit off
+        config.image.user.per_minute = Some(0.0);
+
+        assert_eq!(
+            config.problems(),
+            vec![
+                "limits.llm.chat.per_minute can't be negative",
+                "limits.web.user.burst must be at least 1",
+            ]
+        );
+    }
+}
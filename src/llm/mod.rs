@@ -0,0 +1,437 @@
+//! One interface over the LLM servers: Ollama, LocalAI and OpenAI compatible ones
+//!
+//! Every LLM feature talks to a [`Router`], which hands requests to the configured
+//! providers in order and moves on to the next one when a provider fails.
+
+use std::{future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+
+pub mod openai;
+pub mod output;
+mod stream;
+
+pub use openai::OpenAi;
+pub use stream::lines;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("LLM request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("invalid LLM response: {0}")]
+    Response(#[from] serde_json::Error),
+    #[error("LLM backend failed: {0}")]
+    Backend(#[from] anyhow::Error),
+    #[error("the LLM returned nothing")]
+    Empty,
+    #[error("no LLM providers are configured")]
+    NoProviders,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Message {
+    pub role: Role,
+    pub content: String,
+}
+
+impl Message {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+    System,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::System => "system",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Role::User),
+            "assistant" => Ok(Role::Assistant),
+            "system" => Ok(Role::System),
+            other => Err(format!("unknown role '{other}'")),
+        }
+    }
+}
+
+/// Chat completion request, providers use their own model and temperature when they're not set
+///
+/// The model is the preferred provider's, the providers that take over use their own.
+#[derive(Clone, Debug, Default)]
+pub struct Chat {
+    pub messages: Vec<Message>,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    pub text: String,
+    pub model: String,
+    /// What a reasoning model thought before answering, once the answer is cleaned
+    pub reasoning: Option<String>,
+}
+
+/// Pieces of the answer, as they're generated
+pub type TokenStream = BoxStream<'static, Result<String, Error>>;
+
+#[async_trait]
+pub trait Provider: Send + Sync {
+    /// Name in the logs and the configuration
+    fn name(&self) -> &'static str;
+
+    /// Model of the requests that don't pick one
+    fn model(&self) -> &str;
+
+    async fn chat(&self, chat: &Chat) -> Result<Completion, Error>;
+
+    /// Completion of a single prompt
+    async fn generate(&self, prompt: &str) -> Result<Completion, Error> {
+        let chat = Chat {
+            messages: vec![Message::user(prompt)],
+            ..Default::default()
+        };
+
+        self.chat(&chat).await
+    }
+
+    async fn stream(&self, chat: &Chat) -> Result<TokenStream, Error>;
+
+    /// One embedding per input
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, Error>;
+
+    /// Tokens in a text, estimated unless the server can count them
+    async fn count_tokens(&self, text: &str) -> Result<usize, Error> {
+        Ok(estimate_tokens(text))
+    }
+}
+
+/// About 4 characters per token for English text
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Which providers to use, in order of preference
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Ollama,
+    LocalAi,
+    OpenAi,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    /// The first configured provider answers, the next one takes over when it fails
+    pub providers: Vec<Kind>,
+    /// OpenAI compatible API, including the version, like `https://api.openai.com/v1`
+    pub openai_url: Option<String>,
+    pub openai_api_key: Option<String>,
+    pub openai_model: String,
+    /// Model for embeddings, on every provider
+    pub embedding_model: String,
+    /// Reasoning and template tokens to remove from the answers
+    pub output: output::Config,
+    /// Answers longer than this many messages are sent as a markdown document, 0 never does
+    pub max_parts: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            providers: vec![Kind::Ollama, Kind::LocalAi, Kind::OpenAi],
+            openai_url: None,
+            openai_api_key: None,
+            openai_model: String::from("gpt-4o-mini"),
+            embedding_model: String::from("nomic-embed-text"),
+            output: output::Config::default(),
+            max_parts: 4,
+        }
+    }
+}
+
+impl Config {
+    /// Problems with the configuration, if any
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.providers.is_empty() {
+            problems.push(String::from("llm.providers needs at least one provider"));
+        }
+        if self.openai_url.is_some() && self.openai_model.trim().is_empty() {
+            problems.push(String::from("llm.openai_model can't be empty"));
+        }
+
+        problems.extend(self.output.problems());
+
+        problems
+    }
+}
+
+/// Hands requests to the providers in order, until one of them succeeds, and cleans the answers
+#[derive(Clone)]
+pub struct Router {
+    providers: Arc<[Arc<dyn Provider>]>,
+    output: Arc<output::Config>,
+}
+
+impl Router {
+    pub fn new(providers: Vec<Arc<dyn Provider>>, output: output::Config) -> Self {
+        Self {
+            providers: Arc::from(providers),
+            output: Arc::new(output),
+        }
+    }
+
+    /// Whether to offer the reasoning the answers left out
+    pub fn shows_reasoning(&self) -> bool {
+        self.output.show_reasoning
+    }
+
+    fn clean(&self, completion: Completion) -> Completion {
+        let cleaned = self.output.clean(&completion.model, &completion.text);
+
+        Completion {
+            text: cleaned.text,
+            reasoning: cleaned.reasoning,
+            ..completion
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.providers
+            .iter()
+            .map(|provider| provider.name())
+            .collect()
+    }
+
+    /// The request for a provider, the model of the chat is only meant for the preferred one
+    fn request<'c>(&self, provider: &dyn Provider, chat: &'c Chat, others: &'c Chat) -> &'c Chat {
+        match self.providers.first() {
+            Some(preferred) if preferred.name() == provider.name() => chat,
+            _ => others,
+        }
+    }
+
+    async fn failover<'a, T, F, Fut>(&'a self, call: F) -> Result<T, Error>
+    where
+        F: Fn(&'a dyn Provider) -> Fut + Send,
+        Fut: Future<Output = Result<T, Error>> + Send,
+    {
+        let mut error = Error::NoProviders;
+
+        for provider in self.providers.iter() {
+            match call(provider.as_ref()).await {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    log::warn!("LLM provider {} failed: {err}", provider.name());
+                    error = err;
+                }
+            }
+        }
+
+        Err(error)
+    }
+}
+
+#[async_trait]
+impl Provider for Router {
+    fn name(&self) -> &'static str {
+        "router"
+    }
+
+    /// Model of the preferred provider
+    fn model(&self) -> &str {
+        self.providers
+            .first()
+            .map_or("", |provider| provider.model())
+    }
+
+    async fn chat(&self, chat: &Chat) -> Result<Completion, Error> {
+        let others = Chat {
+            model: None,
+            ..chat.clone()
+        };
+
+        let completion = self
+            .failover(|provider| provider.chat(self.request(provider, chat, &others)))
+            .await?;
+
+        Ok(self.clean(completion))
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<Completion, Error> {
+        let completion = self.failover(|provider| provider.generate(prompt)).await?;
+
+        Ok(self.clean(completion))
+    }
+
+    /// Only failing to start streaming moves on to the next provider
+    async fn stream(&self, chat: &Chat) -> Result<TokenStream, Error> {
+        let others = Chat {
+            model: None,
+            ..chat.clone()
+        };
+
+        let (tokens, model) = self
+            .failover(|provider| {
+                let chat = self.request(provider, chat, &others);
+                async move {
+                    let model = chat
+                        .model
+                        .as_deref()
+                        .unwrap_or(provider.model())
+                        .to_string();
+                    provider.stream(chat).await.map(|tokens| (tokens, model))
+                }
+            })
+            .await?;
+
+        Ok(self.output.clean_stream(&model, tokens))
+    }
+
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        self.failover(|provider| provider.embed(input)).await
+    }
+
+    async fn count_tokens(&self, text: &str) -> Result<usize, Error> {
+        self.failover(|provider| provider.count_tokens(text)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+
+    struct Fake {
+        name: &'static str,
+        up: bool,
+        calls: AtomicUsize,
+        /// Model of the last request
+        model: Mutex<Option<String>>,
+    }
+
+    impl Fake {
+        fn new(name: &'static str, up: bool) -> Arc<Self> {
+            Arc::new(Self {
+                name,
+                up,
+                calls: AtomicUsize::new(0),
+                model: Mutex::new(None),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Provider for Fake {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn model(&self) -> &str {
+            "fake"
+        }
+
+        async fn chat(&self, chat: &Chat) -> Result<Completion, Error> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            *self.model.lock().unwrap() = chat.model.clone();
+
+            if !self.up {
+                return Err(Error::Empty);
+            }
+
+            Ok(Completion {
+                text: format!("hello from {}<|end|>", self.name),
+                model: String::from("fake"),
+                reasoning: None,
+            })
+        }
+
+        async fn stream(&self, _: &Chat) -> Result<TokenStream, Error> {
+            Err(Error::Empty)
+        }
+
+        async fn embed(&self, _: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+            Err(Error::Empty)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let down = Fake::new("down", false);
+        let up = Fake::new("up", true);
+        let unused = Fake::new("unused", true);
+
+        let router = Router::new(
+            vec![down.clone(), up.clone(), unused.clone()],
+            output::Config::default(),
+        );
+
+        let completion = router.generate("hi").await.unwrap();
+        assert_eq!(completion.text, "hello from up");
+        assert_eq!(down.calls.load(Ordering::Relaxed), 1);
+        assert_eq!(unused.calls.load(Ordering::Relaxed), 0);
+
+        assert!(matches!(
+            router.embed(&[String::from("hi")]).await,
+            Err(Error::Empty)
+        ));
+        assert!(matches!(
+            Router::new(Vec::new(), output::Config::default())
+                .generate("hi")
+                .await,
+            Err(Error::NoProviders)
+        ));
+
+        // the model is only meant for the preferred provider
+        let chat = Chat {
+            messages: vec![Message::user("hi")],
+            model: Some(String::from("big")),
+            temperature: None,
+        };
+        router.chat(&chat).await.unwrap();
+        assert_eq!(down.model.lock().unwrap().as_deref(), Some("big"));
+        assert_eq!(up.model.lock().unwrap().as_deref(), None);
+
+        assert_eq!(router.count_tokens("four").await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,215 @@
+//! OpenAI compatible chat completions, embeddings and streaming
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{lines, Chat, Completion, Error, Message, Provider, TokenStream};
+use crate::logging::Correlate;
+
+#[derive(Clone)]
+pub struct OpenAi {
+    name: &'static str,
+    http_client: reqwest::Client,
+    /// Including the version, like `https://api.openai.com/v1`
+    base_url: Arc<String>,
+    api_key: Option<Arc<String>>,
+    model: Arc<String>,
+    embedding_model: Arc<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Request<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    model: String,
+    choices: Vec<Choice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Choice {
+    message: Message,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chunk {
+    choices: Vec<ChunkChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    delta: Delta,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<Embedding>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Embedding {
+    embedding: Vec<f32>,
+}
+
+impl OpenAi {
+    pub fn new(
+        name: &'static str,
+        http_client: reqwest::Client,
+        base_url: String,
+        api_key: Option<String>,
+        model: String,
+        embedding_model: String,
+    ) -> Self {
+        Self {
+            name,
+            http_client,
+            base_url: Arc::new(base_url.trim_end_matches('/').to_string()),
+            api_key: api_key.map(Arc::new),
+            model: Arc::new(model),
+            embedding_model: Arc::new(embedding_model),
+        }
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .http_client
+            .post(format!("{}/{path}", self.base_url))
+            .correlate();
+
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key.as_str()),
+            None => request,
+        }
+    }
+
+    fn request<'a>(&'a self, chat: &'a Chat, stream: bool) -> Request<'a> {
+        Request {
+            model: chat.model.as_deref().unwrap_or(&self.model),
+            messages: &chat.messages,
+            temperature: chat.temperature,
+            stream,
+        }
+    }
+}
+
+/// Content of a server-sent event with a chunk of the answer
+fn delta(line: &str) -> Result<Option<String>, Error> {
+    let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+        return Ok(None);
+    };
+
+    if data == "[DONE]" {
+        return Ok(None);
+    }
+
+    let chunk: Chunk = serde_json::from_str(data)?;
+
+    Ok(chunk
+        .choices
+        .into_iter()
+        .next()
+        .and_then(|choice| choice.delta.content))
+}
+
+#[async_trait]
+impl Provider for OpenAi {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn chat(&self, chat: &Chat) -> Result<Completion, Error> {
+        let response: Response = self
+            .post("chat/completions")
+            .json(&self.request(chat, false))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let text = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or(Error::Empty)?;
+
+        Ok(Completion {
+            text,
+            model: response.model,
+            reasoning: None,
+        })
+    }
+
+    async fn stream(&self, chat: &Chat) -> Result<TokenStream, Error> {
+        let response = self
+            .post("chat/completions")
+            .json(&self.request(chat, true))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(lines(response, delta))
+    }
+
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        let response: EmbeddingResponse = self
+            .post("embeddings")
+            .json(&EmbeddingRequest {
+                model: &self.embedding_model,
+                input,
+            })
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response
+            .data
+            .into_iter()
+            .map(|embedding| embedding.embedding)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delta() {
+        let line = r#"data: {"id":"1","choices":[{"index":0,"delta":{"content":"Hel"}}]}"#;
+        assert_eq!(delta(line).unwrap(), Some(String::from("Hel")));
+
+        let line = r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant"}}]}"#;
+        assert_eq!(delta(line).unwrap(), None);
+
+        assert_eq!(delta("data: [DONE]").unwrap(), None);
+        assert_eq!(delta(": keep-alive").unwrap(), None);
+        assert!(delta("data: {").is_err());
+    }
+}
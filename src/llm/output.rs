@@ -0,0 +1,341 @@
+//! Cleaning up answers: reasoning blocks and chat template tokens
+//!
+//! What to remove depends on the model family, reasoning models wrap their thoughts in a tag
+//! like `<think>` and some chat templates leak tokens like `<|end|>` into the answer.
+
+use futures::{stream, StreamExt};
+use serde::Deserialize;
+
+use super::{Error, TokenStream};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct Config {
+    /// Offer a button under answers that shows the reasoning they left out
+    pub show_reasoning: bool,
+    /// Every family whose `model` is part of the model name applies
+    pub families: Vec<Family>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct Family {
+    /// Part of the model name, empty for every model
+    pub model: String,
+    /// Name of the tag around the reasoning, `think` for `<think>...</think>`
+    pub reasoning: Option<String>,
+    /// Chat template tokens that leak into the answers
+    #[serde(default)]
+    pub template_tokens: Vec<String>,
+}
+
+impl Family {
+    fn reasoning(model: &str) -> Self {
+        Self {
+            model: model.into(),
+            reasoning: Some(String::from("think")),
+            template_tokens: Vec::new(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            show_reasoning: false,
+            families: vec![
+                Family {
+                    model: String::new(),
+                    reasoning: None,
+                    template_tokens: ["<|assistant|>", "<|end|>", "<|im_end|>", "<|eot_id|>"]
+                        .map(String::from)
+                        .to_vec(),
+                },
+                Family::reasoning("deepseek-r1"),
+                Family::reasoning("qwq"),
+                Family::reasoning("qwen3"),
+            ],
+        }
+    }
+}
+
+impl Config {
+    /// Problems with the configuration, if any
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for family in &self.families {
+            let name = &family.model;
+
+            if family
+                .reasoning
+                .as_ref()
+                .is_some_and(|tag| tag.trim().is_empty())
+            {
+                problems.push(format!(
+                    "llm.output family '{name}' has an empty reasoning tag"
+                ));
+            }
+            if family.template_tokens.iter().any(String::is_empty) {
+                problems.push(format!(
+                    "llm.output family '{name}' has an empty template token"
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// The reasoning tags and template tokens of the families a model belongs to
+    fn markers(&self, model: &str) -> Markers {
+        let families = self
+            .families
+            .iter()
+            .filter(|family| model.contains(family.model.as_str()));
+
+        let mut markers = Markers::default();
+        for family in families {
+            if let (None, Some(tag)) = (&markers.reasoning, &family.reasoning) {
+                markers.reasoning = Some((format!("<{tag}>"), format!("</{tag}>")));
+            }
+            markers
+                .tokens
+                .extend(family.template_tokens.iter().cloned());
+        }
+
+        markers
+    }
+
+    /// Clean a complete answer of a model
+    pub fn clean(&self, model: &str, text: &str) -> Cleaned {
+        let mut cleaner = Cleaner::new(self.markers(model));
+
+        // some chat templates open the reasoning in the prompt, so only the closing tag shows up
+        if let Some((open, close)) = &cleaner.markers.reasoning {
+            if let Some(end) = text.find(close.as_str()) {
+                cleaner.thinking = !text[..end].contains(open.as_str());
+            }
+        }
+
+        let mut answer = cleaner.push(text);
+        let rest = cleaner.finish();
+        answer.push_str(&rest.text);
+
+        match (answer.trim(), rest.reasoning) {
+            // better to show the reasoning than nothing at all
+            ("", Some(reasoning)) => Cleaned {
+                text: reasoning,
+                reasoning: None,
+            },
+            (answer, reasoning) => Cleaned {
+                text: answer.to_string(),
+                reasoning,
+            },
+        }
+    }
+
+    /// Clean an answer as it's streamed
+    pub fn clean_stream(&self, model: &str, tokens: TokenStream) -> TokenStream {
+        let cleaner = Cleaner::new(self.markers(model));
+
+        stream::try_unfold((tokens, Some(cleaner)), next_token).boxed()
+    }
+}
+
+/// The next visible piece of a cleaned stream
+async fn next_token(
+    (mut tokens, mut cleaner): (TokenStream, Option<Cleaner>),
+) -> Result<Option<(String, (TokenStream, Option<Cleaner>))>, Error> {
+    loop {
+        let Some(active) = cleaner.as_mut() else {
+            return Ok(None);
+        };
+
+        let visible = match tokens.next().await {
+            Some(token) => active.push(&token?),
+            None => cleaner.take().map(Cleaner::finish).unwrap_or_default().text,
+        };
+
+        if !visible.is_empty() {
+            return Ok(Some((visible, (tokens, cleaner))));
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cleaned {
+    pub text: String,
+    /// What the model thought before answering
+    pub reasoning: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Markers {
+    /// Opening and closing tag
+    reasoning: Option<(String, String)>,
+    tokens: Vec<String>,
+}
+
+/// Removes the markers from text that arrives in pieces, tags split over pieces included
+#[derive(Debug)]
+struct Cleaner {
+    markers: Markers,
+    /// Text that could be the start of a marker
+    pending: String,
+    thinking: bool,
+    reasoning: String,
+}
+
+impl Cleaner {
+    fn new(markers: Markers) -> Self {
+        Self {
+            markers,
+            pending: String::new(),
+            thinking: false,
+            reasoning: String::new(),
+        }
+    }
+
+    /// Markers to look for, the first one switches between reasoning and answering
+    fn active(&self) -> Vec<&str> {
+        let switch = self.markers.reasoning.as_ref().map(|(open, close)| {
+            if self.thinking {
+                close.as_str()
+            } else {
+                open.as_str()
+            }
+        });
+
+        switch
+            .into_iter()
+            .chain(self.markers.tokens.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// The visible text of the next piece
+    fn push(&mut self, piece: &str) -> String {
+        self.pending.push_str(piece);
+
+        let mut visible = String::new();
+        loop {
+            let pending = std::mem::take(&mut self.pending);
+            let active = self.active();
+            let switch = self.markers.reasoning.is_some();
+
+            let found = active
+                .iter()
+                .enumerate()
+                .filter_map(|(index, marker)| Some((pending.find(marker)?, index, marker.len())))
+                .min();
+
+            match found {
+                Some((start, index, length)) => {
+                    self.emit(&pending[..start], &mut visible);
+                    if switch && index == 0 {
+                        self.thinking = !self.thinking;
+                    }
+                    self.pending = pending[start + length..].to_string();
+                }
+                None => {
+                    let held = held_back(&pending, &self.active());
+                    self.emit(&pending[..pending.len() - held], &mut visible);
+                    self.pending = pending[pending.len() - held..].to_string();
+                    return visible;
+                }
+            }
+        }
+    }
+
+    fn emit(&mut self, text: &str, visible: &mut String) {
+        if self.thinking {
+            self.reasoning.push_str(text);
+        } else {
+            visible.push_str(text);
+        }
+    }
+
+    /// What's left once the answer is complete, an unclosed reasoning block ends with the answer
+    fn finish(mut self) -> Cleaned {
+        let pending = std::mem::take(&mut self.pending);
+        let mut text = String::new();
+        self.emit(&pending, &mut text);
+
+        let reasoning = self.reasoning.trim();
+
+        Cleaned {
+            text,
+            reasoning: (!reasoning.is_empty()).then(|| reasoning.to_string()),
+        }
+    }
+}
+
+/// Length of the end of the text that could be the start of a marker
+fn held_back(text: &str, markers: &[&str]) -> usize {
+    text.char_indices()
+        .map(|(index, _)| &text[index..])
+        .find(|end| markers.iter().any(|marker| marker.starts_with(end)))
+        .map_or(0, str::len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean() {
+        let config = Config::default();
+
+        let cleaned = config.clean(
+            "deepseek-r1:7b",
+            "<think>asldkl</think>the rest of the content",
+        );
+        assert_eq!(cleaned.text, "the rest of the content");
+        assert_eq!(cleaned.reasoning.as_deref(), Some("asldkl"));
+
+        // do it again just to be sure
+        let again = config.clean("deepseek-r1:7b", &cleaned.text);
+        assert_eq!(again.text, "the rest of the content");
+        assert_eq!(again.reasoning, None);
+
+        // without other content the reasoning is the answer
+        let cleaned = config.clean(
+            "deepseek-r1",
+            "<think>some thoughts without a result</think>",
+        );
+        assert_eq!(cleaned.text, "some thoughts without a result");
+        assert_eq!(cleaned.reasoning, None);
+
+        // the opening tag was part of the prompt
+        let cleaned = config.clean("qwq", "hmm, let me see</think>\n\n42");
+        assert_eq!(cleaned.text, "42");
+        assert_eq!(cleaned.reasoning.as_deref(), Some("hmm, let me see"));
+
+        // other models keep their tags
+        let cleaned = config.clean("llama3", "<think>not reasoning</think> answer<|eot_id|>");
+        assert_eq!(cleaned.text, "<think>not reasoning</think> answer");
+
+        let cleaned = config.clean("llama", "<|assistant|>Hello<|end|>");
+        assert_eq!(cleaned.text, "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_clean_stream() {
+        let pieces = [
+            "<th",
+            "ink>plan",
+            "ning</th",
+            "ink>\n\nHel",
+            "lo<|im_",
+            "end|>",
+            " <",
+        ];
+        let tokens = stream::iter(pieces.map(|piece| Ok(piece.to_string()))).boxed();
+
+        let cleaned: Vec<String> = Config::default()
+            .clean_stream("qwen3:8b", tokens)
+            .map(Result::unwrap)
+            .collect()
+            .await;
+
+        assert_eq!(cleaned.concat(), "\n\nHello <");
+    }
+}
@@ -0,0 +1,44 @@
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+
+use super::{Error, TokenStream};
+
+type Body = BoxStream<'static, reqwest::Result<Bytes>>;
+
+/// Split a streamed response into lines, and those into tokens with `parse`
+///
+/// `parse` skips a line by returning `None`, such as the keep-alives of server-sent events.
+pub fn lines(
+    response: reqwest::Response,
+    parse: fn(&str) -> Result<Option<String>, Error>,
+) -> TokenStream {
+    let body: Body = response.bytes_stream().boxed();
+
+    futures::stream::try_unfold((body, Vec::new()), next_line)
+        .try_filter_map(move |line| async move {
+            let line = String::from_utf8_lossy(&line);
+            match line.trim() {
+                "" => Ok(None),
+                line => parse(line),
+            }
+        })
+        .boxed()
+}
+
+/// The next line of the body, the rest of the body and what's left of the last chunk
+async fn next_line(
+    (mut body, mut buffer): (Body, Vec<u8>),
+) -> Result<Option<(Vec<u8>, (Body, Vec<u8>))>, Error> {
+    loop {
+        if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            return Ok(Some((line, (body, buffer))));
+        }
+
+        match body.next().await {
+            Some(chunk) => buffer.extend_from_slice(&chunk?),
+            None if buffer.is_empty() => return Ok(None),
+            None => return Ok(Some((std::mem::take(&mut buffer), (body, buffer)))),
+        }
+    }
+}
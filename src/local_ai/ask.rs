@@ -0,0 +1,96 @@
+//! Chat completions on request, with a model and temperature of choice
+
+use serde::Deserialize;
+
+/// Temperatures users can pick
+const TEMPERATURES: std::ops::RangeInclusive<f32> = 0.0..=2.0;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    /// Models users can pick with `--model`, the first one is the default
+    pub models: Vec<String>,
+    /// Temperature when users don't pick one
+    pub temperature: f32,
+    /// Earlier messages of a thread that are sent along with a reply
+    pub history: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            models: vec![String::from("llama"), String::from("ggml-gpt4all-j.bin")],
+            temperature: 0.7,
+            history: 10,
+        }
+    }
+}
+
+impl Config {
+    /// Problems with the configuration, if any
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.models.is_empty() {
+            problems.push(String::from("ask.models needs at least one model"));
+        }
+        if self.models.iter().any(|model| model.trim().is_empty()) {
+            problems.push(String::from("ask.models can't contain empty names"));
+        }
+        if !TEMPERATURES.contains(&self.temperature) {
+            problems.push(String::from("ask.temperature must be between 0 and 2"));
+        }
+
+        problems
+    }
+
+    /// The model with the given name, case insensitive, or the default model
+    pub fn model(&self, name: Option<&str>) -> Option<&str> {
+        match name {
+            Some(name) => self
+                .models
+                .iter()
+                .find(|model| model.eq_ignore_ascii_case(name))
+                .map(String::as_str),
+            None => self.models.first().map(String::as_str),
+        }
+    }
+
+    /// The given temperature when it's valid, or the default temperature
+    pub fn temperature(&self, temperature: Option<&str>) -> Option<f32> {
+        match temperature {
+            Some(temperature) => temperature
+                .parse()
+                .ok()
+                .filter(|temperature| TEMPERATURES.contains(temperature)),
+            None => Some(self.temperature),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = Config::default();
+
+        assert!(config.problems().is_empty());
+        assert_eq!(config.model(None), Some("llama"));
+        assert_eq!(config.model(Some("LLAMA")), Some("llama"));
+        assert_eq!(config.model(Some("gpt-4")), None);
+
+        assert_eq!(config.temperature(None), Some(0.7));
+        assert_eq!(config.temperature(Some("1.5")), Some(1.5));
+        assert_eq!(config.temperature(Some("3")), None);
+        assert_eq!(config.temperature(Some("hot")), None);
+
+        let config = Config {
+            models: Vec::new(),
+            temperature: -1.0,
+            history: 0,
+        };
+        assert_eq!(config.problems().len(), 2);
+    }
+}
@@ -0,0 +1,143 @@
+//! Spoken conversations: a voice message in, the LLM's answer read out loud
+
+use std::{fmt, time::Instant};
+
+use teloxide::types::ChatId;
+
+use super::{LocalAI, Recording, Request, Voices};
+use crate::{
+    handler::local::{Identifier, ResponseVariant, Update},
+    logging, metrics,
+    scheduler::Ticket,
+    utils::languages::LanguageDetector,
+};
+
+/// Picks the voice that reads an answer, in the language of the answer
+#[derive(Clone)]
+pub struct Speech {
+    pub voices: Voices,
+    pub language: LanguageDetector,
+}
+
+/// The step of a conversation that failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Transcription,
+    Answer,
+    Speech,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Transcription => f.write_str("transcription"),
+            Stage::Answer => f.write_str("answer"),
+            Stage::Speech => f.write_str("speech"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    pub stage: Stage,
+    pub reason: String,
+    /// The written answer, when only reading it out loud failed
+    pub answer: Option<String>,
+}
+
+impl Failure {
+    fn new(stage: Stage, reason: impl ToString) -> Self {
+        Self {
+            stage,
+            reason: reason.to_string(),
+            answer: None,
+        }
+    }
+}
+
+impl LocalAI {
+    /// Transcribe, answer and read out loud, as one job
+    pub fn enqueue_conversation(
+        &self,
+        identifier: Identifier,
+        recording: Recording,
+        ticket: Ticket,
+    ) {
+        let client = self.clone();
+
+        logging::spawn(async move {
+            let _permit = ticket.ready().await;
+
+            let update = match client.converse(identifier.chat_id, recording).await {
+                Ok(response) => Update::Finished {
+                    identifier,
+                    response,
+                },
+                Err(failure) => Update::ConversationFailed {
+                    identifier,
+                    failure,
+                },
+            };
+
+            client.notifier.notify(update).await;
+        });
+    }
+
+    async fn converse(
+        &self,
+        chat_id: ChatId,
+        recording: Recording,
+    ) -> Result<ResponseVariant, Failure> {
+        let message_id = recording.message_id;
+        let user_id = recording.user_id;
+
+        let transcript = self
+            .transcribe(recording)
+            .await
+            .map_err(|err| Failure::new(Stage::Transcription, err))?;
+
+        if transcript.is_empty() {
+            return Ok(ResponseVariant::Transcript {
+                message_id,
+                user_id,
+                text: transcript,
+            });
+        }
+
+        let request =
+            Request::from_prompt(self.prompts.get_prompt(chat_id).await, transcript.clone());
+        let response = self
+            .request_chat(request)
+            .await
+            .map_err(|err| Failure::new(Stage::Answer, err))?;
+        let answer = self
+            .answer(&response)
+            .map(|answer| answer.trim().to_string())
+            .filter(|answer| !answer.is_empty())
+            .ok_or_else(|| Failure::new(Stage::Answer, "empty answer"))?;
+
+        let failed = |reason: String| Failure {
+            stage: Stage::Speech,
+            reason,
+            answer: Some(answer.clone()),
+        };
+
+        let language = self.speech.language.detect_language(&answer);
+        let voice = self
+            .speech
+            .voices
+            .for_language(language)
+            .ok_or_else(|| failed(String::from("no voices")))?;
+
+        let started = Instant::now();
+        let note = self.speak(&answer, &voice).await;
+        metrics::TTS_DURATION.observe(&voice.name, started.elapsed().as_secs_f64());
+
+        Ok(ResponseVariant::Conversation {
+            message_id,
+            user_id,
+            transcript,
+            note: note.map_err(|err| failed(err.to_string()))?,
+        })
+    }
+}
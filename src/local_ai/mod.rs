@@ -1,14 +1,31 @@
 use crate::handler::local::{Identifier, Notifier, ResponseVariant, Update};
+use crate::llm::{output, OpenAi};
+use crate::logging::{self, Correlate};
+use crate::metrics;
+use crate::scheduler::Ticket;
+use crate::utils::{
+    audio::{Pcm, VoiceNote},
+    text,
+};
 use bytes::Bytes;
-use lingua::Language;
 use models::Response;
-use std::sync::Arc;
+use reqwest::multipart::{Form, Part};
+use std::{sync::Arc, time::Instant};
+use teloxide::types::MessageId;
 
+pub mod ask;
+pub mod conversation;
 pub mod models;
 pub mod prompts;
+mod provider;
+pub mod transcription;
+pub mod voices;
 
+pub use conversation::{Speech, Stage};
 pub use models::{Message, Model, Request, Role, TtsRequest};
 pub use prompts::Prompts;
+pub use transcription::{AutoTranscription, Recording};
+pub use voices::{Voice, Voices};
 
 #[derive(Clone)]
 pub struct LocalAI {
@@ -16,90 +33,263 @@ pub struct LocalAI {
     http_client: reqwest::Client,
     notifier: Notifier,
     prompts: Prompts,
+    /// Whisper model for speech to text
+    whisper_model: Arc<String>,
+    speech: Speech,
+    /// The OpenAI compatible API, for the LLM provider
+    llm: OpenAi,
+    /// Cleans the answers of the chat completions
+    output: Arc<output::Config>,
 }
 
 impl LocalAI {
+    /// Characters per TTS request, long texts are read a few sentences at a time
+    const TTS_CHUNK: usize = 250;
+
     pub fn new(
         api_uri: String,
         notifier: Notifier,
         http_client: reqwest::Client,
         prompts: Prompts,
+        whisper_model: String,
+        embedding_model: String,
+        speech: Speech,
     ) -> Self {
+        let llm = OpenAi::new(
+            "local_ai",
+            http_client.clone(),
+            format!("{api_uri}/v1"),
+            None,
+            Model::Llama.name().into(),
+            embedding_model,
+        );
+
         Self {
             api_uri: Arc::new(api_uri),
             http_client,
             notifier,
             prompts,
+            whisper_model: Arc::new(whisper_model),
+            speech,
+            llm,
+            output: Arc::default(),
+        }
+    }
+
+    /// Clean the answers with these rules, instead of the defaults
+    pub fn with_output(self, output: output::Config) -> Self {
+        Self {
+            output: Arc::new(output),
+            ..self
         }
     }
 
-    pub fn enqueue_raw_request(&self, identifier: Identifier, request: Request) {
+    /// The cleaned up answer, without reasoning or template tokens
+    fn answer(&self, response: &Response) -> Option<String> {
+        let message = response.message()?;
+
+        Some(self.output.clean(response.model(), &message).text)
+    }
+
+    /// Run the request once the scheduler allows it
+    pub fn enqueue_raw_request(&self, identifier: Identifier, request: Request, ticket: Ticket) {
         let client = self.clone();
 
-        tokio::task::spawn(async move {
+        logging::spawn(async move {
+            let _permit = ticket.ready().await;
+
             match client.request_chat(request).await {
                 Ok(resp) => {
-                    client.notifier.notify(Update::Finished {
-                        identifier,
-                        response: resp
-                            .message()
-                            .map(ResponseVariant::Text)
-                            .unwrap_or_default(),
-                    });
+                    client
+                        .notifier
+                        .notify(Update::Finished {
+                            identifier,
+                            response: client
+                                .answer(&resp)
+                                .map(ResponseVariant::Text)
+                                .unwrap_or_default(),
+                        })
+                        .await;
+                }
+                Err(err) => {
+                    client
+                        .notifier
+                        .notify(Update::Failed {
+                            identifier,
+                            reason: err.to_string(),
+                        })
+                        .await
                 }
-                Err(err) => client.notifier.notify(Update::Failed {
-                    identifier,
-                    reason: err.to_string(),
-                }),
             };
         });
     }
 
     /// Request using the global system prompt
-    pub async fn enqueue_request(&self, identifier: Identifier, prompt: String, model: Model) {
+    pub async fn enqueue_request(
+        &self,
+        identifier: Identifier,
+        prompt: String,
+        model: Model,
+        ticket: Ticket,
+    ) {
         let request = match model {
             Model::Tldr => Request::tldr(prompt),
             Model::GgmlGpt4all | Model::Llama => {
-                Request::from_prompt(self.prompts.get_prompt().await, prompt)
+                Request::from_prompt(self.prompts.get_prompt(identifier.chat_id).await, prompt)
             }
         };
 
         log::debug!("request: {request:?}");
 
-        self.enqueue_raw_request(identifier, request);
+        self.enqueue_raw_request(identifier, request, ticket);
     }
 
     pub async fn enqueue_tts_request(
         &self,
         identifier: Identifier,
         prompt: String,
-        language: Language,
+        voice: Voice,
+        ticket: Ticket,
     ) {
         let client = self.clone();
 
-        let request = TtsRequest::new(prompt, language);
+        logging::spawn(async move {
+            let _permit = ticket.ready().await;
 
-        tokio::task::spawn(async move {
-            match client.request_tts(request).await {
-                Ok(bytes) => {
-                    client.notifier.notify(Update::Finished {
-                        identifier,
-                        response: ResponseVariant::Audio(bytes),
-                    });
+            let started = Instant::now();
+            let response = client.speak(&prompt, &voice).await;
+            metrics::TTS_DURATION.observe(&voice.name, started.elapsed().as_secs_f64());
+
+            match response {
+                Ok(note) => {
+                    client
+                        .notifier
+                        .notify(Update::Finished {
+                            identifier,
+                            response: ResponseVariant::Voice(note),
+                        })
+                        .await;
+                }
+                Err(err) => {
+                    client
+                        .notifier
+                        .notify(Update::Failed {
+                            identifier,
+                            reason: err.to_string(),
+                        })
+                        .await
                 }
-                Err(err) => client.notifier.notify(Update::Failed {
+            };
+        });
+    }
+
+    pub fn enqueue_transcription(
+        &self,
+        identifier: Identifier,
+        recording: Recording,
+        ticket: Ticket,
+    ) {
+        let client = self.clone();
+
+        logging::spawn(async move {
+            let _permit = ticket.ready().await;
+
+            let message_id = recording.message_id;
+            let user_id = recording.user_id;
+
+            match client.transcribe(recording).await {
+                Ok(text) => {
+                    client
+                        .notifier
+                        .notify(Update::Finished {
+                            identifier,
+                            response: ResponseVariant::Transcript {
+                                message_id,
+                                user_id,
+                                text,
+                            },
+                        })
+                        .await;
+                }
+                Err(err) => {
+                    client
+                        .notifier
+                        .notify(Update::Failed {
+                            identifier,
+                            reason: err.to_string(),
+                        })
+                        .await
+                }
+            };
+        });
+    }
+
+    /// Chat completion that continues a thread, `parent` is the message the prompt replies to
+    pub fn enqueue_ask(
+        &self,
+        identifier: Identifier,
+        request: Request,
+        parent: Option<MessageId>,
+        ticket: Ticket,
+    ) {
+        let client = self.clone();
+
+        logging::spawn(async move {
+            let _permit = ticket.ready().await;
+
+            let prompt = request.prompt().unwrap_or_default().to_string();
+
+            let update = match client.request_chat(request).await {
+                Ok(response) => Update::Finished {
+                    identifier,
+                    response: client
+                        .answer(&response)
+                        .map(|text| ResponseVariant::Answer {
+                            prompt,
+                            parent,
+                            text,
+                        })
+                        .unwrap_or_default(),
+                },
+                Err(err) => Update::Failed {
                     identifier,
                     reason: err.to_string(),
-                }),
+                },
             };
+
+            client.notifier.notify(update).await;
         });
     }
 
+    /// Speech to text through the Whisper compatible endpoint
+    pub async fn transcribe(&self, recording: Recording) -> Result<String, anyhow::Error> {
+        let form = Form::new()
+            .text("model", self.whisper_model.to_string())
+            .part(
+                "file",
+                Part::bytes(recording.audio).file_name(recording.file_name),
+            );
+
+        let transcription: transcription::Transcription = self
+            .http_client
+            .post(format!("{}/v1/audio/transcriptions", self.api_uri))
+            .multipart(form)
+            .correlate()
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(transcription.text.trim().to_string())
+    }
+
     async fn request_chat(&self, request: Request) -> Result<Response, anyhow::Error> {
         let res = self
             .http_client
             .post(format!("{}/v1/chat/completions", self.api_uri))
             .json(&request)
+            .correlate()
             .send()
             .await?
             .text()
@@ -110,12 +300,28 @@ impl LocalAI {
         serde_json::from_str(res.as_str()).map_err(Into::into)
     }
 
+    /// Read the text a few sentences at a time and join the audio into a voice note
+    async fn speak(&self, text: &str, voice: &Voice) -> Result<VoiceNote, anyhow::Error> {
+        let mut pcm = Pcm::default();
+
+        for chunk in text::split(text, Self::TTS_CHUNK) {
+            let wav = self.request_tts(TtsRequest::new(chunk, voice)).await?;
+            pcm.append(Pcm::from_wav(&wav)?);
+        }
+
+        let note = tokio::task::spawn_blocking(move || VoiceNote::try_from(pcm)).await??;
+
+        Ok(note)
+    }
+
     async fn request_tts(&self, request: TtsRequest) -> Result<Bytes, anyhow::Error> {
         self.http_client
             .post(format!("{}/tts", self.api_uri))
             .json(&request)
+            .correlate()
             .send()
             .await?
+            .error_for_status()?
             .bytes()
             .await
             .map_err(Into::into)
@@ -1,12 +1,14 @@
 #![allow(unused)]
 
-use lingua::Language;
 use serde::{Deserialize, Serialize};
 
+use super::voices::Voice;
+pub use crate::llm::{Message, Role};
+
 /// Text generation request
 #[derive(Clone, Debug, Serialize)]
 pub struct Request {
-    pub model: Model,
+    pub model: String,
     pub messages: Vec<Message>,
     pub temperature: f32,
 }
@@ -14,7 +16,7 @@ pub struct Request {
 impl Request {
     pub fn from_prompt(system: String, user: String) -> Self {
         Self {
-            model: Model::Llama,
+            model: Model::Llama.name().into(),
             messages: vec![
                 Message {
                     role: Role::System,
@@ -31,7 +33,7 @@ impl Request {
 
     pub fn tldr(content: String) -> Self {
         Self {
-            model: Model::Tldr,
+            model: Model::Tldr.name().into(),
             messages: vec![Message {
                 role: Role::User,
                 content,
@@ -39,25 +41,33 @@ impl Request {
             temperature: 0.7,
         }
     }
+
+    /// The user's prompt, the last message
+    pub fn prompt(&self) -> Option<&str> {
+        self.messages
+            .last()
+            .filter(|message| matches!(message.role, Role::User))
+            .map(|message| message.content.as_str())
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Response {
     object: String,
-    model: Model,
+    model: String,
     choices: Vec<Choice>,
     usage: Usage,
 }
 
 impl Response {
     pub fn message(&self) -> Option<String> {
-        self.choices.first().map(|choice| {
-            choice
-                .message
-                .content
-                .replace("<|assistant|>", "")
-                .replace("<|end|>", "")
-        })
+        self.choices
+            .first()
+            .map(|choice| choice.message.content.clone())
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
     }
 }
 
@@ -74,18 +84,15 @@ pub enum Model {
     Tldr,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Message {
-    pub role: Role,
-    pub content: String,
-}
-
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum Role {
-    User,
-    Assistant,
-    System,
+impl Model {
+    /// Name of the model in LocalAI
+    pub fn name(&self) -> &'static str {
+        match self {
+            Model::GgmlGpt4all => "ggml-gpt4all-j.bin",
+            Model::Llama => "llama",
+            Model::Tldr => "tldr",
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -105,17 +112,14 @@ struct Usage {
 /// Text to speech
 #[derive(Debug, Serialize)]
 pub struct TtsRequest {
-    model: TtsModel,
+    model: String,
     backend: TtsBackend,
     input: String,
-}
-
-#[derive(Debug, Serialize)]
-pub enum TtsModel {
-    #[serde(rename = "en-us-libritts-high.onnx")]
-    EnUsLibritts,
-    #[serde(rename = "nl-nathalie-x-low.onnx")]
-    NlNathalie,
+    /// Speaker id, for models with multiple speakers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    voice: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed: Option<f32>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize)]
@@ -125,20 +129,13 @@ pub enum TtsBackend {
 }
 
 impl TtsRequest {
-    pub fn new(mut input: String, language: Language) -> Self {
-        let model = match language {
-            Language::Dutch => TtsModel::NlNathalie,
-            Language::English => TtsModel::EnUsLibritts,
-            Language::French => {
-                input = String::from("wablieft?");
-                TtsModel::NlNathalie
-            }
-        };
-
+    pub fn new(input: String, voice: &Voice) -> Self {
         Self {
-            model,
+            model: voice.model.clone(),
             backend: TtsBackend::Piper,
             input,
+            voice: voice.speaker.map(|speaker| speaker.to_string()),
+            speed: voice.speed,
         }
     }
 }
@@ -0,0 +1,28 @@
+use async_trait::async_trait;
+
+use super::LocalAI;
+use crate::llm::{Chat, Completion, Error, Provider, TokenStream};
+
+/// LocalAI speaks the OpenAI API
+#[async_trait]
+impl Provider for LocalAI {
+    fn name(&self) -> &'static str {
+        "local_ai"
+    }
+
+    fn model(&self) -> &str {
+        self.llm.model()
+    }
+
+    async fn chat(&self, chat: &Chat) -> Result<Completion, Error> {
+        self.llm.chat(chat).await
+    }
+
+    async fn stream(&self, chat: &Chat) -> Result<TokenStream, Error> {
+        self.llm.stream(chat).await
+    }
+
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, Error> {
+        self.llm.embed(input).await
+    }
+}
@@ -0,0 +1,78 @@
+//! Speech to text, on request or for every voice message in a chat
+
+use std::{collections::HashSet, fmt, sync::Arc};
+
+use serde::Deserialize;
+use teloxide::types::{ChatId, MessageId, UserId};
+use tokio::sync::RwLock;
+
+use crate::store::Store;
+
+/// A voice message, audio file or video note to transcribe
+pub struct Recording {
+    /// The message with the recording, its transcript is stored as its text
+    pub message_id: MessageId,
+    pub user_id: UserId,
+    pub file_name: String,
+    pub audio: Vec<u8>,
+}
+
+impl fmt::Debug for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Recording({}-{}, {}, {} bytes)",
+            self.user_id,
+            self.message_id,
+            self.file_name,
+            self.audio.len()
+        )
+    }
+}
+
+/// Response of the Whisper compatible transcription endpoint
+#[derive(Debug, Deserialize)]
+pub struct Transcription {
+    pub text: String,
+}
+
+/// Chats whose voice messages are transcribed without asking, persisted in the store
+#[derive(Clone)]
+pub struct AutoTranscription {
+    chats: Arc<RwLock<HashSet<ChatId>>>,
+    store: Store,
+    /// Whether LocalAI is configured
+    available: bool,
+}
+
+impl AutoTranscription {
+    pub async fn load(store: Store, available: bool) -> Result<Self, anyhow::Error> {
+        let chats = store.auto_transcribed_chats().await?.into_iter().collect();
+
+        Ok(Self {
+            chats: Arc::new(RwLock::new(chats)),
+            store,
+            available,
+        })
+    }
+
+    pub fn available(&self) -> bool {
+        self.available
+    }
+
+    pub async fn enabled(&self, chat_id: ChatId) -> bool {
+        self.available && self.chats.read().await.contains(&chat_id)
+    }
+
+    pub async fn set(&self, chat_id: ChatId, enabled: bool) -> Result<(), anyhow::Error> {
+        self.store.set_auto_transcription(chat_id, enabled).await?;
+
+        if enabled {
+            self.chats.write().await.insert(chat_id);
+        } else {
+            self.chats.write().await.remove(&chat_id);
+        }
+
+        Ok(())
+    }
+}
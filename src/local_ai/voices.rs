@@ -0,0 +1,207 @@
+//! Piper voices for text to speech, configurable and reloadable
+
+use std::sync::{Arc, RwLock};
+
+use lingua::Language;
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Voice {
+    /// What users pass to `/say --voice`
+    pub name: String,
+    /// Piper model, such as `nl-nathalie-x-low.onnx`
+    pub model: String,
+    /// ISO 639-1 code of the language the voice speaks
+    pub language: String,
+    /// Speaker of a model with multiple speakers
+    pub speaker: Option<u32>,
+    /// Speaking rate, 1.0 is the speed of the model
+    pub speed: Option<f32>,
+}
+
+impl Voice {
+    fn new(name: &str, model: &str, language: &str) -> Self {
+        Self {
+            name: name.into(),
+            model: model.into(),
+            language: language.into(),
+            speaker: None,
+            speed: None,
+        }
+    }
+}
+
+/// The voices, the first voice of a language is used when users don't pick one
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Config {
+    pub voices: Vec<Voice>,
+    /// Voice for text in a language without a voice
+    pub fallback: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            voices: vec![
+                Voice::new("nathalie", "nl-nathalie-x-low.onnx", "nl"),
+                Voice::new("libritts", "en-us-libritts-high.onnx", "en"),
+            ],
+            fallback: String::from("libritts"),
+        }
+    }
+}
+
+impl Config {
+    /// Problems with the configuration, if any
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.voices.is_empty() {
+            problems.push(String::from("tts.voices needs at least one voice"));
+        }
+
+        for (index, voice) in self.voices.iter().enumerate() {
+            if voice.name.trim().is_empty() || voice.name.contains(char::is_whitespace) {
+                problems.push(format!("tts.voices[{index}] needs a name without spaces"));
+            }
+            if self.voices[..index]
+                .iter()
+                .any(|other| other.name == voice.name)
+            {
+                problems.push(format!("tts.voices: '{}' is used twice", voice.name));
+            }
+            if voice.model.trim().is_empty() {
+                problems.push(format!("tts.voices.{}: model can't be empty", voice.name));
+            }
+            if voice.language.len() != 2 || !voice.language.chars().all(|c| c.is_ascii_lowercase())
+            {
+                problems.push(format!(
+                    "tts.voices.{}: language must be an ISO 639-1 code, like nl",
+                    voice.name
+                ));
+            }
+            if voice
+                .speed
+                .is_some_and(|speed| !speed.is_finite() || speed <= 0.0)
+            {
+                problems.push(format!("tts.voices.{}: speed must be positive", voice.name));
+            }
+        }
+
+        if !self.voices.iter().any(|voice| voice.name == self.fallback) {
+            problems.push(format!(
+                "tts.fallback: there's no voice '{}'",
+                self.fallback
+            ));
+        }
+
+        problems
+    }
+}
+
+#[derive(Clone)]
+pub struct Voices {
+    config: Arc<RwLock<Config>>,
+}
+
+impl Voices {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+        }
+    }
+
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|err| err.into_inner()) = config;
+    }
+
+    fn config(&self) -> std::sync::RwLockReadGuard<'_, Config> {
+        self.config.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// The voice with the given name, case insensitive
+    pub fn named(&self, name: &str) -> Option<Voice> {
+        self.config()
+            .voices
+            .iter()
+            .find(|voice| voice.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// The first voice that speaks the language, or the fallback voice
+    pub fn for_language(&self, language: Option<Language>) -> Option<Voice> {
+        let config = self.config();
+        let code = language.map(|language| language.iso_code_639_1().to_string());
+
+        config
+            .voices
+            .iter()
+            .find(|voice| Some(&voice.language) == code.as_ref())
+            .or_else(|| {
+                config
+                    .voices
+                    .iter()
+                    .find(|voice| voice.name == config.fallback)
+            })
+            .or_else(|| config.voices.first())
+            .cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.config()
+            .voices
+            .iter()
+            .map(|voice| voice.name.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voices() {
+        let mut config = Config::default();
+        config.voices.push(Voice {
+            speaker: Some(3),
+            ..Voice::new("libritts-3", "en-us-libritts-high.onnx", "en")
+        });
+
+        assert!(config.problems().is_empty());
+
+        let voices = Voices::new(config);
+
+        assert_eq!(voices.named("Nathalie").unwrap().language, "nl");
+        assert_eq!(voices.named("libritts-3").unwrap().speaker, Some(3));
+        assert_eq!(voices.named("bob"), None);
+
+        let english = voices.for_language(Some(Language::English)).unwrap();
+        assert_eq!(english.name, "libritts");
+
+        let dutch = voices.for_language(Some(Language::Dutch)).unwrap();
+        assert_eq!(dutch.name, "nathalie");
+
+        // no french voice, so the text is read by the fallback voice
+        let french = voices.for_language(Some(Language::French)).unwrap();
+        assert_eq!(french.name, "libritts");
+        assert_eq!(voices.for_language(None).unwrap().name, "libritts");
+    }
+
+    #[test]
+    fn test_problems() {
+        let config = Config {
+            voices: vec![
+                Voice::new("nathalie", "nl-nathalie-x-low.onnx", "dutch"),
+                Voice {
+                    speed: Some(0.0),
+                    ..Voice::new("nathalie", "", "nl")
+                },
+            ],
+            fallback: String::from("bob"),
+        };
+
+        assert_eq!(config.problems().len(), 5);
+    }
+}
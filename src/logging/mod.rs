@@ -0,0 +1,142 @@
+//! Log setup and correlation ids
+//!
+//! Every command gets a [`CorrelationId`] that's attached to all logs written while
+//! handling it, including in the backend handlers and spawned tasks, and is sent along
+//! with backend HTTP requests.
+
+use std::{fmt, future::Future, io::Write};
+
+use serde::Deserialize;
+
+/// Header carrying the correlation id on backend requests
+const HEADER: &str = "X-Correlation-Id";
+
+tokio::task_local! {
+    static CORRELATION_ID: CorrelationId;
+}
+
+/// Short id that ties together everything that happens for a single command
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CorrelationId(u32);
+
+impl CorrelationId {
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}", self.0)
+    }
+}
+
+impl fmt::Debug for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CorrelationId({self})")
+    }
+}
+
+/// Correlation id of the command that's currently being handled
+pub fn current() -> Option<CorrelationId> {
+    CORRELATION_ID.try_with(|id| *id).ok()
+}
+
+/// Run the future with the correlation id attached to its logs
+pub async fn scope<F: Future>(id: Option<CorrelationId>, future: F) -> F::Output {
+    match id {
+        Some(id) => CORRELATION_ID.scope(id, future).await,
+        None => future.await,
+    }
+}
+
+/// Spawn a task that keeps the current correlation id
+pub fn spawn<F>(future: F) -> tokio::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::spawn(scope(current(), future))
+}
+
+/// Send the current correlation id along with backend requests
+pub trait Correlate {
+    fn correlate(self) -> Self;
+}
+
+impl Correlate for reqwest::RequestBuilder {
+    fn correlate(self) -> Self {
+        match current() {
+            Some(id) => self.header(HEADER, id.to_string()),
+            None => self,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Initialise the logger, filtered through `RUST_LOG`
+pub fn init(format: Format) {
+    env_logger::Builder::from_default_env()
+        .format(move |buf, record| {
+            let timestamp = buf.timestamp();
+            let correlation_id = current();
+
+            match format {
+                Format::Text => {
+                    let correlation_id = correlation_id
+                        .map(|id| format!(" {id}"))
+                        .unwrap_or_default();
+
+                    writeln!(
+                        buf,
+                        "[{timestamp} {:<5} {}{correlation_id}] {}",
+                        record.level(),
+                        record.target(),
+                        record.args()
+                    )
+                }
+                Format::Json => {
+                    let line = serde_json::json!({
+                        "timestamp": timestamp.to_string(),
+                        "level": record.level().as_str(),
+                        "target": record.target(),
+                        "correlation_id": correlation_id.map(|id| id.to_string()),
+                        "message": record.args().to_string(),
+                    });
+
+                    writeln!(buf, "{line}")
+                }
+            }
+        })
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scope() {
+        let id = CorrelationId(0xbeef);
+        assert_eq!(id.to_string(), "0000beef");
+
+        assert_eq!(current(), None);
+        assert_eq!(scope(Some(id), async { current() }).await, Some(id));
+        assert_eq!(scope(None, async { current() }).await, None);
+
+        let spawned = scope(Some(id), async {
+            spawn(async { current() }).await.unwrap()
+        })
+        .await;
+        assert_eq!(spawned, Some(id));
+    }
+}
@@ -1,22 +1,31 @@
-use std::num::NonZeroUsize;
+use std::{net::SocketAddr, num::NonZeroUsize};
 
-use config::Config;
 use serde::Deserialize;
 use teloxide::types::UserId;
 
 pub mod handler;
+pub mod health;
+pub mod i18n;
 pub mod invoke_ai;
+pub mod limits;
+pub mod llm;
 pub mod local_ai;
+pub mod logging;
+pub mod metrics;
 pub mod ollama;
+pub mod roles;
+pub mod rules;
+pub mod scheduler;
+pub mod settings;
 pub mod store;
 pub mod telegram;
 pub mod utils;
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct AppConfig {
-    invoke_ai_url: String,
-    local_ai_url: String,
-    ollama_url: String,
+    invoke_ai_url: Option<String>,
+    local_ai_url: Option<String>,
+    ollama_url: Option<String>,
     teloxide_token: String,
     telegram_admin_user_id: Option<UserId>,
     max_in_progress: Option<NonZeroUsize>,
@@ -25,22 +34,50 @@ pub struct AppConfig {
     enable_french_detection: bool,
     #[serde(default)]
     ollama_model: ollama::Model,
-    searxng_url: String,
-    fact_check_path: String,
+    searxng_url: Option<String>,
+    fact_check_path: Option<String>,
+    #[serde(default)]
+    auto_grant_chat_admins: bool,
+    min_role_image: Option<roles::Role>,
+    min_role_llm: Option<roles::Role>,
+    min_role_tts: Option<roles::Role>,
+    min_role_admin: Option<roles::Role>,
+    #[serde(default)]
+    limits: limits::Config,
+    #[serde(default)]
+    prompts: local_ai::prompts::Config,
+    #[serde(default)]
+    presets: invoke_ai::presets::Config,
+    /// Voices for text to speech
+    #[serde(default)]
+    tts: local_ai::voices::Config,
+    /// Models and temperature of `/ask`
+    #[serde(default)]
+    ask: local_ai::ask::Config,
+    /// Which LLM servers answer, and in what order
+    #[serde(default)]
+    llm: llm::Config,
+    /// Whisper model for speech to text, defaults to `whisper-1`
+    whisper_model: Option<String>,
+    #[serde(default)]
+    scheduler: scheduler::Config,
+    channel_capacity: Option<NonZeroUsize>,
+    /// Serve Prometheus metrics on this address
+    metrics_address: Option<SocketAddr>,
+    #[serde(default)]
+    log_format: logging::Format,
+    /// Language of the replies in chats without a language, to users without a known language
+    #[serde(default)]
+    language: i18n::Language,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     dotenv::dotenv().ok();
 
-    env_logger::init();
-
-    log::info!("Loading config...");
-    let config = Config::builder()
-        .add_source(config::Environment::with_prefix("app").try_parsing(true))
-        .build()?;
+    let config = settings::load()?;
 
-    let config: AppConfig = config.try_deserialize()?;
+    logging::init(config.log_format);
 
     log::info!("Initializing...");
     handler::Handler::dispatch(config).await?;
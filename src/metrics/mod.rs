@@ -0,0 +1,400 @@
+//! Prometheus metrics, served in the text exposition format when enabled
+//!
+//! Counters and histograms are global so every module can record them without threading
+//! a registry through, gauges are read from their source when scraped.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    scheduler::{Backend, Scheduler},
+    store::Store,
+};
+
+/// Buckets in seconds, from quick LLM replies to slow image batches
+const DURATION_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+pub static COMMANDS: Counter = Counter::new(
+    "bot_commands_total",
+    "Commands received, per command",
+    "command",
+);
+pub static JOBS_ENQUEUED: Counter = Counter::new(
+    "bot_jobs_enqueued_total",
+    "Jobs accepted by the scheduler, per backend",
+    "backend",
+);
+pub static JOBS_COMPLETED: Counter = Counter::new(
+    "bot_jobs_completed_total",
+    "Jobs that finished successfully, per backend",
+    "backend",
+);
+pub static JOBS_FAILED: Counter = Counter::new(
+    "bot_jobs_failed_total",
+    "Jobs that failed, per backend",
+    "backend",
+);
+pub static TELEGRAM_ERRORS: Counter = Counter::new(
+    "bot_telegram_api_errors_total",
+    "Failed Telegram API requests, per source",
+    "source",
+);
+
+pub static JOB_DURATION: Histogram = Histogram::new(
+    "bot_job_duration_seconds",
+    "Time jobs hold a scheduler slot, per backend",
+    "backend",
+);
+pub static INVOKE_AI_BATCH_DURATION: Histogram = Histogram::new(
+    "bot_invoke_ai_batch_duration_seconds",
+    "Time between enqueueing an InvokeAI batch and receiving the image",
+    "",
+);
+pub static OLLAMA_TOTAL_DURATION: Histogram = Histogram::new(
+    "bot_ollama_total_duration_seconds",
+    "Generation time reported by Ollama, per model",
+    "model",
+);
+pub static TTS_DURATION: Histogram = Histogram::new(
+    "bot_tts_duration_seconds",
+    "Time spent generating speech, per voice",
+    "voice",
+);
+
+/// Escape a label value for the exposition format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn labels(name: &str, value: &str) -> String {
+    if name.is_empty() {
+        String::new()
+    } else {
+        format!("{{{name}=\"{}\"}}", escape(value))
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Counter with a single label
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str, label: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            label,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, label: &str) {
+        let mut values = self.values.lock().unwrap_or_else(|err| err.into_inner());
+
+        match values.get_mut(label) {
+            Some(value) => *value += 1,
+            None => {
+                values.insert(label.to_string(), 1);
+            }
+        }
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "counter");
+
+        let values = self.values.lock().unwrap_or_else(|err| err.into_inner());
+        for (label, value) in values.iter() {
+            let _ = writeln!(out, "{}{} {value}", self.name, labels(self.label, label));
+        }
+    }
+}
+
+#[derive(Default)]
+struct Observations {
+    /// Observations per bucket, not cumulative
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Histogram in seconds with a single label, an empty label name means no label
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: Mutex<BTreeMap<String, Observations>>,
+}
+
+impl Histogram {
+    pub const fn new(name: &'static str, help: &'static str, label: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            label,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, label: &str, seconds: f64) {
+        let mut values = self.values.lock().unwrap_or_else(|err| err.into_inner());
+        let observations = values.entry(label.to_string()).or_default();
+
+        if observations.buckets.is_empty() {
+            observations.buckets = vec![0; DURATION_BUCKETS.len()];
+        }
+
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|le| seconds <= *le) {
+            observations.buckets[bucket] += 1;
+        }
+
+        observations.sum += seconds;
+        observations.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        header(out, self.name, self.help, "histogram");
+
+        let values = self.values.lock().unwrap_or_else(|err| err.into_inner());
+        for (label, observations) in values.iter() {
+            let prefix = if self.label.is_empty() {
+                String::new()
+            } else {
+                format!("{}=\"{}\",", self.label, escape(label))
+            };
+
+            let mut cumulative = 0;
+            for (le, count) in DURATION_BUCKETS.iter().zip(&observations.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{prefix}le=\"{le}\"}} {cumulative}",
+                    self.name
+                );
+            }
+
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{prefix}le=\"+Inf\"}} {}",
+                self.name, observations.count
+            );
+
+            let labels = labels(self.label, label);
+            let _ = writeln!(out, "{}_sum{labels} {}", self.name, observations.sum);
+            let _ = writeln!(out, "{}_count{labels} {}", self.name, observations.count);
+        }
+    }
+}
+
+/// Depth of a handler's update queue
+pub type QueueDepth = Box<dyn Fn() -> usize + Send + Sync>;
+
+/// Queue depths of the handlers that are running
+pub type Queues = Arc<[(Backend, QueueDepth)]>;
+
+/// Time a client gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the metrics over HTTP
+#[derive(Clone)]
+pub struct Exporter {
+    scheduler: Scheduler,
+    queues: Queues,
+    store: Store,
+}
+
+impl Exporter {
+    pub fn new(scheduler: Scheduler, queues: Queues, store: Store) -> Self {
+        Self {
+            scheduler,
+            queues,
+            store,
+        }
+    }
+
+    async fn render(&self) -> String {
+        let mut out = String::new();
+
+        for counter in [
+            &COMMANDS,
+            &JOBS_ENQUEUED,
+            &JOBS_COMPLETED,
+            &JOBS_FAILED,
+            &TELEGRAM_ERRORS,
+        ] {
+            counter.render(&mut out);
+        }
+
+        for histogram in [
+            &JOB_DURATION,
+            &INVOKE_AI_BATCH_DURATION,
+            &OLLAMA_TOTAL_DURATION,
+            &TTS_DURATION,
+        ] {
+            histogram.render(&mut out);
+        }
+
+        header(
+            &mut out,
+            "bot_jobs_running",
+            "Jobs holding a scheduler slot, per backend",
+            "gauge",
+        );
+        for (backend, _) in self.queues.iter() {
+            let (running, _) = self.scheduler.load(*backend);
+            let labels = labels("backend", backend.as_str());
+            let _ = writeln!(out, "bot_jobs_running{labels} {running}");
+        }
+
+        header(
+            &mut out,
+            "bot_jobs_waiting",
+            "Jobs waiting for a scheduler slot, per backend",
+            "gauge",
+        );
+        for (backend, _) in self.queues.iter() {
+            let (_, waiting) = self.scheduler.load(*backend);
+            let labels = labels("backend", backend.as_str());
+            let _ = writeln!(out, "bot_jobs_waiting{labels} {waiting}");
+        }
+
+        header(
+            &mut out,
+            "bot_handler_queue_depth",
+            "Updates waiting to be handled, per handler",
+            "gauge",
+        );
+        for (backend, depth) in self.queues.iter() {
+            let labels = labels("backend", backend.as_str());
+            let _ = writeln!(out, "bot_handler_queue_depth{labels} {}", depth());
+        }
+
+        match self.store.message_count().await {
+            Ok(count) => {
+                header(
+                    &mut out,
+                    "bot_stored_messages",
+                    "Chat messages in the store",
+                    "gauge",
+                );
+                let _ = writeln!(out, "bot_stored_messages {count}");
+            }
+            Err(err) => log::error!("failed to count the stored messages: {err}"),
+        }
+
+        out
+    }
+
+    /// Serve `GET /metrics` on the given address
+    pub async fn serve(self, address: SocketAddr) {
+        let listener = match TcpListener::bind(address).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                log::error!("failed to serve metrics on {address}: {err}");
+                return;
+            }
+        };
+
+        log::info!("Serving metrics on http://{address}/metrics");
+
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    log::warn!("failed to accept metrics connection: {err}");
+                    continue;
+                }
+            };
+
+            // a slow client shouldn't hold up the next scrape
+            let exporter = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = exporter.respond(stream).await {
+                    log::warn!("failed to serve metrics: {err}");
+                }
+            });
+        }
+    }
+
+    async fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        let mut buffer = [0; 1024];
+        let read = tokio::time::timeout(READ_TIMEOUT, stream.read(&mut buffer))
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        let request = String::from_utf8_lossy(&buffer[..read]);
+
+        let response = if request.starts_with("GET /metrics ") {
+            let body = self.render().await;
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+        } else {
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+        };
+
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter() {
+        let counter = Counter::new("test_total", "Test", "command");
+        counter.inc("hey");
+        counter.inc("hey");
+        counter.inc("say \"hi\"");
+
+        let mut out = String::new();
+        counter.render(&mut out);
+
+        assert_eq!(
+            out,
+            "# HELP test_total Test\n# TYPE test_total counter\ntest_total{command=\"hey\"} 2\ntest_total{command=\"say \\\"hi\\\"\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new("test_seconds", "Test", "");
+        histogram.observe("", 0.2);
+        histogram.observe("", 7.0);
+        histogram.observe("", 1000.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out);
+
+        assert!(out.contains("test_seconds_bucket{le=\"0.1\"} 0\n"));
+        assert!(out.contains("test_seconds_bucket{le=\"0.5\"} 1\n"));
+        assert!(out.contains("test_seconds_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("test_seconds_bucket{le=\"300\"} 2\n"));
+        assert!(out.contains("test_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("test_seconds_sum 1007.2\n"));
+        assert!(out.contains("test_seconds_count 3\n"));
+    }
+}
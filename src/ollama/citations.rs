@@ -0,0 +1,148 @@
+//! Citations of `/deepsearch` answers, checked against the search results the model got
+
+use std::collections::BTreeSet;
+
+use crate::utils::searxng::SearchResult;
+
+/// Link the `[n]` citations to the search results, drop citations of results that don't exist
+/// and list the cited results under the answer
+///
+/// The model's own list of sources is replaced, it can get the URLs wrong.
+pub fn link(answer: &str, sources: &[SearchResult], heading: &str) -> String {
+    let body: Vec<&str> = answer
+        .lines()
+        .filter(|line| !is_source_line(line))
+        .collect();
+    let body = body.join("\n");
+
+    let mut cited = BTreeSet::new();
+    let mut linked = String::with_capacity(body.len());
+    let mut rest = body.as_str();
+
+    while let Some(start) = rest.find('[') {
+        linked.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some((numbers, length)) = citation(rest) else {
+            linked.push('[');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let known: Vec<usize> = numbers
+            .into_iter()
+            .filter(|number| (1..=sources.len()).contains(number))
+            .collect();
+
+        if known.is_empty() {
+            // no space left in front of the punctuation
+            linked.truncate(linked.trim_end().len());
+        }
+
+        for number in known {
+            linked.push_str(&format!("[[{number}]]({})", sources[number - 1].url));
+            cited.insert(number);
+        }
+
+        rest = &rest[length..];
+    }
+    linked.push_str(rest);
+
+    let mut linked = linked.trim_end().to_string();
+
+    if !cited.is_empty() {
+        linked.push_str(&format!("\n\n**{heading}**"));
+    }
+
+    for number in cited {
+        let source = &sources[number - 1];
+        linked.push_str(&format!("\n{number}. [{}]({})", title(source), source.url));
+    }
+
+    linked
+}
+
+/// `[1]` or `[1, 2]`, with the length of the marker, markdown links aren't citations
+fn citation(text: &str) -> Option<(Vec<usize>, usize)> {
+    let end = text.find(']')?;
+    if text[end + 1..].starts_with('(') {
+        return None;
+    }
+
+    let numbers = text[1..end]
+        .split(',')
+        .map(|number| number.trim().parse().ok())
+        .collect::<Option<Vec<usize>>>()?;
+
+    Some((numbers, end + 1))
+}
+
+/// A line like `- Citation [1]: https://example.com`, the list the prompt used to ask for
+fn is_source_line(line: &str) -> bool {
+    let line = line
+        .trim_start()
+        .trim_start_matches(['-', '*'])
+        .trim_start();
+    let line = match line.get(..8) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("citation") => line[8..].trim_start(),
+        _ => line,
+    };
+
+    let Some((_, length)) = citation(line) else {
+        return false;
+    };
+
+    let url = line[length..]
+        .trim_start_matches([':', ' '])
+        .trim_matches(['[', ']', '<', '>', '(', ')']);
+
+    url.starts_with("http") && !url.contains(char::is_whitespace)
+}
+
+/// The title of a result, without the brackets that would end the link early
+fn title(source: &SearchResult) -> String {
+    let title = source.title.trim();
+    let title = if title.is_empty() { &source.url } else { title };
+
+    title.replace('[', "(").replace(']', ")").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(number: usize) -> SearchResult {
+        SearchResult {
+            url: format!("https://example.com/{number}"),
+            title: format!("Result [{number}]"),
+            content: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_link() {
+        let sources: Vec<SearchResult> = (1..=3).map(result).collect();
+
+        let answer = "Paris is big [2]. It has a tower [1][7].\n\
+            - Rivers [2, 3]\n\
+            - Unknown [9].\n\
+            See [the docs](https://docs.example.com) and [x].\n\n\
+            - Citation [1]: https://wrong.example.com\n\
+            [2]: <https://wrong.example.com/2>";
+
+        assert_eq!(
+            link(answer, &sources, "Sources"),
+            "Paris is big [[2]](https://example.com/2). It has a tower [[1]](https://example.com/1).\n\
+            - Rivers [[2]](https://example.com/2)[[3]](https://example.com/3)\n\
+            - Unknown.\n\
+            See [the docs](https://docs.example.com) and [x].\n\n\
+            **Sources**\n\
+            1. [Result (1)](https://example.com/1)\n\
+            2. [Result (2)](https://example.com/2)\n\
+            3. [Result (3)](https://example.com/3)"
+        );
+
+        // nothing cited, nothing listed
+        assert_eq!(link("No sources [4].", &sources, "Sources"), "No sources.");
+    }
+}
@@ -1,13 +1,18 @@
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+use crate::logging::Correlate;
+
+pub mod citations;
 pub mod prompts;
+mod provider;
 
 #[derive(Clone)]
 pub struct Ollama {
     api_uri: Arc<String>,
     http_client: reqwest::Client,
     model: Model,
+    embedding_model: Arc<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -47,11 +52,17 @@ pub struct Response {
 }
 
 impl Ollama {
-    pub fn new(http_client: reqwest::Client, api_uri: String, model: Model) -> Self {
+    pub fn new(
+        http_client: reqwest::Client,
+        api_uri: String,
+        model: Model,
+        embedding_model: String,
+    ) -> Self {
         Self {
             api_uri: Arc::new(api_uri),
             http_client,
             model,
+            embedding_model: Arc::new(embedding_model),
         }
     }
 
@@ -64,73 +75,14 @@ impl Ollama {
                 model: &self.model,
                 stream: false,
             })
+            .correlate()
             .send()
             .await?
             .text()
             .await?;
 
-        let response = serde_json::from_str(res.as_str()).map(Self::filter_think_tags)?;
+        let response = serde_json::from_str(res.as_str())?;
 
         Ok(response)
     }
-
-    fn filter_think_tags(mut response: Response) -> Response {
-        const CLOSE_TAG: &'static str = "</think>";
-
-        if !response.model.as_str().contains("deepseek-r1") {
-            return response;
-        }
-
-        if !response.response.as_str().starts_with("<think>") {
-            return response;
-        }
-
-        let Some(stop_idx) = response.response.as_str().find(CLOSE_TAG) else {
-            return response;
-        };
-
-        if response.response.len() > stop_idx + CLOSE_TAG.len() {
-            response.response = response.response[(stop_idx + CLOSE_TAG.len())..].to_string();
-        }
-
-        response
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_filter_think_tags() {
-        let response = Response {
-            response: "<think>asldkl</think>the rest of the content".into(),
-            model: "deepseek-r1".into(),
-            created_at: "foo".into(),
-            done: true,
-            eval_count: None,
-            total_duration: None,
-            load_duration: None,
-            prompt_eval_count: None,
-            prompt_eval_duration: None,
-            eval_duration: None,
-        };
-
-        let filtered = Ollama::filter_think_tags(response);
-        assert_eq!(filtered.response, "the rest of the content".to_string());
-
-        // do it again just to be sure
-        let filtered = Ollama::filter_think_tags(filtered);
-        assert_eq!(filtered.response, "the rest of the content".to_string());
-
-        // without other content
-        let response = Ollama::filter_think_tags(Response {
-            response: "<think>some thoughts without a result</think>".to_string(),
-            ..filtered
-        });
-        assert_eq!(
-            response.response,
-            "<think>some thoughts without a result</think>".to_string()
-        );
-    }
 }
@@ -17,7 +17,7 @@ End with a 1 sentence summary of the text.
     )
 }
 
-pub fn deep_search(question: impl Display, sources: Vec<SearchResult>) -> String {
+pub fn deep_search(question: impl Display, sources: &[SearchResult]) -> String {
     let formatted_sources = sources
         .iter()
         .enumerate()
@@ -40,15 +40,9 @@ Place citations at the end of the sentence. You can do multiple citations in a r
 
 Only cite the most relevant results that answer the question accurately. If different results refer to different entities with the same name, write separate answers for each entity.
 
-When you include citations in your response, you absolutely have to list the sources at the bottom of your response with their URL in the following format:
-- Citation [number]: [URL]
-- Citation [number]: [URL]
-- ...
-
 ONLY cite inline.
-DO NOT include a reference section, DO NOT include URLs.
+DO NOT include a reference section, DO NOT include URLs, the sources are listed under your answer.
 DO NOT repeat the question.
-DO NOT include citations WITHOUT referencing the search result's URL at the bottom.
 
 
 You can use markdown formatting. You should include bullets to list the information in your answer.
@@ -0,0 +1,167 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::Ollama;
+use crate::llm::{self, Chat, Completion, Message, Provider, TokenStream};
+use crate::logging::Correlate;
+use crate::metrics;
+
+#[derive(Debug, Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: &'a [Message],
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<Options>,
+}
+
+#[derive(Debug, Serialize)]
+struct Options {
+    temperature: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    model: String,
+    message: Message,
+    total_duration: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunk {
+    message: Option<Chunk>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chunk {
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl Ollama {
+    fn chat_request<'a>(&'a self, chat: &'a Chat, stream: bool) -> ChatRequest<'a> {
+        ChatRequest {
+            model: chat.model.as_deref().unwrap_or(&self.model.0),
+            messages: &chat.messages,
+            stream,
+            options: chat.temperature.map(|temperature| Options { temperature }),
+        }
+    }
+
+    fn observe(model: &str, total_duration: Option<usize>) {
+        if let Some(nanos) = total_duration {
+            let seconds = nanos as f64 / 1_000_000_000.0;
+            metrics::OLLAMA_TOTAL_DURATION.observe(model, seconds);
+        }
+    }
+}
+
+/// A line of the streamed chat, Ollama sends one JSON object per line
+fn chunk(line: &str) -> Result<Option<String>, llm::Error> {
+    let chunk: ChatChunk = serde_json::from_str(line)?;
+
+    Ok(chunk
+        .message
+        .map(|message| message.content)
+        .filter(|content| !content.is_empty()))
+}
+
+#[async_trait]
+impl Provider for Ollama {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model.0
+    }
+
+    async fn chat(&self, chat: &Chat) -> Result<Completion, llm::Error> {
+        let response: ChatResponse = self
+            .http_client
+            .post(format!("{}/api/chat", self.api_uri))
+            .json(&self.chat_request(chat, false))
+            .correlate()
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Self::observe(&response.model, response.total_duration);
+
+        Ok(Completion {
+            text: response.message.content,
+            model: response.model,
+            reasoning: None,
+        })
+    }
+
+    async fn generate(&self, prompt: &str) -> Result<Completion, llm::Error> {
+        let response = self.request_completion(prompt.to_string()).await?;
+
+        Self::observe(&response.model, response.total_duration);
+
+        Ok(Completion {
+            text: response.response,
+            model: response.model,
+            reasoning: None,
+        })
+    }
+
+    async fn stream(&self, chat: &Chat) -> Result<TokenStream, llm::Error> {
+        let response = self
+            .http_client
+            .post(format!("{}/api/chat", self.api_uri))
+            .json(&self.chat_request(chat, true))
+            .correlate()
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(llm::lines(response, chunk))
+    }
+
+    async fn embed(&self, input: &[String]) -> Result<Vec<Vec<f32>>, llm::Error> {
+        let response: EmbedResponse = self
+            .http_client
+            .post(format!("{}/api/embed", self.api_uri))
+            .json(&EmbedRequest {
+                model: &self.embedding_model,
+                input,
+            })
+            .correlate()
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.embeddings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk() {
+        let line =
+            r#"{"model":"llama3","message":{"role":"assistant","content":"Hi"},"done":false}"#;
+        assert_eq!(chunk(line).unwrap(), Some(String::from("Hi")));
+
+        let line = r#"{"model":"llama3","message":{"role":"assistant","content":""},"done":true}"#;
+        assert_eq!(chunk(line).unwrap(), None);
+    }
+}
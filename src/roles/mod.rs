@@ -0,0 +1,284 @@
+//! Per chat roles and the minimum role required for each command family
+
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
+
+use moka::future::Cache;
+use serde::Deserialize;
+use teloxide::{
+    requests::Requester,
+    types::{ChatId, UserId},
+    Bot,
+};
+use tokio::sync::RwLock;
+
+use crate::store::Store;
+
+/// Roles in ascending order of privileges
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Banned,
+    User,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Banned => "banned",
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "banned" => Ok(Role::Banned),
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            other => Err(format!(
+                "unknown role '{other}', use banned, user, moderator, admin or owner"
+            )),
+        }
+    }
+}
+
+/// Command families that can be restricted to a minimum role
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gate {
+    Image,
+    Llm,
+    Tts,
+    Admin,
+}
+
+/// Minimum role required per command family
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    pub image: Role,
+    pub llm: Role,
+    pub tts: Role,
+    pub admin: Role,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            image: Role::User,
+            llm: Role::User,
+            tts: Role::User,
+            admin: Role::Admin,
+        }
+    }
+}
+
+impl Permissions {
+    pub fn minimum(&self, gate: Gate) -> Role {
+        match gate {
+            Gate::Image => self.image,
+            Gate::Llm => self.llm,
+            Gate::Tts => self.tts,
+            Gate::Admin => self.admin,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Owner of the bot, has every privilege in every chat
+    pub owner: Option<UserId>,
+    /// Grant the admin role to the administrators of a Telegram chat
+    pub auto_grant_chat_admins: bool,
+    pub permissions: Permissions,
+}
+
+/// Roles of users per chat, persisted in the store
+#[derive(Clone)]
+pub struct Roles {
+    roles: Arc<RwLock<HashMap<(ChatId, UserId), Role>>>,
+    /// Telegram administrators per chat
+    chat_admins: Cache<ChatId, Arc<Vec<UserId>>>,
+    store: Store,
+    bot: Bot,
+    config: Arc<Config>,
+}
+
+impl Roles {
+    /// Load the persisted roles
+    pub async fn load(store: Store, bot: Bot, config: Config) -> Result<Self, anyhow::Error> {
+        let roles = store
+            .roles()
+            .await?
+            .into_iter()
+            .map(|(chat_id, user_id, role)| ((chat_id, user_id), role))
+            .collect();
+
+        let chat_admins = Cache::builder()
+            .time_to_live(Duration::from_secs(60 * 10))
+            .build();
+
+        Ok(Self {
+            roles: Arc::new(RwLock::new(roles)),
+            chat_admins,
+            store,
+            bot,
+            config: Arc::new(config),
+        })
+    }
+
+    /// The effective role of a user in a chat
+    ///
+    /// The owner always wins, followed by explicitly granted roles and finally the
+    /// Telegram chat administrators, when auto granting is enabled.
+    pub async fn role(&self, chat_id: ChatId, user_id: UserId) -> Role {
+        if self.config.owner == Some(user_id) {
+            return Role::Owner;
+        }
+
+        if let Some(role) = self.roles.read().await.get(&(chat_id, user_id)) {
+            return *role;
+        }
+
+        if self.config.auto_grant_chat_admins && self.chat_admins(chat_id).await.contains(&user_id)
+        {
+            return Role::Admin;
+        }
+
+        Role::User
+    }
+
+    /// Whether the user is allowed to use a command family, returns the role of the user otherwise
+    pub async fn authorize(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        gate: Gate,
+    ) -> Result<(), Role> {
+        let role = self.role(chat_id, user_id).await;
+
+        if role >= self.minimum(gate) {
+            Ok(())
+        } else {
+            Err(role)
+        }
+    }
+
+    pub fn minimum(&self, gate: Gate) -> Role {
+        self.config.permissions.minimum(gate)
+    }
+
+    pub fn owner(&self) -> Option<UserId> {
+        self.config.owner
+    }
+
+    pub fn grants_chat_admins(&self) -> bool {
+        self.config.auto_grant_chat_admins
+    }
+
+    /// Explicitly granted roles, across all chats
+    pub async fn granted(&self) -> Vec<(ChatId, UserId, Role)> {
+        self.roles
+            .read()
+            .await
+            .iter()
+            .map(|((chat_id, user_id), role)| (*chat_id, *user_id, *role))
+            .collect()
+    }
+
+    pub async fn set_role(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        role: Role,
+    ) -> Result<(), anyhow::Error> {
+        self.store.set_role(chat_id, user_id, role).await?;
+        self.roles.write().await.insert((chat_id, user_id), role);
+
+        Ok(())
+    }
+
+    /// Explicitly granted roles in a chat
+    pub async fn in_chat(&self, chat_id: ChatId) -> Vec<(UserId, Role)> {
+        let mut roles: Vec<(UserId, Role)> = self
+            .roles
+            .read()
+            .await
+            .iter()
+            .filter(|((chat, _), _)| *chat == chat_id)
+            .map(|((_, user_id), role)| (*user_id, *role))
+            .collect();
+
+        roles.sort_by(|a, b| b.1.cmp(&a.1));
+
+        roles
+    }
+
+    async fn chat_admins(&self, chat_id: ChatId) -> Arc<Vec<UserId>> {
+        if let Some(admins) = self.chat_admins.get(&chat_id).await {
+            return admins;
+        }
+
+        let admins = match self.bot.get_chat_administrators(chat_id).await {
+            Ok(members) => members.into_iter().map(|member| member.user.id).collect(),
+            Err(err) => {
+                // private chats don't have administrators
+                log::debug!("failed to fetch chat administrators of {chat_id}: {err}");
+                Vec::new()
+            }
+        };
+
+        let admins = Arc::new(admins);
+        self.chat_admins.insert(chat_id, admins.clone()).await;
+
+        admins
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_role_order() {
+        assert!(Role::Owner > Role::Admin);
+        assert!(Role::Admin > Role::Moderator);
+        assert!(Role::Moderator > Role::User);
+        assert!(Role::User > Role::Banned);
+
+        let permissions = Permissions::default();
+        assert!(Role::User >= permissions.minimum(Gate::Image));
+        assert!(Role::Banned < permissions.minimum(Gate::Llm));
+        assert!(Role::Moderator < permissions.minimum(Gate::Admin));
+    }
+
+    #[test]
+    fn test_parse_role() {
+        for role in [
+            Role::Banned,
+            Role::User,
+            Role::Moderator,
+            Role::Admin,
+            Role::Owner,
+        ] {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+
+        assert!("clown".parse::<Role>().is_err());
+    }
+}
@@ -0,0 +1,590 @@
+//! Admin configurable rules that rewrite or refuse commands before they're enqueued
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use chrono::{NaiveTime, Timelike, Utc};
+use teloxide::types::{ChatId, UserId};
+use tokio::sync::RwLock;
+
+use crate::store::Store;
+
+/// Group of commands a rule applies to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    Image,
+    Llm,
+    Tts,
+}
+
+impl Family {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Family::Image => "image",
+            Family::Llm => "llm",
+            Family::Tts => "tts",
+        }
+    }
+}
+
+impl FromStr for Family {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "image" => Ok(Family::Image),
+            "llm" => Ok(Family::Llm),
+            "tts" => Ok(Family::Tts),
+            other => Err(format!(
+                "unknown command family '{other}', use image, llm or tts"
+            )),
+        }
+    }
+}
+
+/// Image style that can be forced upon image commands
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Style {
+    Photo,
+    Drawing,
+    Gigachad,
+    Anime,
+    Lego,
+    Knit,
+}
+
+impl Style {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Style::Photo => "photo",
+            Style::Drawing => "drawing",
+            Style::Gigachad => "gigachad",
+            Style::Anime => "anime",
+            Style::Lego => "lego",
+            Style::Knit => "knit",
+        }
+    }
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "photo" => Ok(Style::Photo),
+            "drawing" => Ok(Style::Drawing),
+            "gigachad" => Ok(Style::Gigachad),
+            "anime" => Ok(Style::Anime),
+            "lego" => Ok(Style::Lego),
+            "knit" => Ok(Style::Knit),
+            other => Err(format!(
+                "unknown style '{other}', use photo, drawing, gigachad, anime, lego or knit"
+            )),
+        }
+    }
+}
+
+/// What happens when a rule matches
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Replace the prompt entirely
+    Replace(String),
+    /// Add text to the end of the prompt
+    Append(String),
+    /// Force an image style, only affects image commands
+    Style(Style),
+    /// Refuse the command with the given reply
+    Refuse(String),
+}
+
+impl Action {
+    const DEFAULT_REFUSAL: &'static str = "no";
+
+    /// Build an action from its name and argument
+    pub fn from_parts(kind: &str, argument: &str) -> Result<Self, String> {
+        let argument = argument.trim();
+
+        match kind {
+            "replace" | "append" if argument.is_empty() => Err(format!("'{kind}' needs a prompt")),
+            "replace" => Ok(Action::Replace(argument.to_string())),
+            "append" => Ok(Action::Append(argument.to_string())),
+            "style" => argument.parse().map(Action::Style),
+            "refuse" if argument.is_empty() => Ok(Action::Refuse(Self::DEFAULT_REFUSAL.into())),
+            "refuse" => Ok(Action::Refuse(argument.to_string())),
+            other => Err(format!(
+                "unknown action '{other}', use replace, append, style or refuse"
+            )),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Action::Replace(_) => "replace",
+            Action::Append(_) => "append",
+            Action::Style(_) => "style",
+            Action::Refuse(_) => "refuse",
+        }
+    }
+
+    pub fn argument(&self) -> &str {
+        match self {
+            Action::Replace(argument) | Action::Append(argument) | Action::Refuse(argument) => {
+                argument
+            }
+            Action::Style(style) => style.as_str(),
+        }
+    }
+}
+
+/// Time of day (UTC) during which a rule is active, may wrap around midnight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl Window {
+    const FORMAT: &'static str = "%H:%M";
+
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+
+    pub fn parse_time(time: &str) -> Result<NaiveTime, String> {
+        NaiveTime::parse_from_str(time, Self::FORMAT)
+            .map_err(|_| format!("invalid time '{time}', use HH:MM"))
+    }
+
+    pub fn format_time(time: NaiveTime) -> String {
+        time.format(Self::FORMAT).to_string()
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("invalid time window '{s}', use HH:MM-HH:MM"))?;
+
+        Ok(Window {
+            start: Self::parse_time(start)?,
+            end: Self::parse_time(end)?,
+        })
+    }
+}
+
+/// When a rule applies, `None` fields match everything
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Condition {
+    pub chat_id: Option<ChatId>,
+    pub user_id: Option<UserId>,
+    pub family: Option<Family>,
+    /// Chance between 0 and 1 that the rule applies
+    pub probability: Option<f64>,
+    pub window: Option<Window>,
+}
+
+impl Condition {
+    /// Whether the rule applies, ignoring the probability
+    fn matches(&self, chat_id: ChatId, user_id: UserId, family: Family, now: NaiveTime) -> bool {
+        self.chat_id.map(|id| id == chat_id).unwrap_or(true)
+            && self.user_id.map(|id| id == user_id).unwrap_or(true)
+            && self.family.map(|f| f == family).unwrap_or(true)
+            && self.window.map(|w| w.contains(now)).unwrap_or(true)
+    }
+
+    /// Whether the rule only applies in the given chat, other rules are for the owner to add
+    pub fn only_in(&self, chat_id: ChatId) -> bool {
+        self.chat_id == Some(chat_id)
+    }
+
+    fn roll(&self) -> bool {
+        self.probability
+            .map(|probability| rand::random::<f64>() < probability)
+            .unwrap_or(true)
+    }
+
+    /// Parse the leading `key=value` arguments of the add rule command,
+    /// returns the condition and the remaining arguments.
+    ///
+    /// The rule is scoped to `chat_id` unless `chat=any` is given,
+    /// `user_id` is used when no `user=` is given.
+    pub fn parse(
+        mut args: &str,
+        chat_id: ChatId,
+        user_id: Option<UserId>,
+    ) -> Result<(Self, &str), String> {
+        let mut condition = Condition {
+            chat_id: Some(chat_id),
+            user_id,
+            ..Default::default()
+        };
+
+        loop {
+            args = args.trim_start();
+
+            let (arg, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+
+            if arg.is_empty() {
+                return Err(String::from(
+                    "missing action, use replace, append, style or refuse",
+                ));
+            }
+
+            let Some((key, value)) = arg.split_once('=') else {
+                return Ok((condition, args));
+            };
+
+            match (key, value) {
+                ("chat", "any") => condition.chat_id = None,
+                ("chat", "this") => condition.chat_id = Some(chat_id),
+                ("chat", id) => {
+                    condition.chat_id = Some(ChatId(
+                        id.parse().map_err(|_| format!("invalid chat id '{id}'"))?,
+                    ))
+                }
+                ("user", "any") => condition.user_id = None,
+                ("user", id) => {
+                    condition.user_id = Some(UserId(
+                        id.parse().map_err(|_| format!("invalid user id '{id}'"))?,
+                    ))
+                }
+                ("family", "any") => condition.family = None,
+                ("family", family) => condition.family = Some(family.parse()?),
+                ("chance", chance) => {
+                    let probability = match chance.strip_suffix('%') {
+                        Some(percentage) => percentage.parse::<f64>().map(|p| p / 100.0),
+                        None => chance.parse::<f64>(),
+                    }
+                    .map_err(|_| format!("invalid chance '{chance}'"))?;
+
+                    if !(0.0..=1.0).contains(&probability) {
+                        return Err(format!("chance '{chance}' should be between 0% and 100%"));
+                    }
+
+                    condition.probability = Some(probability);
+                }
+                ("time", window) => condition.window = Some(window.parse()?),
+                (key, _) => {
+                    return Err(format!(
+                        "unknown condition '{key}', use chat, user, family, chance or time"
+                    ))
+                }
+            }
+
+            args = rest;
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub id: i64,
+    pub condition: Condition,
+    pub action: Action,
+}
+
+impl Rule {
+    /// Parse the arguments of the add rule command:
+    /// `[chat=..] [user=..] [family=..] [chance=..] [time=HH:MM-HH:MM] <action> [argument]`
+    pub fn parse(
+        args: &str,
+        chat_id: ChatId,
+        user_id: Option<UserId>,
+    ) -> Result<(Condition, Action), String> {
+        let (condition, action) = Condition::parse(args, chat_id, user_id)?;
+
+        let (kind, argument) = action
+            .split_once(char::is_whitespace)
+            .unwrap_or((action, ""));
+
+        Ok((condition, Action::from_parts(kind, argument)?))
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Condition {
+            chat_id,
+            user_id,
+            family,
+            probability,
+            window,
+        } = &self.condition;
+
+        write!(f, "#{}", self.id)?;
+
+        if let Some(chat_id) = chat_id {
+            write!(f, " chat={chat_id}")?;
+        }
+        if let Some(user_id) = user_id {
+            write!(f, " user={user_id}")?;
+        }
+        if let Some(family) = family {
+            write!(f, " family={}", family.as_str())?;
+        }
+        if let Some(probability) = probability {
+            write!(f, " chance={}%", probability * 100.0)?;
+        }
+        if let Some(window) = window {
+            write!(
+                f,
+                " time={}-{}",
+                Window::format_time(window.start),
+                Window::format_time(window.end)
+            )?;
+        }
+
+        write!(f, " {} {}", self.action.kind(), self.action.argument())
+    }
+}
+
+/// Combined effect of all matching rules
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    pub refusal: Option<String>,
+    pub style: Option<Style>,
+    replace: Option<String>,
+    append: Vec<String>,
+}
+
+impl Outcome {
+    /// The prompt after applying the rules, `None` when it's unchanged
+    fn rewrite(&self, prompt: &str) -> Option<String> {
+        if self.replace.is_none() && self.append.is_empty() {
+            return None;
+        }
+
+        let mut prompt = self.replace.as_deref().unwrap_or(prompt).to_string();
+
+        for addition in self.append.iter() {
+            prompt.push(' ');
+            prompt.push_str(addition);
+        }
+
+        Some(prompt)
+    }
+
+    /// Apply the outcome to a command, returns the refusal when the command should not run
+    pub fn apply(&self, command: &mut impl Rewritable) -> Result<(), String> {
+        if let Some(refusal) = &self.refusal {
+            return Err(refusal.clone());
+        }
+
+        if let Some(style) = self.style {
+            command.force_style(style);
+        }
+
+        if let Some(prompt) = command.prompt().and_then(|prompt| self.rewrite(prompt)) {
+            command.override_prompt(prompt);
+        }
+
+        Ok(())
+    }
+}
+
+/// Commands whose prompt can be altered by rules and overrides
+pub trait Rewritable {
+    /// The rules of this family apply to the command
+    const FAMILY: Family;
+
+    /// The current prompt, `None` when the command has no user prompt
+    fn prompt(&self) -> Option<&str>;
+
+    fn override_prompt(&mut self, prompt: impl ToString);
+
+    /// Only relevant for image commands
+    fn force_style(&mut self, _style: Style) {}
+}
+
+/// All configured rules, persisted in the store
+#[derive(Clone)]
+pub struct Rules {
+    rules: Arc<RwLock<Vec<Rule>>>,
+    store: Store,
+}
+
+impl Rules {
+    /// Load the persisted rules
+    pub async fn load(store: Store) -> Result<Self, anyhow::Error> {
+        let rules = store.rules().await?;
+
+        Ok(Self {
+            rules: Arc::new(RwLock::new(rules)),
+            store,
+        })
+    }
+
+    /// Evaluate all rules for a command, later rules take precedence over earlier ones
+    pub async fn evaluate(&self, chat_id: ChatId, user_id: UserId, family: Family) -> Outcome {
+        let now = Utc::now().time();
+        // ignore sub-minute precision, the windows only have minutes anyway
+        let now = now.with_second(0).unwrap_or(now);
+
+        let mut outcome = Outcome::default();
+
+        for rule in self.rules.read().await.iter() {
+            if !rule.condition.matches(chat_id, user_id, family, now) || !rule.condition.roll() {
+                continue;
+            }
+
+            log::info!("Rule matched: {rule}, ChatId({chat_id}), UserId({user_id})");
+
+            match &rule.action {
+                Action::Replace(prompt) => {
+                    outcome.replace = Some(prompt.clone());
+                    outcome.append.clear();
+                }
+                Action::Append(prompt) => outcome.append.push(prompt.clone()),
+                Action::Style(style) => outcome.style = Some(*style),
+                Action::Refuse(reply) => outcome.refusal = Some(reply.clone()),
+            }
+        }
+
+        outcome
+    }
+
+    pub async fn add(&self, condition: Condition, action: Action) -> Result<Rule, anyhow::Error> {
+        let rule = self.store.insert_rule(condition, action).await?;
+
+        self.rules.write().await.push(rule.clone());
+
+        Ok(rule)
+    }
+
+    /// Remove a rule of a chat, or any rule without a chat, returns whether it was removed
+    pub async fn remove(&self, id: i64, chat_id: Option<ChatId>) -> Result<bool, anyhow::Error> {
+        let removed = self.store.remove_rule(id, chat_id).await?;
+
+        if removed {
+            self.rules.write().await.retain(|rule| rule.id != id);
+        }
+
+        Ok(removed)
+    }
+
+    /// Rules that can apply in a chat, global rules included
+    pub async fn in_chat(&self, chat_id: ChatId) -> Vec<Rule> {
+        self.rules
+            .read()
+            .await
+            .iter()
+            .filter(|rule| {
+                rule.condition
+                    .chat_id
+                    .map(|id| id == chat_id)
+                    .unwrap_or(true)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> NaiveTime {
+        Window::parse_time(time).unwrap()
+    }
+
+    #[test]
+    fn test_window() {
+        let window: Window = "09:00-17:00".parse().unwrap();
+        assert!(window.contains(time("09:00")));
+        assert!(window.contains(time("12:30")));
+        assert!(!window.contains(time("17:00")));
+        assert!(!window.contains(time("23:00")));
+
+        let overnight: Window = "22:00-06:00".parse().unwrap();
+        assert!(overnight.contains(time("23:00")));
+        assert!(overnight.contains(time("03:00")));
+        assert!(!overnight.contains(time("12:00")));
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let (condition, action) = Rule::parse(
+            "family=image chance=50% time=22:00-06:00 replace man dressed as clown",
+            ChatId(-1),
+            Some(UserId(7)),
+        )
+        .unwrap();
+
+        assert_eq!(
+            condition,
+            Condition {
+                chat_id: Some(ChatId(-1)),
+                user_id: Some(UserId(7)),
+                family: Some(Family::Image),
+                probability: Some(0.5),
+                window: Some(Window {
+                    start: time("22:00"),
+                    end: time("06:00")
+                }),
+            }
+        );
+        assert_eq!(action, Action::Replace("man dressed as clown".into()));
+
+        let (condition, action) = Rule::parse("chat=any user=3 refuse", ChatId(-1), None).unwrap();
+        assert_eq!(condition.chat_id, None);
+        assert!(!condition.only_in(ChatId(-1)));
+        assert_eq!(condition.user_id, Some(UserId(3)));
+        assert_eq!(action, Action::Refuse("no".into()));
+
+        // rules for other chats are the owner's
+        let (condition, _) = Rule::parse("chat=-2 refuse", ChatId(-1), None).unwrap();
+        assert!(!condition.only_in(ChatId(-1)));
+        let (condition, _) = Rule::parse("chat=this refuse", ChatId(-1), None).unwrap();
+        assert!(condition.only_in(ChatId(-1)));
+
+        assert!(Rule::parse("family=image", ChatId(-1), None).is_err());
+        assert!(Rule::parse("style cubism", ChatId(-1), None).is_err());
+        assert!(Rule::parse("chance=150% refuse", ChatId(-1), None).is_err());
+        assert!(Rule::parse("replace", ChatId(-1), None).is_err());
+    }
+
+    #[test]
+    fn test_condition_matches() {
+        let condition = Condition {
+            user_id: Some(UserId(1)),
+            family: Some(Family::Llm),
+            ..Default::default()
+        };
+
+        let now = time("12:00");
+        assert!(condition.matches(ChatId(5), UserId(1), Family::Llm, now));
+        assert!(!condition.matches(ChatId(5), UserId(2), Family::Llm, now));
+        assert!(!condition.matches(ChatId(5), UserId(1), Family::Image, now));
+    }
+
+    #[test]
+    fn test_outcome_rewrite() {
+        let outcome = Outcome::default();
+        assert_eq!(outcome.rewrite("a cat"), None);
+
+        let outcome = Outcome {
+            append: vec!["wearing a hat".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            outcome.rewrite("a cat"),
+            Some("a cat wearing a hat".to_string())
+        );
+
+        let outcome = Outcome {
+            replace: Some("a dog".into()),
+            append: vec!["wearing a hat".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            outcome.rewrite("a cat"),
+            Some("a dog wearing a hat".to_string())
+        );
+    }
+}
@@ -0,0 +1,648 @@
+//! Fair-share job scheduler shared by all backend handlers
+//!
+//! Every backend has a limited amount of slots. Jobs that don't fit wait in line, ordered by
+//! priority class first and served round-robin across users within a class, so a single user
+//! can't starve the rest of the group.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use serde::Deserialize;
+use teloxide::types::UserId;
+use tokio::sync::oneshot;
+
+use crate::metrics;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    InvokeAi,
+    LocalAi,
+    /// Every LLM provider, whichever answers
+    Llm,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::InvokeAi => "invoke-ai",
+            Backend::LocalAi => "local-ai",
+            Backend::Llm => "llm",
+        }
+    }
+}
+
+/// Priority classes, in the order they are served
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Admin,
+    #[default]
+    Normal,
+}
+
+/// Amount of jobs every backend runs concurrently
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    #[serde(default = "Config::default_concurrency")]
+    pub invoke_ai: NonZeroUsize,
+    #[serde(default = "Config::default_concurrency")]
+    pub local_ai: NonZeroUsize,
+    #[serde(default = "Config::default_concurrency", alias = "ollama")]
+    pub llm: NonZeroUsize,
+}
+
+impl Config {
+    fn default_concurrency() -> NonZeroUsize {
+        NonZeroUsize::new(2).unwrap()
+    }
+
+    fn concurrency(&self, backend: Backend) -> NonZeroUsize {
+        match backend {
+            Backend::InvokeAi => self.invoke_ai,
+            Backend::LocalAi => self.local_ai,
+            Backend::Llm => self.llm,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            invoke_ai: Self::default_concurrency(),
+            local_ai: Self::default_concurrency(),
+            llm: Self::default_concurrency(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Rejected {
+    #[error("Too many jobs in progress")]
+    TooManyInProgress,
+}
+
+type JobId = u64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Job {
+    id: JobId,
+    user_id: UserId,
+    priority: Priority,
+}
+
+/// Jobs in progress or waiting per user
+#[derive(Debug, Default)]
+struct UserCounts {
+    users: HashMap<UserId, usize>,
+}
+
+impl UserCounts {
+    fn get(&self, user_id: UserId) -> usize {
+        self.users.get(&user_id).copied().unwrap_or_default()
+    }
+
+    fn increment(&mut self, user_id: UserId) -> usize {
+        log::debug!("Incrementing user {user_id}");
+
+        let count = self.users.entry(user_id).or_default();
+        *count += 1;
+
+        *count
+    }
+
+    fn decrement(&mut self, user_id: UserId) -> usize {
+        log::debug!("Decrementing user {user_id}");
+
+        let Some(count) = self.users.get_mut(&user_id) else {
+            log::warn!("Decrementing user {user_id} without jobs");
+            return 0;
+        };
+
+        *count -= 1;
+        let count = *count;
+
+        if count == 0 {
+            self.users.remove(&user_id);
+        }
+
+        count
+    }
+}
+
+/// Waiting jobs of a single priority class, served one job per user in turn
+#[derive(Debug, Default)]
+struct RoundRobin {
+    /// Users with waiting jobs, the next user to be served comes first
+    turns: VecDeque<UserId>,
+    jobs: HashMap<UserId, VecDeque<Job>>,
+}
+
+impl RoundRobin {
+    fn len(&self) -> usize {
+        self.jobs.values().map(VecDeque::len).sum()
+    }
+
+    /// Amount of jobs that will be served before a new job of this user
+    fn ahead_of(&self, user_id: UserId) -> usize {
+        let own = self
+            .jobs
+            .get(&user_id)
+            .map(VecDeque::len)
+            .unwrap_or_default();
+        let turn = self
+            .turns
+            .iter()
+            .position(|user| *user == user_id)
+            .unwrap_or(self.turns.len());
+
+        // the new job is served in round `own`, after the users that come before in that round
+        let earlier_rounds: usize = self.jobs.values().map(|jobs| jobs.len().min(own)).sum();
+
+        let this_round = self
+            .turns
+            .iter()
+            .take(turn)
+            .filter(|user| self.jobs[*user].len() > own)
+            .count();
+
+        earlier_rounds + this_round
+    }
+
+    fn push(&mut self, job: Job) {
+        let jobs = self.jobs.entry(job.user_id).or_default();
+
+        if jobs.is_empty() {
+            self.turns.push_back(job.user_id);
+        }
+
+        jobs.push_back(job);
+    }
+
+    fn pop(&mut self) -> Option<Job> {
+        let user_id = self.turns.pop_front()?;
+        let jobs = self.jobs.get_mut(&user_id)?;
+        let job = jobs.pop_front();
+
+        if jobs.is_empty() {
+            self.jobs.remove(&user_id);
+        } else {
+            self.turns.push_back(user_id);
+        }
+
+        job
+    }
+
+    fn remove(&mut self, job_id: JobId) -> Option<Job> {
+        let (user_id, jobs) = self
+            .jobs
+            .iter_mut()
+            .find(|(_, jobs)| jobs.iter().any(|job| job.id == job_id))?;
+        let user_id = *user_id;
+
+        let index = jobs.iter().position(|job| job.id == job_id)?;
+        let job = jobs.remove(index);
+
+        if jobs.is_empty() {
+            self.jobs.remove(&user_id);
+            self.turns.retain(|user| *user != user_id);
+        }
+
+        job
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Admission {
+    Running,
+    /// Waiting in line, 1 means next up
+    Queued(usize),
+}
+
+/// Slots and waiting line of a single backend
+#[derive(Debug)]
+struct Lane {
+    concurrency: usize,
+    max_in_progress: usize,
+    running: usize,
+    waiting: BTreeMap<Priority, RoundRobin>,
+    users: UserCounts,
+}
+
+impl Lane {
+    fn new(concurrency: NonZeroUsize, max_in_progress: NonZeroUsize) -> Self {
+        Self {
+            concurrency: concurrency.get(),
+            max_in_progress: max_in_progress.get(),
+            running: 0,
+            waiting: BTreeMap::new(),
+            users: UserCounts::default(),
+        }
+    }
+
+    fn waiting(&self) -> usize {
+        self.waiting.values().map(RoundRobin::len).sum()
+    }
+
+    fn submit(&mut self, job: Job) -> Result<Admission, Rejected> {
+        if self.users.get(job.user_id) >= self.max_in_progress {
+            return Err(Rejected::TooManyInProgress);
+        }
+
+        self.users.increment(job.user_id);
+
+        if self.running < self.concurrency && self.waiting() == 0 {
+            self.running += 1;
+            return Ok(Admission::Running);
+        }
+
+        let higher_priority: usize = self
+            .waiting
+            .range(..job.priority)
+            .map(|(_, class)| class.len())
+            .sum();
+
+        let class = self.waiting.entry(job.priority).or_default();
+        let position = higher_priority + class.ahead_of(job.user_id) + 1;
+        class.push(job);
+
+        Ok(Admission::Queued(position))
+    }
+
+    fn is_waiting(&self, job_id: JobId) -> bool {
+        self.waiting
+            .values()
+            .any(|class| class.jobs.values().flatten().any(|job| job.id == job_id))
+    }
+
+    /// Remove a job that is still waiting
+    fn cancel(&mut self, job_id: JobId) {
+        for class in self.waiting.values_mut() {
+            if let Some(job) = class.remove(job_id) {
+                self.users.decrement(job.user_id);
+                return;
+            }
+        }
+    }
+
+    /// Free the slot of a finished job, returns the next job that may run
+    fn finish(&mut self, user_id: UserId) -> Option<Job> {
+        self.users.decrement(user_id);
+        self.running = self.running.saturating_sub(1);
+
+        self.next()
+    }
+
+    fn next(&mut self) -> Option<Job> {
+        if self.running >= self.concurrency {
+            return None;
+        }
+
+        let job = self.waiting.values_mut().find_map(RoundRobin::pop)?;
+        self.waiting.retain(|_, class| class.len() > 0);
+        self.running += 1;
+
+        Some(job)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    lanes: HashMap<Backend, Lane>,
+    /// Wakes up waiting jobs once they may run
+    waiters: HashMap<JobId, oneshot::Sender<()>>,
+    next_id: JobId,
+}
+
+impl State {
+    fn lane(&mut self, backend: Backend) -> &mut Lane {
+        self.lanes
+            .get_mut(&backend)
+            .expect("every backend has a lane")
+    }
+
+    fn finish(&mut self, backend: Backend, user_id: UserId) {
+        let mut next = self.lane(backend).finish(user_id);
+
+        while let Some(job) = next {
+            match self.waiters.remove(&job.id) {
+                Some(waiter) if waiter.send(()).is_ok() => return,
+                // nobody is waiting for it anymore, hand the slot to the next job
+                _ => next = self.lane(backend).finish(job.user_id),
+            }
+        }
+    }
+}
+
+/// Handle to the scheduler, shared by all handlers
+#[derive(Clone)]
+pub struct Scheduler {
+    state: Arc<Mutex<State>>,
+}
+
+impl Scheduler {
+    pub fn new(config: Config, max_in_progress: NonZeroUsize) -> Self {
+        let lanes = [Backend::InvokeAi, Backend::LocalAi, Backend::Llm]
+            .into_iter()
+            .map(|backend| {
+                let lane = Lane::new(config.concurrency(backend), max_in_progress);
+                (backend, lane)
+            })
+            .collect();
+
+        Self {
+            state: Arc::new(Mutex::new(State {
+                lanes,
+                waiters: HashMap::new(),
+                next_id: 0,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // the state stays consistent, every critical section is panic free
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Amount of running and waiting jobs of a backend
+    pub fn load(&self, backend: Backend) -> (usize, usize) {
+        let mut state = self.lock();
+        let lane = state.lane(backend);
+
+        (lane.running, lane.waiting())
+    }
+
+    /// Submit a job, wait for the returned ticket before running it
+    pub fn submit(
+        &self,
+        backend: Backend,
+        user_id: UserId,
+        priority: Priority,
+    ) -> Result<Ticket, Rejected> {
+        let mut state = self.lock();
+
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let job = Job {
+            id,
+            user_id,
+            priority,
+        };
+
+        let (position, receiver) = match state.lane(backend).submit(job)? {
+            Admission::Running => (None, None),
+            Admission::Queued(position) => {
+                let (sender, receiver) = oneshot::channel();
+                state.waiters.insert(id, sender);
+                (Some(position), Some(receiver))
+            }
+        };
+
+        log::debug!(
+            "Submitted job {id} to {}, position {position:?}",
+            backend.as_str()
+        );
+        metrics::JOBS_ENQUEUED.inc(backend.as_str());
+
+        Ok(Ticket {
+            scheduler: self.clone(),
+            backend,
+            job,
+            position,
+            receiver,
+            redeemed: false,
+        })
+    }
+}
+
+/// A submitted job, waiting for its turn
+pub struct Ticket {
+    scheduler: Scheduler,
+    backend: Backend,
+    job: Job,
+    position: Option<usize>,
+    receiver: Option<oneshot::Receiver<()>>,
+    redeemed: bool,
+}
+
+impl Ticket {
+    /// Position in line when the job has to wait, 1 means next up
+    pub fn position(&self) -> Option<usize> {
+        self.position
+    }
+
+    /// Wait until the job may run, dropping the permit frees the slot
+    pub async fn ready(mut self) -> Permit {
+        if let Some(receiver) = self.receiver.as_mut() {
+            // the sender only disappears once the job may run
+            let _ = receiver.await;
+        }
+
+        self.redeemed = true;
+
+        Permit {
+            scheduler: self.scheduler.clone(),
+            backend: self.backend,
+            user_id: self.job.user_id,
+            granted_at: Instant::now(),
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if self.redeemed {
+            return;
+        }
+
+        let mut state = self.scheduler.lock();
+
+        if state.lane(self.backend).is_waiting(self.job.id) {
+            state.waiters.remove(&self.job.id);
+            state.lane(self.backend).cancel(self.job.id);
+        } else {
+            // it got its turn in the meantime
+            state.finish(self.backend, self.job.user_id);
+        }
+    }
+}
+
+/// Right to run a job, frees the slot once dropped
+pub struct Permit {
+    scheduler: Scheduler,
+    backend: Backend,
+    user_id: UserId,
+    granted_at: Instant,
+}
+
+impl fmt::Debug for Permit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Permit({}, {})", self.backend.as_str(), self.user_id)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let held = self.granted_at.elapsed().as_secs_f64();
+        metrics::JOB_DURATION.observe(self.backend.as_str(), held);
+
+        self.scheduler.lock().finish(self.backend, self.user_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: JobId, user_id: u64) -> Job {
+        Job {
+            id,
+            user_id: UserId(user_id),
+            priority: Priority::Normal,
+        }
+    }
+
+    fn lane(concurrency: usize, max_in_progress: usize) -> Lane {
+        Lane::new(
+            NonZeroUsize::new(concurrency).unwrap(),
+            NonZeroUsize::new(max_in_progress).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_user_counts() {
+        let mut counts = UserCounts::default();
+
+        assert_eq!(counts.increment(UserId(1)), 1);
+        assert_eq!(counts.increment(UserId(1)), 2);
+        assert_eq!(counts.increment(UserId(2)), 1);
+
+        assert_eq!(counts.decrement(UserId(1)), 1);
+        assert_eq!(counts.decrement(UserId(1)), 0);
+        assert_eq!(counts.get(UserId(1)), 0);
+        assert!(!counts.users.contains_key(&UserId(1)));
+
+        // never underflows
+        assert_eq!(counts.decrement(UserId(1)), 0);
+        assert_eq!(counts.get(UserId(2)), 1);
+    }
+
+    #[test]
+    fn test_max_in_progress() {
+        let mut lane = lane(1, 2);
+
+        assert_eq!(lane.submit(job(0, 1)), Ok(Admission::Running));
+        assert_eq!(lane.submit(job(1, 1)), Ok(Admission::Queued(1)));
+        assert_eq!(lane.submit(job(2, 1)), Err(Rejected::TooManyInProgress));
+
+        // other users are not affected
+        assert_eq!(lane.submit(job(3, 2)), Ok(Admission::Queued(2)));
+
+        assert_eq!(lane.finish(UserId(1)), Some(job(1, 1)));
+        assert_eq!(lane.submit(job(4, 1)), Ok(Admission::Queued(2)));
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut lane = lane(1, 10);
+
+        assert_eq!(lane.submit(job(0, 1)), Ok(Admission::Running));
+
+        // user 1 floods the queue before the others show up
+        assert_eq!(lane.submit(job(1, 1)), Ok(Admission::Queued(1)));
+        assert_eq!(lane.submit(job(2, 1)), Ok(Admission::Queued(2)));
+        assert_eq!(lane.submit(job(3, 1)), Ok(Admission::Queued(3)));
+        assert_eq!(lane.submit(job(4, 2)), Ok(Admission::Queued(2)));
+        assert_eq!(lane.submit(job(5, 3)), Ok(Admission::Queued(3)));
+        assert_eq!(lane.submit(job(6, 2)), Ok(Admission::Queued(5)));
+
+        let mut order = Vec::new();
+        let mut user_id = UserId(1);
+        while let Some(next) = lane.finish(user_id) {
+            order.push(next.id);
+            user_id = next.user_id;
+        }
+
+        assert_eq!(order, vec![1, 4, 5, 2, 6, 3]);
+        assert_eq!(lane.running, 0);
+        assert_eq!(lane.users.users.len(), 0);
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut lane = lane(1, 10);
+
+        assert_eq!(lane.submit(job(0, 1)), Ok(Admission::Running));
+        assert_eq!(lane.submit(job(1, 1)), Ok(Admission::Queued(1)));
+
+        let admin = Job {
+            priority: Priority::Admin,
+            ..job(2, 2)
+        };
+        assert_eq!(lane.submit(admin), Ok(Admission::Queued(1)));
+
+        assert_eq!(lane.finish(UserId(1)), Some(admin));
+        assert_eq!(lane.finish(UserId(2)), Some(job(1, 1)));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut lane = lane(1, 10);
+
+        assert_eq!(lane.submit(job(0, 1)), Ok(Admission::Running));
+        assert_eq!(lane.submit(job(1, 2)), Ok(Admission::Queued(1)));
+        assert_eq!(lane.submit(job(2, 3)), Ok(Admission::Queued(2)));
+
+        assert!(lane.is_waiting(1));
+        lane.cancel(1);
+        assert!(!lane.is_waiting(1));
+        assert_eq!(lane.users.get(UserId(2)), 0);
+
+        assert_eq!(lane.finish(UserId(1)), Some(job(2, 3)));
+    }
+
+    #[tokio::test]
+    async fn test_scheduler() {
+        let scheduler = Scheduler::new(Config::default(), NonZeroUsize::new(3).unwrap());
+
+        let first = scheduler
+            .submit(Backend::Llm, UserId(1), Priority::Normal)
+            .unwrap();
+        let second = scheduler
+            .submit(Backend::Llm, UserId(1), Priority::Normal)
+            .unwrap();
+        let third = scheduler
+            .submit(Backend::Llm, UserId(1), Priority::Normal)
+            .unwrap();
+
+        assert_eq!(first.position(), None);
+        assert_eq!(second.position(), None);
+        assert_eq!(third.position(), Some(1));
+        assert!(scheduler
+            .submit(Backend::Llm, UserId(1), Priority::Normal)
+            .is_err());
+
+        // other backends have their own slots
+        let other = scheduler
+            .submit(Backend::InvokeAi, UserId(1), Priority::Normal)
+            .unwrap();
+        assert_eq!(other.position(), None);
+
+        let first = first.ready().await;
+        let waiting = tokio::spawn(third.ready());
+
+        // an abandoned ticket frees its slot as well
+        drop(second);
+        let third = waiting.await.unwrap();
+
+        drop(first);
+        drop(third);
+        assert_eq!(scheduler.lock().lane(Backend::Llm).running, 0);
+    }
+}
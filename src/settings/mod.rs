@@ -0,0 +1,163 @@
+//! Loading and validating the configuration
+//!
+//! Settings are read from a TOML file, environment variables override the file.
+//! Settings that don't affect connections can be reloaded at runtime.
+
+use std::path::Path;
+
+use config::{Config, Environment, File, FileFormat};
+use tokio::signal::unix::{signal, SignalKind};
+use url::Url;
+
+use crate::{
+    invoke_ai::presets::{self, Presets},
+    limits::Limiter,
+    local_ai::{Prompts, Voices},
+    AppConfig,
+};
+
+/// Environment variable with the path of the configuration file
+const PATH_VARIABLE: &str = "APP_CONFIG_PATH";
+/// Read when it exists and no other path is given
+const DEFAULT_PATH: &str = "config.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to read the configuration: {0}")]
+    Load(#[from] config::ConfigError),
+    #[error("invalid configuration:\n{}", .0.join("\n"))]
+    Invalid(Vec<String>),
+    #[error("failed to read the configuration: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+/// Read and validate the configuration
+pub fn load() -> Result<AppConfig, Error> {
+    let file = match std::env::var(PATH_VARIABLE) {
+        Ok(path) => File::new(&path, FileFormat::Toml),
+        Err(_) => File::new(DEFAULT_PATH, FileFormat::Toml).required(false),
+    };
+
+    let config: AppConfig = Config::builder()
+        .add_source(file)
+        .add_source(
+            Environment::with_prefix("app")
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true),
+        )
+        .build()?
+        .try_deserialize()?;
+
+    let problems = problems(&config);
+    if !problems.is_empty() {
+        return Err(Error::Invalid(problems));
+    }
+
+    Ok(config)
+}
+
+fn problems(config: &AppConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if config.teloxide_token.trim().is_empty() {
+        problems.push(String::from("teloxide_token can't be empty"));
+    }
+
+    if config.sqlite_path.trim().is_empty() {
+        problems.push(String::from("sqlite_path can't be empty"));
+    }
+
+    let urls = [
+        ("invoke_ai_url", &config.invoke_ai_url),
+        ("local_ai_url", &config.local_ai_url),
+        ("ollama_url", &config.ollama_url),
+        ("llm.openai_url", &config.llm.openai_url),
+        ("searxng_url", &config.searxng_url),
+    ];
+
+    for (name, url) in urls {
+        let Some(url) = url else {
+            continue;
+        };
+
+        match Url::parse(url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => (),
+            Ok(_) => problems.push(format!("{name} must be an http or https URL")),
+            Err(err) => problems.push(format!("{name} is not a valid URL: {err}")),
+        }
+    }
+
+    if let Some(path) = &config.fact_check_path {
+        if !Path::new(path).is_dir() {
+            problems.push(format!("fact_check_path '{path}' is not a directory"));
+        }
+    }
+
+    problems.extend(config.limits.problems());
+    problems.extend(config.prompts.problems());
+    problems.extend(config.tts.problems());
+    problems.extend(config.ask.problems());
+    problems.extend(config.llm.problems());
+    problems.extend(presets::problems(&config.presets));
+
+    problems
+        .iter_mut()
+        .for_each(|problem| problem.insert_str(0, "- "));
+
+    problems
+}
+
+/// Applies the settings that can change without restarting
+#[derive(Clone)]
+pub struct Reloader {
+    limits: Limiter,
+    prompts: Prompts,
+    presets: Presets,
+    voices: Voices,
+}
+
+impl Reloader {
+    pub fn new(limits: Limiter, prompts: Prompts, presets: Presets, voices: Voices) -> Self {
+        Self {
+            limits,
+            prompts,
+            presets,
+            voices,
+        }
+    }
+
+    /// Read the configuration again and apply the limits, prompts, presets and voices
+    ///
+    /// Nothing changes when the configuration is invalid.
+    pub async fn reload(&self) -> Result<(), Error> {
+        // reading the file blocks
+        let config = tokio::task::spawn_blocking(load).await??;
+
+        self.limits.set_config(config.limits);
+        self.prompts.set_config(config.prompts);
+        self.presets.set_config(config.presets);
+        self.voices.set_config(config.tts);
+
+        log::info!("Reloaded the settings");
+
+        Ok(())
+    }
+
+    /// Reload whenever the process receives SIGHUP
+    pub async fn on_hangup(self) {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(err) => {
+                log::error!("failed to listen for SIGHUP: {err}");
+                return;
+            }
+        };
+
+        while hangups.recv().await.is_some() {
+            if let Err(err) = self.reload().await {
+                log::error!("{err}");
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use teloxide::types::ChatId;
+
+use super::{Store, UsernameProvider};
+use crate::i18n::Language;
+
+impl<U: UsernameProvider> Store<U> {
+    /// The language set per chat
+    pub async fn chat_languages(&self) -> Result<Vec<(ChatId, Language)>, anyhow::Error> {
+        let rows: Vec<(i64, String)> = sqlx::query_as(
+            r#"
+            SELECT chat_id, language
+            FROM chat_languages"#,
+        )
+        .fetch_all(&self.sqlite)
+        .await?;
+
+        let languages = rows
+            .into_iter()
+            .filter_map(|(chat_id, language)| {
+                language
+                    .parse()
+                    .inspect_err(|err| log::error!("skipping invalid language: {err}"))
+                    .ok()
+                    .map(|language| (ChatId(chat_id), language))
+            })
+            .collect();
+
+        Ok(languages)
+    }
+
+    pub async fn set_chat_language(
+        &self,
+        chat_id: ChatId,
+        language: Language,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            r#"
+        INSERT INTO chat_languages
+        (chat_id, language)
+        VALUES ($1, $2)
+        ON CONFLICT (chat_id)
+        DO UPDATE SET language = excluded.language, created_at = current_timestamp
+        "#,
+        )
+        .bind(chat_id.0)
+        .bind(language.as_str())
+        .execute(&self.sqlite)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn remove_chat_language(&self, chat_id: ChatId) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM chat_languages WHERE chat_id = $1")
+            .bind(chat_id.0)
+            .execute(&self.sqlite)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::super::tests::UsernameStore;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_chat_languages() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        store
+            .set_chat_language(ChatId(1), Language::Fr)
+            .await
+            .unwrap();
+        store
+            .set_chat_language(ChatId(1), Language::Nl)
+            .await
+            .unwrap();
+        store
+            .set_chat_language(ChatId(2), Language::En)
+            .await
+            .unwrap();
+        store.remove_chat_language(ChatId(2)).await.unwrap();
+
+        assert_eq!(
+            store.chat_languages().await.unwrap(),
+            vec![(ChatId(1), Language::Nl)]
+        );
+    }
+}
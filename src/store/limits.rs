@@ -0,0 +1,236 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::{Store, UsernameProvider};
+use crate::limits::{Bucket, Kind, Scope};
+
+impl<U: UsernameProvider> Store<U> {
+    pub async fn bucket(&self, scope: Scope, kind: Kind) -> Result<Option<Bucket>, anyhow::Error> {
+        let bucket: Option<(f64, DateTime<Utc>)> = sqlx::query_as(
+            r#"
+            SELECT tokens, updated_at
+            FROM rate_limit_buckets
+            WHERE scope = $1 AND id = $2 AND kind = $3"#,
+        )
+        .bind(scope.name())
+        .bind(scope.id())
+        .bind(kind.as_str())
+        .fetch_optional(&self.sqlite)
+        .await?;
+
+        Ok(bucket.map(|(tokens, updated_at)| Bucket { tokens, updated_at }))
+    }
+
+    pub async fn set_bucket(
+        &self,
+        scope: Scope,
+        kind: Kind,
+        bucket: Bucket,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            r#"
+        INSERT INTO rate_limit_buckets
+        (scope, id, kind, tokens, updated_at)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (scope, id, kind)
+        DO UPDATE SET tokens = excluded.tokens, updated_at = excluded.updated_at
+        "#,
+        )
+        .bind(scope.name())
+        .bind(scope.id())
+        .bind(kind.as_str())
+        .bind(bucket.tokens)
+        .bind(bucket.updated_at)
+        .execute(&self.sqlite)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Amount of requests on a given day
+    pub async fn daily_usage(
+        &self,
+        scope: Scope,
+        kind: Kind,
+        day: NaiveDate,
+    ) -> Result<u32, anyhow::Error> {
+        let count: Option<i64> = sqlx::query_scalar(
+            r#"
+            SELECT count
+            FROM daily_usage
+            WHERE scope = $1 AND id = $2 AND kind = $3 AND day = $4"#,
+        )
+        .bind(scope.name())
+        .bind(scope.id())
+        .bind(kind.as_str())
+        .bind(day)
+        .fetch_optional(&self.sqlite)
+        .await?;
+
+        Ok(count.unwrap_or_default() as u32)
+    }
+
+    pub async fn increment_daily_usage(
+        &self,
+        scope: Scope,
+        kind: Kind,
+        day: NaiveDate,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            r#"
+        INSERT INTO daily_usage
+        (scope, id, kind, day, count)
+        VALUES ($1, $2, $3, $4, 1)
+        ON CONFLICT (scope, id, kind, day)
+        DO UPDATE SET count = count + 1
+        "#,
+        )
+        .bind(scope.name())
+        .bind(scope.id())
+        .bind(kind.as_str())
+        .bind(day)
+        .execute(&self.sqlite)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use teloxide::types::{ChatId, UserId};
+
+    use super::super::tests::UsernameStore;
+    use super::*;
+    use crate::limits::{self, Error, Exceeded, Limit, Limiter};
+
+    #[tokio::test]
+    async fn test_daily_quota() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        let mut config = limits::Config::default();
+        config.image.user = Limit {
+            daily: Some(2),
+            ..Default::default()
+        };
+
+        let limiter = Limiter::new(config, store);
+
+        let remaining = limiter
+            .check(ChatId(1), UserId(1), &[Kind::Image])
+            .await
+            .unwrap();
+        assert_eq!(remaining.map(|remaining| remaining.today), Some(1));
+
+        let remaining = limiter
+            .check(ChatId(1), UserId(1), &[Kind::Image])
+            .await
+            .unwrap();
+        assert_eq!(remaining.map(|remaining| remaining.today), Some(0));
+
+        let exceeded = limiter.check(ChatId(1), UserId(1), &[Kind::Image]).await;
+        assert!(matches!(
+            exceeded,
+            Err(Error::Exceeded(Exceeded::Quota {
+                scope: Scope::User(UserId(1)),
+                ..
+            }))
+        ));
+
+        // other users and other kinds are not affected
+        assert!(limiter
+            .check(ChatId(1), UserId(2), &[Kind::Image])
+            .await
+            .is_ok());
+        assert!(limiter
+            .check(ChatId(1), UserId(1), &[Kind::Llm])
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_several_kinds() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        let mut config = limits::Config::default();
+        config.llm.user = Limit {
+            daily: Some(1),
+            ..Default::default()
+        };
+        config.web.user = Limit {
+            daily: Some(3),
+            ..Default::default()
+        };
+
+        let limiter = Limiter::new(config, store);
+
+        // the lowest quota is the one to report
+        let remaining = limiter
+            .check(ChatId(1), UserId(1), &[Kind::Llm, Kind::Web])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(remaining.kind, Kind::Llm));
+        assert_eq!(remaining.today, 0);
+
+        let exceeded = limiter
+            .check(ChatId(1), UserId(1), &[Kind::Llm, Kind::Web])
+            .await;
+        assert!(matches!(
+            exceeded,
+            Err(Error::Exceeded(Exceeded::Quota {
+                kind: Kind::Llm,
+                ..
+            }))
+        ));
+
+        // a refused request consumes none of its kinds
+        let remaining = limiter
+            .check(ChatId(1), UserId(1), &[Kind::Web])
+            .await
+            .unwrap();
+        assert_eq!(remaining.map(|remaining| remaining.today), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        let mut config = limits::Config::default();
+        config.llm.chat = Limit {
+            per_minute: Some(1.0),
+            burst: Some(2.0),
+            daily: None,
+        };
+
+        let limiter = Limiter::new(config, store);
+
+        assert!(limiter
+            .check(ChatId(1), UserId(1), &[Kind::Llm])
+            .await
+            .is_ok());
+        assert!(limiter
+            .check(ChatId(1), UserId(2), &[Kind::Llm])
+            .await
+            .is_ok());
+
+        let exceeded = limiter.check(ChatId(1), UserId(3), &[Kind::Llm]).await;
+        assert!(matches!(
+            exceeded,
+            Err(Error::Exceeded(Exceeded::RateLimited { .. }))
+        ));
+
+        assert!(limiter
+            .check(ChatId(2), UserId(3), &[Kind::Llm])
+            .await
+            .is_ok());
+    }
+}
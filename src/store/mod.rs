@@ -11,6 +11,16 @@ use teloxide::{types::ChatId, Bot};
 
 use crate::ollama;
 
+mod languages;
+mod limits;
+mod overrides;
+mod roles;
+mod rules;
+mod threads;
+mod transcripts;
+
+pub use overrides::{SystemPrompt, UserOverride};
+
 static MIGRATOR: Migrator = sqlx::migrate!();
 
 #[derive(Clone)]
@@ -157,6 +167,25 @@ impl<U: UsernameProvider> Store<U> {
         Ok(Some(chat_content))
     }
 
+    pub async fn username(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<String, anyhow::Error> {
+        self.usernames
+            .get_username(chat_id, user_id, &self.cache)
+            .await
+    }
+
+    /// Amount of stored chat messages
+    pub async fn message_count(&self) -> Result<i64, anyhow::Error> {
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM chat_messages")
+            .fetch_one(&self.sqlite)
+            .await?;
+
+        Ok(count)
+    }
+
     pub async fn store_message(&self, msg: Message) -> Result<(), anyhow::Error> {
         let user = msg
             .from()
@@ -205,7 +234,7 @@ mod tests {
 
     static ID_COUNTER: AtomicI32 = AtomicI32::new(1);
 
-    struct UsernameStore {
+    pub(super) struct UsernameStore {
         store: HashMap<UserId, String>,
     }
 
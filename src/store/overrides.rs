@@ -0,0 +1,322 @@
+use chrono::{DateTime, Utc};
+use teloxide::types::{ChatId, UserId};
+
+use super::{Store, UsernameProvider};
+
+/// Prompt that replaces every prompt of a user in a specific chat
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserOverride {
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub prompt: String,
+    pub created_at: DateTime<Utc>,
+    /// When the override gets lifted, `None` means never
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl UserOverride {
+    pub fn is_expired(&self) -> bool {
+        self.is_expired_at(Utc::now())
+    }
+
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= now)
+            .unwrap_or_default()
+    }
+}
+
+type UserOverrideRow = (i64, i64, String, DateTime<Utc>, Option<DateTime<Utc>>);
+
+impl From<UserOverrideRow> for UserOverride {
+    fn from((chat_id, user_id, prompt, created_at, expires_at): UserOverrideRow) -> Self {
+        Self {
+            chat_id: ChatId(chat_id),
+            user_id: UserId(user_id as u64),
+            prompt,
+            created_at,
+            expires_at,
+        }
+    }
+}
+
+/// Custom LLM system prompt for a specific chat
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SystemPrompt {
+    pub chat_id: ChatId,
+    pub prompt: String,
+    /// The chat uses the configured drunk prompt, `prompt` is the one it had when stored
+    pub drunk: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl<U: UsernameProvider> Store<U> {
+    /// All user overrides, across all chats
+    pub async fn user_overrides(&self) -> Result<Vec<UserOverride>, anyhow::Error> {
+        let overrides: Vec<UserOverrideRow> = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, prompt, created_at, expires_at
+            FROM user_overrides
+            WHERE expires_at IS NULL
+               OR expires_at > datetime('now')
+            ORDER BY created_at"#,
+        )
+        .fetch_all(&self.sqlite)
+        .await?;
+
+        Ok(overrides.into_iter().map(UserOverride::from).collect())
+    }
+
+    /// Create or replace the override of a user in a chat
+    ///
+    /// Without a duration, the override lasts until it's removed
+    pub async fn set_user_override(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        prompt: String,
+        duration: Option<chrono::Duration>,
+    ) -> Result<UserOverride, anyhow::Error> {
+        let (created_at, expires_at): (DateTime<Utc>, Option<DateTime<Utc>>) = sqlx::query_as(
+            r#"
+        INSERT INTO user_overrides
+        (chat_id, user_id, prompt, expires_at)
+        VALUES ($1, $2, $3, datetime('now', '+' || $4 || ' seconds'))
+        ON CONFLICT (chat_id, user_id)
+        DO UPDATE SET
+            prompt = excluded.prompt,
+            created_at = current_timestamp,
+            expires_at = excluded.expires_at
+        RETURNING created_at, expires_at
+        "#,
+        )
+        .bind(chat_id.0)
+        .bind(user_id.0 as i64)
+        .bind(&prompt)
+        .bind(duration.map(|duration| duration.num_seconds()))
+        .fetch_one(&self.sqlite)
+        .await?;
+
+        Ok(UserOverride {
+            chat_id,
+            user_id,
+            prompt,
+            created_at,
+            expires_at,
+        })
+    }
+
+    /// Remove all overrides that have expired by `now`, returns the removed overrides
+    pub async fn remove_expired_user_overrides(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<UserOverride>, anyhow::Error> {
+        let expired: Vec<UserOverrideRow> = sqlx::query_as(
+            r#"
+        DELETE FROM user_overrides
+        WHERE expires_at <= datetime($1)
+        RETURNING chat_id, user_id, prompt, created_at, expires_at
+        "#,
+        )
+        .bind(now)
+        .fetch_all(&self.sqlite)
+        .await?;
+
+        Ok(expired.into_iter().map(UserOverride::from).collect())
+    }
+
+    pub async fn remove_user_override(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM user_overrides WHERE chat_id = $1 AND user_id = $2")
+            .bind(chat_id.0)
+            .bind(user_id.0 as i64)
+            .execute(&self.sqlite)
+            .await?;
+
+        Ok(())
+    }
+
+    /// All custom system prompts, across all chats
+    pub async fn system_prompts(&self) -> Result<Vec<SystemPrompt>, anyhow::Error> {
+        let prompts: Vec<(i64, String, bool, DateTime<Utc>)> = sqlx::query_as(
+            r#"
+            SELECT chat_id, prompt, drunk, created_at
+            FROM system_prompts"#,
+        )
+        .fetch_all(&self.sqlite)
+        .await?;
+
+        Ok(prompts
+            .into_iter()
+            .map(|(chat_id, prompt, drunk, created_at)| SystemPrompt {
+                chat_id: ChatId(chat_id),
+                prompt,
+                drunk,
+                created_at,
+            })
+            .collect())
+    }
+
+    /// Create or replace the custom system prompt of a chat
+    pub async fn set_system_prompt(
+        &self,
+        chat_id: ChatId,
+        prompt: String,
+        drunk: bool,
+    ) -> Result<SystemPrompt, anyhow::Error> {
+        let created_at: DateTime<Utc> = sqlx::query_scalar(
+            r#"
+        INSERT INTO system_prompts
+        (chat_id, prompt, drunk)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (chat_id)
+        DO UPDATE SET
+            prompt = excluded.prompt,
+            drunk = excluded.drunk,
+            created_at = current_timestamp
+        RETURNING created_at
+        "#,
+        )
+        .bind(chat_id.0)
+        .bind(&prompt)
+        .bind(drunk)
+        .fetch_one(&self.sqlite)
+        .await?;
+
+        Ok(SystemPrompt {
+            chat_id,
+            prompt,
+            drunk,
+            created_at,
+        })
+    }
+
+    pub async fn remove_system_prompt(&self, chat_id: ChatId) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM system_prompts WHERE chat_id = $1")
+            .bind(chat_id.0)
+            .execute(&self.sqlite)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::super::tests::UsernameStore;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_user_overrides() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        store
+            .set_user_override(ChatId(1), UserId(1), "a clown".into(), None)
+            .await
+            .unwrap();
+        store
+            .set_user_override(ChatId(2), UserId(1), "a mime".into(), None)
+            .await
+            .unwrap();
+        // replaces the existing override
+        store
+            .set_user_override(ChatId(1), UserId(1), "a juggler".into(), None)
+            .await
+            .unwrap();
+
+        let overrides = store.user_overrides().await.unwrap();
+        assert_eq!(overrides.len(), 2);
+        assert!(overrides
+            .iter()
+            .any(|o| o.chat_id == ChatId(1) && o.prompt == "a juggler"));
+
+        store
+            .remove_user_override(ChatId(1), UserId(1))
+            .await
+            .unwrap();
+
+        let overrides = store.user_overrides().await.unwrap();
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[0].chat_id, ChatId(2));
+    }
+
+    #[tokio::test]
+    async fn test_expired_user_overrides() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        let expiring = store
+            .set_user_override(
+                ChatId(1),
+                UserId(1),
+                "a clown".into(),
+                Some(chrono::Duration::seconds(1)),
+            )
+            .await
+            .unwrap();
+        assert!(expiring.expires_at.is_some());
+        assert!(!expiring.is_expired());
+
+        store
+            .set_user_override(
+                ChatId(1),
+                UserId(2),
+                "a mime".into(),
+                Some(chrono::Duration::hours(1)),
+            )
+            .await
+            .unwrap();
+
+        assert!(store
+            .remove_expired_user_overrides(Utc::now())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // the expiry is wall clock time, so move the clock instead of waiting
+        let later = Utc::now() + chrono::Duration::seconds(2);
+        assert!(expiring.is_expired_at(later));
+
+        let expired = store.remove_expired_user_overrides(later).await.unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].user_id, UserId(1));
+
+        let overrides = store.user_overrides().await.unwrap();
+        assert_eq!(overrides.len(), 1);
+        assert_eq!(overrides[0].user_id, UserId(2));
+    }
+
+    #[tokio::test]
+    async fn test_system_prompts() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        store
+            .set_system_prompt(ChatId(1), "be drunk".into(), true)
+            .await
+            .unwrap();
+        assert!(store.system_prompts().await.unwrap()[0].drunk);
+
+        store
+            .set_system_prompt(ChatId(1), "be sober".into(), false)
+            .await
+            .unwrap();
+
+        let prompts = store.system_prompts().await.unwrap();
+        assert_eq!(prompts.len(), 1);
+        assert_eq!(prompts[0].prompt, "be sober");
+        assert!(!prompts[0].drunk);
+
+        store.remove_system_prompt(ChatId(1)).await.unwrap();
+        assert!(store.system_prompts().await.unwrap().is_empty());
+    }
+}
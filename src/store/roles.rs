@@ -0,0 +1,93 @@
+use teloxide::types::{ChatId, UserId};
+
+use super::{Store, UsernameProvider};
+use crate::roles::Role;
+
+impl<U: UsernameProvider> Store<U> {
+    /// All explicitly granted roles, across all chats
+    pub async fn roles(&self) -> Result<Vec<(ChatId, UserId, Role)>, anyhow::Error> {
+        let rows: Vec<(i64, i64, String)> = sqlx::query_as(
+            r#"
+            SELECT chat_id, user_id, role
+            FROM roles"#,
+        )
+        .fetch_all(&self.sqlite)
+        .await?;
+
+        let roles = rows
+            .into_iter()
+            .filter_map(|(chat_id, user_id, role)| {
+                role.parse()
+                    .inspect_err(|err| log::error!("skipping invalid role: {err}"))
+                    .ok()
+                    .map(|role| (ChatId(chat_id), UserId(user_id as u64), role))
+            })
+            .collect();
+
+        Ok(roles)
+    }
+
+    /// Grant a role to a user in a chat, replacing their previous role
+    pub async fn set_role(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        role: Role,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            r#"
+        INSERT INTO roles
+        (chat_id, user_id, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (chat_id, user_id)
+        DO UPDATE SET role = excluded.role, created_at = current_timestamp
+        "#,
+        )
+        .bind(chat_id.0)
+        .bind(user_id.0 as i64)
+        .bind(role.as_str())
+        .execute(&self.sqlite)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::super::tests::UsernameStore;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roles() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        store
+            .set_role(ChatId(1), UserId(1), Role::Moderator)
+            .await
+            .unwrap();
+        store
+            .set_role(ChatId(1), UserId(1), Role::Banned)
+            .await
+            .unwrap();
+        store
+            .set_role(ChatId(2), UserId(1), Role::Admin)
+            .await
+            .unwrap();
+
+        let mut roles = store.roles().await.unwrap();
+        roles.sort_by_key(|(chat_id, _, _)| chat_id.0);
+
+        assert_eq!(
+            roles,
+            vec![
+                (ChatId(1), UserId(1), Role::Banned),
+                (ChatId(2), UserId(1), Role::Admin)
+            ]
+        );
+    }
+}
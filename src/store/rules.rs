@@ -0,0 +1,160 @@
+use teloxide::types::{ChatId, UserId};
+
+use super::{Store, UsernameProvider};
+use crate::rules::{Action, Condition, Rule, Window};
+
+type RuleRow = (
+    i64,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+    Option<f64>,
+    Option<String>,
+    Option<String>,
+    String,
+    String,
+);
+
+fn parse_rule(row: RuleRow) -> Result<Rule, String> {
+    let (id, chat_id, user_id, family, probability, starts_at, ends_at, action, argument) = row;
+
+    let window = match (starts_at, ends_at) {
+        (Some(start), Some(end)) => Some(Window {
+            start: Window::parse_time(&start)?,
+            end: Window::parse_time(&end)?,
+        }),
+        _ => None,
+    };
+
+    Ok(Rule {
+        id,
+        condition: Condition {
+            chat_id: chat_id.map(ChatId),
+            user_id: user_id.map(|id| UserId(id as u64)),
+            family: family.map(|family| family.parse()).transpose()?,
+            probability,
+            window,
+        },
+        action: Action::from_parts(&action, &argument)?,
+    })
+}
+
+impl<U: UsernameProvider> Store<U> {
+    /// All rules, in order of creation
+    pub async fn rules(&self) -> Result<Vec<Rule>, anyhow::Error> {
+        let rows: Vec<RuleRow> = sqlx::query_as(
+            r#"
+            SELECT id, chat_id, user_id, family, probability, starts_at, ends_at, action, argument
+            FROM rules
+            ORDER BY id"#,
+        )
+        .fetch_all(&self.sqlite)
+        .await?;
+
+        let rules = rows
+            .into_iter()
+            .filter_map(|row| {
+                let id = row.0;
+                parse_rule(row)
+                    .inspect_err(|err| log::error!("skipping invalid rule #{id}: {err}"))
+                    .ok()
+            })
+            .collect();
+
+        Ok(rules)
+    }
+
+    pub async fn insert_rule(
+        &self,
+        condition: Condition,
+        action: Action,
+    ) -> Result<Rule, anyhow::Error> {
+        let id: i64 = sqlx::query_scalar(
+            r#"
+        INSERT INTO rules
+        (chat_id, user_id, family, probability, starts_at, ends_at, action, argument)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id
+        "#,
+        )
+        .bind(condition.chat_id.map(|id| id.0))
+        .bind(condition.user_id.map(|id| id.0 as i64))
+        .bind(condition.family.map(|family| family.as_str()))
+        .bind(condition.probability)
+        .bind(condition.window.map(|w| Window::format_time(w.start)))
+        .bind(condition.window.map(|w| Window::format_time(w.end)))
+        .bind(action.kind())
+        .bind(action.argument())
+        .fetch_one(&self.sqlite)
+        .await?;
+
+        Ok(Rule {
+            id,
+            condition,
+            action,
+        })
+    }
+
+    /// Delete a rule, only when it belongs to `chat_id` if one is given, returns whether it
+    /// was deleted
+    pub async fn remove_rule(
+        &self,
+        id: i64,
+        chat_id: Option<ChatId>,
+    ) -> Result<bool, anyhow::Error> {
+        let result =
+            sqlx::query("DELETE FROM rules WHERE id = $1 AND ($2 IS NULL OR chat_id = $2)")
+                .bind(id)
+                .bind(chat_id.map(|id| id.0))
+                .execute(&self.sqlite)
+                .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::super::tests::UsernameStore;
+    use super::*;
+    use crate::rules::{Family, Style};
+
+    #[tokio::test]
+    async fn test_rules() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        // the migrations seed the formerly hardcoded rules
+        let seeded = store.rules().await.unwrap().len();
+
+        let (condition, action) = Rule::parse(
+            "family=image chance=25% time=22:00-06:00 style lego",
+            ChatId(-5),
+            Some(UserId(3)),
+        )
+        .unwrap();
+
+        let inserted = store.insert_rule(condition, action).await.unwrap();
+        assert_eq!(inserted.condition.family, Some(Family::Image));
+        assert_eq!(inserted.action, Action::Style(Style::Lego));
+
+        let rules = store.rules().await.unwrap();
+        assert_eq!(rules.len(), seeded + 1);
+        assert_eq!(rules.last(), Some(&inserted));
+
+        // other chats can't remove it
+        assert!(!store
+            .remove_rule(inserted.id, Some(ChatId(-6)))
+            .await
+            .unwrap());
+        assert!(store
+            .remove_rule(inserted.id, Some(ChatId(-5)))
+            .await
+            .unwrap());
+        assert!(!store.remove_rule(inserted.id, None).await.unwrap());
+        assert_eq!(store.rules().await.unwrap().len(), seeded);
+    }
+}
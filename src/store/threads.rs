@@ -0,0 +1,152 @@
+use teloxide::types::{ChatId, MessageId};
+
+use super::{Store, UsernameProvider};
+use crate::local_ai::{Message, Role};
+
+impl<U: UsernameProvider> Store<U> {
+    /// The thread that ends with the given message, oldest message first
+    pub async fn thread(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            r#"
+            WITH RECURSIVE thread (message_id, parent_id, role, content, depth) AS (
+                SELECT message_id, parent_id, role, content, 1
+                FROM llm_threads
+                WHERE chat_id = $1 AND message_id = $2
+                UNION ALL
+                SELECT llm_threads.message_id, llm_threads.parent_id, llm_threads.role, llm_threads.content, thread.depth + 1
+                FROM llm_threads
+                JOIN thread ON llm_threads.message_id = thread.parent_id
+                WHERE llm_threads.chat_id = $1 AND thread.depth < $3
+            )
+            SELECT role, content
+            FROM thread
+            ORDER BY depth DESC"#,
+        )
+        .bind(chat_id.0)
+        .bind(message_id.0)
+        .bind(limit as i64)
+        .fetch_all(&self.sqlite)
+        .await?;
+
+        let messages = rows
+            .into_iter()
+            .filter_map(|(role, content)| {
+                role.parse::<Role>()
+                    .inspect_err(|err| log::error!("skipping thread message: {err}"))
+                    .ok()
+                    .map(|role| Message { role, content })
+            })
+            .collect();
+
+        Ok(messages)
+    }
+
+    /// Add a message to a thread, `parent` is the message it replies to
+    pub async fn add_to_thread(
+        &self,
+        chat_id: ChatId,
+        message_id: MessageId,
+        parent: Option<MessageId>,
+        message: &Message,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            r#"
+        INSERT INTO llm_threads
+        (chat_id, message_id, parent_id, role, content)
+        VALUES ($1, $2, $3, $4, $5)
+        ON CONFLICT (chat_id, message_id)
+        DO UPDATE SET parent_id = excluded.parent_id, role = excluded.role, content = excluded.content
+        "#,
+        )
+        .bind(chat_id.0)
+        .bind(message_id.0)
+        .bind(parent.map(|parent| parent.0))
+        .bind(message.role.as_str())
+        .bind(&message.content)
+        .execute(&self.sqlite)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::super::tests::UsernameStore;
+    use super::*;
+
+    fn message(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_thread() {
+        let store = Store::new_in_memory(UsernameStore::new(HashMap::new()))
+            .await
+            .unwrap();
+
+        let chat = ChatId(1);
+        let thread = [
+            (MessageId(1), None, message(Role::User, "hi")),
+            (
+                MessageId(2),
+                Some(MessageId(1)),
+                message(Role::Assistant, "hello"),
+            ),
+            (
+                MessageId(3),
+                Some(MessageId(2)),
+                message(Role::User, "how are you?"),
+            ),
+            (
+                MessageId(4),
+                Some(MessageId(3)),
+                message(Role::Assistant, "fine"),
+            ),
+            // another branch of the same thread
+            (MessageId(5), Some(MessageId(2)), message(Role::User, "bye")),
+        ];
+
+        for (message_id, parent, message) in thread.iter() {
+            store
+                .add_to_thread(chat, *message_id, *parent, message)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            store.thread(chat, MessageId(4), 10).await.unwrap(),
+            vec![
+                message(Role::User, "hi"),
+                message(Role::Assistant, "hello"),
+                message(Role::User, "how are you?"),
+                message(Role::Assistant, "fine"),
+            ]
+        );
+
+        // only the most recent messages
+        assert_eq!(
+            store.thread(chat, MessageId(5), 2).await.unwrap(),
+            vec![
+                message(Role::Assistant, "hello"),
+                message(Role::User, "bye")
+            ]
+        );
+
+        assert!(store
+            .thread(ChatId(2), MessageId(4), 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
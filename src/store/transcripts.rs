@@ -0,0 +1,106 @@
+use teloxide::types::{ChatId, MessageId, UserId};
+
+use super::{Store, UsernameProvider};
+
+impl<U: UsernameProvider> Store<U> {
+    /// Store the transcript of a voice message as its text, so it shows up in the chat history
+    pub async fn store_transcript(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        message_id: MessageId,
+        transcript: &str,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            r#"
+        INSERT INTO chat_messages
+        (chat_id, user_id, message_id, message)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (chat_id, user_id, message_id)
+        DO UPDATE SET message = excluded.message
+        "#,
+        )
+        .bind(chat_id.0)
+        .bind(user_id.0 as i64)
+        .bind(message_id.0)
+        .bind(transcript)
+        .execute(&self.sqlite)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Chats whose voice messages are transcribed automatically
+    pub async fn auto_transcribed_chats(&self) -> Result<Vec<ChatId>, anyhow::Error> {
+        let chats: Vec<i64> = sqlx::query_scalar("SELECT chat_id FROM auto_transcription")
+            .fetch_all(&self.sqlite)
+            .await?;
+
+        Ok(chats.into_iter().map(ChatId).collect())
+    }
+
+    pub async fn set_auto_transcription(
+        &self,
+        chat_id: ChatId,
+        enabled: bool,
+    ) -> Result<(), anyhow::Error> {
+        let query = if enabled {
+            "INSERT INTO auto_transcription (chat_id) VALUES ($1) ON CONFLICT (chat_id) DO NOTHING"
+        } else {
+            "DELETE FROM auto_transcription WHERE chat_id = $1"
+        };
+
+        sqlx::query(query)
+            .bind(chat_id.0)
+            .execute(&self.sqlite)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::UsernameStore;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_transcripts() {
+        let store = Store::new_in_memory(UsernameStore::new([(
+            UserId(1),
+            String::from("@don_johnson"),
+        )]))
+        .await
+        .unwrap();
+
+        store
+            .store_transcript(ChatId(1), UserId(1), MessageId(7), "first try")
+            .await
+            .unwrap();
+        store
+            .store_transcript(
+                ChatId(1),
+                UserId(1),
+                MessageId(7),
+                "hello from a voice note",
+            )
+            .await
+            .unwrap();
+
+        let history = store.chat_history(ChatId(1)).await.unwrap().unwrap();
+        assert!(history.ends_with("@don_johnson: hello from a voice note\n"));
+
+        store.set_auto_transcription(ChatId(1), true).await.unwrap();
+        store.set_auto_transcription(ChatId(1), true).await.unwrap();
+        store.set_auto_transcription(ChatId(2), true).await.unwrap();
+        store
+            .set_auto_transcription(ChatId(2), false)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.auto_transcribed_chats().await.unwrap(),
+            vec![ChatId(1)]
+        );
+    }
+}
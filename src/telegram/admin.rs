@@ -1,47 +1,258 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, fmt::Write, sync::Arc};
 
 use teloxide::{
     macros::BotCommands,
     requests::Requester,
-    types::{Message, User, UserId},
+    types::{ChatId, Message, User, UserId},
+    Bot,
 };
 use tokio::sync::RwLock;
 
-use super::Context;
+use super::{help::Help, Context};
+use crate::i18n::{Language, Locales, Text};
+use crate::roles::Role;
+use crate::rules::Rule;
+use crate::store::{Store, UserOverride};
 
 #[derive(BotCommands, Clone, Debug)]
 #[command(rename_rule = "lowercase", description = "Admin only commands")]
 pub enum AdminCommands {
-    #[command(description = "Give someone the clowns")]
-    Clown,
+    #[command(description = "Give someone the clowns, optionally for a while")]
+    Clown(String),
     #[command(description = "Remove the clowns from someone")]
     UnClown,
-    #[command(description = "set the LLM to drunk mode")]
+    #[command(description = "Set the LLM to drunk mode")]
     DrunkLlm,
-    #[command(description = "revert the LLM prompt to default")]
+    #[command(description = "Revert the LLM prompt to default")]
     DefaultLlm,
     #[command(description = "Use a custom LLM system prompt")]
     CustomLlm(String),
+    #[command(description = "List the active overrides in this chat")]
+    Overrides,
+    #[command(description = "Add a rule that rewrites, restyles or refuses commands")]
+    AddRule(String),
+    #[command(description = "List the rules that apply in this chat")]
+    Rules,
+    #[command(description = "Remove a rule by its number")]
+    DelRule(String),
+    #[command(description = "Grant a role to the user you reply to")]
+    Role(String),
+    #[command(description = "List the granted roles in this chat")]
+    Roles,
+    #[command(description = "Reload the limits, prompts, presets and voices")]
+    Reload,
+    #[command(description = "Set the language of the replies in this chat")]
+    Language(String),
+    #[command(description = "Write out every voice message in this chat")]
+    AutoTranscribe(String),
 }
 
-#[derive(Clone, Default)]
+/// Prompt overrides per user, per chat
+///
+/// Every change is persisted, so the overrides survive restarts
+#[derive(Clone)]
 pub struct Overrides {
-    overrides: Arc<RwLock<HashMap<UserId, &'static str>>>,
+    overrides: Arc<RwLock<HashMap<(ChatId, UserId), UserOverride>>>,
+    store: Store,
 }
 
 impl Overrides {
+    /// Load the persisted overrides
+    pub async fn load(store: Store) -> Result<Self, anyhow::Error> {
+        let overrides = store
+            .user_overrides()
+            .await?
+            .into_iter()
+            .map(|entry| ((entry.chat_id, entry.user_id), entry))
+            .collect();
+
+        Ok(Self {
+            overrides: Arc::new(RwLock::new(overrides)),
+            store,
+        })
+    }
+
     /// Get the override for a user if there exists one
-    pub(crate) async fn get_override(&self, user_id: UserId) -> Option<&'static str> {
-        self.overrides.read().await.get(&user_id).copied()
+    pub(crate) async fn get_override(&self, chat_id: ChatId, user_id: UserId) -> Option<String> {
+        self.overrides
+            .read()
+            .await
+            .get(&(chat_id, user_id))
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.prompt.clone())
+    }
+
+    pub(crate) async fn set_override(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+        prompt: impl Into<String>,
+        duration: Option<chrono::Duration>,
+    ) -> Result<UserOverride, anyhow::Error> {
+        let entry = self
+            .store
+            .set_user_override(chat_id, user_id, prompt.into(), duration)
+            .await?;
+
+        self.overrides
+            .write()
+            .await
+            .insert((chat_id, user_id), entry.clone());
+
+        Ok(entry)
+    }
+
+    pub(crate) async fn remove_override(
+        &self,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<(), anyhow::Error> {
+        self.store.remove_user_override(chat_id, user_id).await?;
+        self.overrides.write().await.remove(&(chat_id, user_id));
+
+        Ok(())
+    }
+
+    /// All overrides in a chat, oldest first
+    pub(crate) async fn in_chat(&self, chat_id: ChatId) -> Vec<UserOverride> {
+        let mut overrides: Vec<UserOverride> = self
+            .overrides
+            .read()
+            .await
+            .values()
+            .filter(|entry| entry.chat_id == chat_id && !entry.is_expired())
+            .cloned()
+            .collect();
+
+        overrides.sort_by_key(|entry| entry.created_at);
+
+        overrides
+    }
+
+    /// Lift all expired overrides, returns the lifted overrides
+    async fn remove_expired(&self) -> Result<Vec<UserOverride>, anyhow::Error> {
+        let expired = self
+            .store
+            .remove_expired_user_overrides(chrono::Utc::now())
+            .await?;
+
+        let mut overrides = self.overrides.write().await;
+        for entry in expired.iter() {
+            overrides.remove(&(entry.chat_id, entry.user_id));
+        }
+
+        Ok(expired)
+    }
+}
+
+/// Periodically lift expired overrides and announce it in their chat
+pub async fn expire_overrides(overrides: Overrides, bot: Bot, locales: Locales) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
+
+    loop {
+        interval.tick().await;
+
+        let expired = match overrides.remove_expired().await {
+            Ok(expired) => expired,
+            Err(err) => {
+                log::error!("failed to remove expired overrides: {err}");
+                continue;
+            }
+        };
+
+        for entry in expired {
+            let username = overrides
+                .store
+                .username(entry.chat_id, entry.user_id)
+                .await
+                .unwrap_or_else(|_| format!("UserId({})", entry.user_id));
+
+            log::info!(
+                "Override expired for '{username}', UserId({}), ChatId({})",
+                entry.user_id,
+                entry.chat_id
+            );
+
+            let language = locales.chat(entry.chat_id).await;
+
+            bot.send_message(
+                entry.chat_id,
+                Text::SentenceServed(username).translate(language),
+            )
+            .await
+            .inspect_err(|err| log::error!("failed to announce expired override: {err}"))
+            .ok();
+        }
+    }
+}
+
+/// Arguments of the clown command: `[duration] [prompt]`
+#[derive(Debug, PartialEq, Eq)]
+struct Sentence {
+    duration: Option<chrono::Duration>,
+    prompt: String,
+}
+
+impl Sentence {
+    const DEFAULT_PROMPT: &'static str = "a silly homeless drunk clown";
+
+    fn parse(args: &str) -> Self {
+        let args = args.trim();
+
+        let (duration, prompt) = match args.split_once(char::is_whitespace) {
+            Some((first, rest)) => match parse_duration(first) {
+                Some(duration) => (Some(duration), rest.trim()),
+                None => (None, args),
+            },
+            None => match parse_duration(args) {
+                Some(duration) => (Some(duration), ""),
+                None => (None, args),
+            },
+        };
+
+        let prompt = match prompt {
+            "" => Self::DEFAULT_PROMPT.to_string(),
+            prompt => prompt.to_string(),
+        };
+
+        Self { duration, prompt }
     }
+}
+
+/// Parse durations such as `30m`, `2h` or `1d12h`
+fn parse_duration(input: &str) -> Option<chrono::Duration> {
+    /// A year should be plenty for any sentence
+    const MAX_SECONDS: i64 = 365 * 24 * 60 * 60;
+
+    let mut total: i64 = 0;
+    let mut amount = String::new();
 
-    pub(crate) async fn set_override(&self, user_id: UserId, prompt: &'static str) {
-        self.overrides.write().await.insert(user_id, prompt);
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            amount.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+
+        let value: i64 = amount.parse().ok()?;
+        amount.clear();
+
+        total = total.checked_add(value.checked_mul(unit)?)?;
     }
 
-    pub(crate) async fn remove_override(&self, user_id: UserId) {
-        self.overrides.write().await.remove(&user_id);
+    if !amount.is_empty() || total == 0 || total > MAX_SECONDS {
+        return None;
     }
+
+    Some(chrono::Duration::seconds(total))
 }
 
 pub async fn handler(
@@ -49,9 +260,12 @@ pub async fn handler(
     msg: Message,
     cmd: AdminCommands,
     overrides: Overrides,
+    help: Help,
 ) -> Result<(), teloxide::RequestError> {
+    let language = ctx.locale(&msg).await;
+
     match cmd {
-        AdminCommands::Clown => {
+        AdminCommands::Clown(args) => {
             let target_user = match target_user(&msg) {
                 Some(id) => id,
                 None => {
@@ -64,16 +278,34 @@ pub async fn handler(
                 .mention()
                 .unwrap_or_else(|| target_user.full_name());
 
+            let Sentence { duration, prompt } = Sentence::parse(&args);
+
             log::info!(
-                "Added clownmode for '{username}', UserId({})",
-                target_user.id
+                "Added clownmode for '{username}', UserId({}), ChatId({}), Duration({duration:?})",
+                target_user.id,
+                msg.chat.id
             );
-            overrides
-                .set_override(target_user.id, "a silly homeless drunk clown")
-                .await;
+            let entry = match overrides
+                .set_override(msg.chat.id, target_user.id, prompt, duration)
+                .await
+            {
+                Ok(entry) => entry,
+                Err(err) => {
+                    log::error!("failed to store override: {err}");
+                    ctx.reply(&msg, Text::ClownFailed).await;
+                    return Ok(());
+                }
+            };
+
+            let announcement = Text::Clowned {
+                username,
+                until: entry
+                    .expires_at
+                    .map(|expires_at| expires_at.format(TIME_FORMAT).to_string()),
+            };
 
             ctx.bot
-                .send_message(msg.chat.id, format!("{username} has been clowned"))
+                .send_message(msg.chat.id, announcement.translate(language))
                 .await?;
         }
         AdminCommands::UnClown => {
@@ -89,23 +321,260 @@ pub async fn handler(
                 .mention()
                 .unwrap_or_else(|| target_user.full_name());
             log::info!(
-                "Removed clownmode from '{username}', UserId({})",
-                target_user.id
+                "Removed clownmode from '{username}', UserId({}), ChatId({})",
+                target_user.id,
+                msg.chat.id
             );
-            overrides.remove_override(target_user.id).await;
+            if let Err(err) = overrides.remove_override(msg.chat.id, target_user.id).await {
+                log::error!("failed to remove override: {err}");
+                ctx.reply(&msg, Text::UnclownFailed).await;
+                return Ok(());
+            }
 
             ctx.bot
-                .send_message(msg.chat.id, format!("{username} has been unclowned"))
+                .send_message(msg.chat.id, Text::Unclowned(username).translate(language))
                 .await?;
         }
         AdminCommands::DefaultLlm => {
-            ctx.prompts.reset().await;
+            if let Err(err) = ctx.prompts.reset(msg.chat.id).await {
+                log::error!("failed to reset system prompt: {err}");
+                ctx.reply(&msg, Text::PromptResetFailed).await;
+            }
         }
         AdminCommands::CustomLlm(prompt) => {
-            ctx.prompts.overwrite_prompt(prompt).await;
+            if let Err(err) = ctx.prompts.overwrite_prompt(msg.chat.id, prompt).await {
+                log::error!("failed to store system prompt: {err}");
+                ctx.reply(&msg, Text::PromptSetFailed).await;
+            }
         }
         AdminCommands::DrunkLlm => {
-            ctx.prompts.overwrite_to_drunk().await;
+            if let Err(err) = ctx.prompts.overwrite_to_drunk(msg.chat.id).await {
+                log::error!("failed to store system prompt: {err}");
+                ctx.reply(&msg, Text::DrunkFailed).await;
+            }
+        }
+        AdminCommands::Reload => {
+            let Some(user) = msg.from() else {
+                return Ok(());
+            };
+
+            // the settings apply to every chat
+            if ctx.roles.role(msg.chat.id, user.id).await < Role::Owner {
+                ctx.reply(&msg, Text::OwnerOnlyReload).await;
+                return Ok(());
+            }
+
+            let reply = match ctx.reloader.reload().await {
+                Ok(()) => Text::Reloaded.translate(language),
+                Err(err) => {
+                    log::error!("{err}");
+                    err.to_string()
+                }
+            };
+
+            ctx.quick_reply(&msg, reply).await;
+        }
+        AdminCommands::Overrides => {
+            let report = overrides_report(&ctx, &overrides, msg.chat.id, language).await;
+            ctx.quick_reply(&msg, report).await;
+        }
+        AdminCommands::AddRule(args) => {
+            let user_id = target_user(&msg).map(|user| user.id);
+
+            let (condition, action) = match Rule::parse(&args, msg.chat.id, user_id) {
+                Ok(rule) => rule,
+                Err(err) => {
+                    ctx.reply(&msg, Text::InvalidRule(err.to_string())).await;
+                    return Ok(());
+                }
+            };
+
+            // chat admins only decide over their own chat
+            if !condition.only_in(msg.chat.id) && !is_owner(&ctx, &msg).await {
+                ctx.reply(&msg, Text::OwnerOnlyRuleScope).await;
+                return Ok(());
+            }
+
+            match ctx.rules.add(condition, action).await {
+                Ok(rule) => {
+                    log::info!("Added rule {rule}");
+                    ctx.reply(&msg, Text::RuleAdded(rule.to_string())).await;
+                }
+                Err(err) => {
+                    log::error!("failed to store rule: {err}");
+                    ctx.reply(&msg, Text::RuleAddFailed).await;
+                }
+            }
+        }
+        AdminCommands::Rules => {
+            let rules = ctx.rules.in_chat(msg.chat.id).await;
+
+            let report = if rules.is_empty() {
+                Text::NoRules.translate(language)
+            } else {
+                rules
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            };
+
+            ctx.quick_reply(&msg, report).await;
+        }
+        AdminCommands::DelRule(id) => {
+            let Ok(id) = id.trim().trim_start_matches('#').parse::<i64>() else {
+                ctx.reply(&msg, Text::MissingRuleNumber).await;
+                return Ok(());
+            };
+
+            // the owner removes rules of every chat
+            let chat_id = match is_owner(&ctx, &msg).await {
+                true => None,
+                false => Some(msg.chat.id),
+            };
+
+            match ctx.rules.remove(id, chat_id).await {
+                Ok(true) => {
+                    log::info!("Removed rule #{id}");
+                    ctx.reply(&msg, Text::RuleRemoved(id)).await;
+                }
+                Ok(false) => ctx.reply(&msg, Text::UnknownRule(id)).await,
+                Err(err) => {
+                    log::error!("failed to remove rule: {err}");
+                    ctx.reply(&msg, Text::RuleRemoveFailed).await;
+                }
+            }
+        }
+        AdminCommands::Role(role) => {
+            let Some(target_user) = target_user(&msg) else {
+                ctx.reply(&msg, Text::ReplyToGrant).await;
+                return Ok(());
+            };
+
+            let role: Role = match role.trim().to_lowercase().parse() {
+                Ok(role) => role,
+                Err(_) => {
+                    ctx.reply(&msg, Text::UnknownRole(role.trim().to_string()))
+                        .await;
+                    return Ok(());
+                }
+            };
+
+            let Some(granter) = msg.from() else {
+                return Ok(());
+            };
+
+            let own_role = ctx.roles.role(msg.chat.id, granter.id).await;
+            let current_role = ctx.roles.role(msg.chat.id, target_user.id).await;
+
+            // nobody can grant a role equal to their own, or change the role of their equals
+            if role >= own_role || current_role >= own_role {
+                ctx.reply(&msg, Text::NotAllowed(own_role)).await;
+                return Ok(());
+            }
+
+            let username = target_user
+                .mention()
+                .unwrap_or_else(|| target_user.full_name());
+
+            if let Err(err) = ctx.roles.set_role(msg.chat.id, target_user.id, role).await {
+                log::error!("failed to store role: {err}");
+                ctx.reply(&msg, Text::GrantFailed).await;
+                return Ok(());
+            }
+
+            help.register_member(&ctx.bot, msg.chat.id, target_user.id, role, &ctx.roles)
+                .await;
+
+            log::info!(
+                "Granted role {role} to '{username}', UserId({}), ChatId({})",
+                target_user.id,
+                msg.chat.id
+            );
+
+            ctx.bot
+                .send_message(
+                    msg.chat.id,
+                    Text::Granted { username, role }.translate(language),
+                )
+                .await?;
+        }
+        AdminCommands::Roles => {
+            let mut report = String::new();
+
+            for (user_id, role) in ctx.roles.in_chat(msg.chat.id).await {
+                let username = ctx
+                    .store
+                    .username(msg.chat.id, user_id)
+                    .await
+                    .unwrap_or_else(|_| format!("UserId({user_id})"));
+
+                writeln!(report, "{username}: {role}").ok();
+            }
+
+            if report.is_empty() {
+                report.push_str(&Text::NoRoles.translate(language));
+            }
+
+            ctx.quick_reply(&msg, report).await;
+        }
+        AdminCommands::Language(code) => {
+            let language = match code.trim().to_lowercase().as_str() {
+                "auto" => None,
+                code => match code.parse::<Language>() {
+                    Ok(language) => Some(language),
+                    Err(_) => {
+                        ctx.reply(&msg, Text::UnknownLanguage(code.to_string()))
+                            .await;
+                        return Ok(());
+                    }
+                },
+            };
+
+            if let Err(err) = ctx.locales.set(msg.chat.id, language).await {
+                log::error!("failed to store language: {err}");
+                ctx.reply(&msg, Text::LanguageFailed).await;
+                return Ok(());
+            }
+
+            log::info!("Set language {language:?}, ChatId({})", msg.chat.id);
+
+            let reply = match language {
+                Some(language) => Text::LanguageSet(language),
+                None => Text::LanguageFollowsUsers,
+            };
+            ctx.reply(&msg, reply).await;
+        }
+        AdminCommands::AutoTranscribe(toggle) => {
+            if !ctx.transcription.available() {
+                ctx.reply(&msg, Text::TranscriptionUnavailable).await;
+                return Ok(());
+            }
+
+            let enabled = match toggle.trim().to_lowercase().as_str() {
+                "on" => true,
+                "off" => false,
+                other => {
+                    ctx.reply(&msg, Text::UnknownToggle(other.to_string()))
+                        .await;
+                    return Ok(());
+                }
+            };
+
+            if let Err(err) = ctx.transcription.set(msg.chat.id, enabled).await {
+                log::error!("failed to store auto transcription: {err}");
+                ctx.reply(&msg, Text::AutoTranscribeFailed).await;
+                return Ok(());
+            }
+
+            log::info!("Set auto transcription {enabled}, ChatId({})", msg.chat.id);
+
+            let reply = if enabled {
+                Text::AutoTranscribeOn
+            } else {
+                Text::AutoTranscribeOff
+            };
+            ctx.reply(&msg, reply).await;
         }
     };
 
@@ -115,3 +584,115 @@ pub async fn handler(
 fn target_user(msg: &Message) -> Option<&User> {
     msg.reply_to_message()?.from()
 }
+
+async fn is_owner(ctx: &Context, msg: &Message) -> bool {
+    match msg.from() {
+        Some(user) => ctx.roles.role(msg.chat.id, user.id).await >= Role::Owner,
+        None => false,
+    }
+}
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+/// Human readable overview of who has what override in a chat, and since when
+async fn overrides_report(
+    ctx: &Context,
+    overrides: &Overrides,
+    chat_id: ChatId,
+    language: Language,
+) -> String {
+    let mut report = String::new();
+
+    let users = overrides.in_chat(chat_id).await;
+
+    if users.is_empty() {
+        writeln!(report, "{}", Text::NobodyClowned.translate(language)).ok();
+    }
+
+    for entry in users {
+        let username = ctx
+            .store
+            .username(chat_id, entry.user_id)
+            .await
+            .unwrap_or_else(|_| format!("UserId({})", entry.user_id));
+
+        let until = entry
+            .expires_at
+            .map(|expires_at| Text::Until(expires_at.format(TIME_FORMAT).to_string()))
+            .map(|until| until.translate(language))
+            .unwrap_or_default();
+
+        let clowned = Text::ClownedEntry {
+            username,
+            prompt: entry.prompt,
+            since: entry.created_at.format(TIME_FORMAT).to_string(),
+        };
+        writeln!(report, "{}{until}", clowned.translate(language)).ok();
+    }
+
+    let llm = match ctx.prompts.get_custom_prompt(chat_id).await {
+        Some(prompt) if prompt.drunk => {
+            Text::DrunkSince(prompt.created_at.format(TIME_FORMAT).to_string())
+        }
+        Some(prompt) => Text::CustomPromptSince {
+            prompt: prompt.prompt,
+            since: prompt.created_at.format(TIME_FORMAT).to_string(),
+        },
+        None => Text::DefaultPrompt,
+    };
+    writeln!(report, "{}", llm.translate(language)).ok();
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Some(chrono::Duration::seconds(30)));
+        assert_eq!(parse_duration("2h"), Some(chrono::Duration::hours(2)));
+        assert_eq!(parse_duration("1d12h"), Some(chrono::Duration::hours(36)));
+        assert_eq!(parse_duration("1w"), Some(chrono::Duration::weeks(1)));
+
+        assert_eq!(parse_duration("2"), None);
+        assert_eq!(parse_duration("0h"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("clown"), None);
+        assert_eq!(parse_duration("99999999999999999999h"), None);
+        assert_eq!(parse_duration("1000w"), None);
+    }
+
+    #[test]
+    fn test_parse_sentence() {
+        assert_eq!(
+            Sentence::parse(""),
+            Sentence {
+                duration: None,
+                prompt: Sentence::DEFAULT_PROMPT.into()
+            }
+        );
+        assert_eq!(
+            Sentence::parse("2h"),
+            Sentence {
+                duration: Some(chrono::Duration::hours(2)),
+                prompt: Sentence::DEFAULT_PROMPT.into()
+            }
+        );
+        assert_eq!(
+            Sentence::parse("2h  a sad mime "),
+            Sentence {
+                duration: Some(chrono::Duration::hours(2)),
+                prompt: "a sad mime".into()
+            }
+        );
+        assert_eq!(
+            Sentence::parse("a sad mime"),
+            Sentence {
+                duration: None,
+                prompt: "a sad mime".into()
+            }
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use teloxide::{prelude::*, utils::command::BotCommands};
+
+use crate::{
+    handler::local::{Identifier, Notifier, Update},
+    i18n::Text,
+    limits::Kind,
+    local_ai::{Message as ChatMessage, Request, Role},
+    logging::CorrelationId,
+    rules::{Family, Rewritable},
+};
+
+use super::Context;
+
+#[derive(BotCommands, Clone, Debug)]
+#[command(
+    rename_rule = "lowercase",
+    description = "These commands are supported:"
+)]
+pub enum Command {
+    #[command(description = "Ask the LocalAI LLM, reply to its answer to continue")]
+    Ask(String),
+}
+
+impl Rewritable for Command {
+    const FAMILY: Family = Family::Llm;
+
+    fn prompt(&self) -> Option<&str> {
+        match self {
+            Command::Ask(prompt) => Some(prompt),
+        }
+    }
+
+    fn override_prompt(&mut self, prompt: impl ToString) {
+        match self {
+            Command::Ask(_) => *self = Command::Ask(prompt.to_string()),
+        }
+    }
+}
+
+impl super::Limited for Command {
+    fn kinds(&self) -> &'static [Kind] {
+        &[Kind::Llm]
+    }
+
+    fn problem(&self, ctx: &Context, _msg: &Message) -> Option<Text> {
+        let Command::Ask(args) = self;
+        resolve(ctx, args).err()
+    }
+}
+
+/// The prompt, model and temperature of the arguments, or why they're not valid
+fn resolve<'a>(ctx: &'a Context, args: &'a str) -> Result<(&'a str, &'a str, f32), Text> {
+    let ask = Ask::parse(args);
+
+    if ask.prompt.is_empty() {
+        return Err(Text::MissingPrompt);
+    }
+
+    let model = ctx.ask.model(ask.model).ok_or_else(|| Text::UnknownModel {
+        model: ask.model.unwrap_or_default().to_string(),
+        models: ctx.ask.models.join(", "),
+    })?;
+
+    let temperature = ctx
+        .ask
+        .temperature(ask.temperature)
+        .ok_or_else(|| Text::InvalidTemperature(ask.temperature.unwrap_or_default().to_string()))?;
+
+    Ok((ask.prompt, model, temperature))
+}
+
+pub async fn handler(
+    ctx: Context,
+    msg: Message,
+    command: Command,
+    correlation_id: CorrelationId,
+    notifier: Notifier,
+) -> Result<(), teloxide::RequestError> {
+    log::info!("Received command: {command:?}, Chat ID: {}", msg.chat.id);
+
+    let Some(user) = msg.from() else {
+        log::warn!("Received a command without a user");
+        return Ok(());
+    };
+
+    let Command::Ask(args) = command;
+    let (prompt, model, temperature) = match resolve(&ctx, &args) {
+        Ok(resolved) => resolved,
+        Err(text) => {
+            ctx.reply(&msg, text).await;
+            return Ok(());
+        }
+    };
+
+    // a reply to an earlier answer continues its thread
+    let history = match msg.reply_to_message() {
+        Some(reply) if ctx.ask.history > 0 => ctx
+            .store
+            .thread(msg.chat.id, reply.id, ctx.ask.history)
+            .await
+            .inspect_err(|err| log::error!("failed to fetch thread: {err}"))
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    let parent = msg
+        .reply_to_message()
+        .map(|reply| reply.id)
+        .filter(|_| !history.is_empty());
+
+    let mut messages = vec![ChatMessage {
+        role: Role::System,
+        content: ctx.prompts.get_prompt(msg.chat.id).await,
+    }];
+    messages.extend(history);
+    messages.push(ChatMessage {
+        role: Role::User,
+        content: prompt.to_string(),
+    });
+
+    let result = notifier.try_notify(Update::Ask {
+        identifier: Identifier {
+            chat_id: msg.chat.id,
+            user_id: user.id,
+            message_id: msg.id,
+            correlation_id,
+            language: ctx.locale(&msg).await,
+        },
+        request: Request {
+            model: model.to_string(),
+            messages,
+            temperature,
+        },
+        parent,
+        priority: ctx.priority(msg.chat.id, user.id).await,
+    });
+    ctx.report_rejection(&msg, result).await;
+
+    Ok(())
+}
+
+/// Arguments of the ask command: `[--model <name>] [--temperature <t>] <prompt>`
+#[derive(Debug, Default, PartialEq)]
+struct Ask<'a> {
+    model: Option<&'a str>,
+    temperature: Option<&'a str>,
+    prompt: &'a str,
+}
+
+impl<'a> Ask<'a> {
+    fn parse(args: &'a str) -> Self {
+        let mut ask = Self::default();
+        let mut rest = args.trim();
+
+        loop {
+            let (first, after) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let (flag, value) = match first.split_once('=') {
+                Some((flag, value)) => (flag, Some(value)),
+                None => (first, None),
+            };
+
+            let option = match flag {
+                "--model" => &mut ask.model,
+                "--temperature" => &mut ask.temperature,
+                _ => break,
+            };
+
+            let after = after.trim_start();
+            let (value, after) = match value {
+                Some(value) => (value, after),
+                None => after.split_once(char::is_whitespace).unwrap_or((after, "")),
+            };
+
+            *option = Some(value).filter(|value| !value.is_empty());
+            rest = after.trim_start();
+        }
+
+        ask.prompt = rest;
+        ask
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ask() {
+        assert_eq!(
+            Ask::parse("what's the capital of Belgium?"),
+            Ask {
+                prompt: "what's the capital of Belgium?",
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            Ask::parse(" --temperature 0.2 --model=llama  write a haiku"),
+            Ask {
+                model: Some("llama"),
+                temperature: Some("0.2"),
+                prompt: "write a haiku",
+            }
+        );
+        assert_eq!(
+            Ask::parse("--model"),
+            Ask {
+                model: None,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            Ask::parse("is --model a flag?"),
+            Ask {
+                prompt: "is --model a flag?",
+                ..Default::default()
+            }
+        );
+    }
+}
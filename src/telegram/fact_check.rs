@@ -8,6 +8,7 @@ use tokio::fs::read_dir;
 use std::{io::Cursor, path::Path, sync::Arc};
 
 use super::Context;
+use crate::i18n::Text;
 use teloxide::{prelude::*, types::InputFile, utils::command::BotCommands};
 
 #[derive(BotCommands, Clone, Debug)]
@@ -16,6 +17,7 @@ use teloxide::{prelude::*, types::InputFile, utils::command::BotCommands};
     description = "These commands are supported:"
 )]
 pub enum Command {
+    #[command(description = "Fact check the message you reply to, with 100% accuracy")]
     FactCHeck,
 }
 
@@ -23,16 +25,13 @@ pub async fn handler(
     ctx: Context,
     msg: Message,
     command: Command,
+    engine: Engine,
 ) -> Result<(), teloxide::RequestError> {
     log::info!("Received command: {command:?}, Chat ID: {}", msg.chat.id);
     match command {
         Command::FactCHeck => {
             let Some(original_message) = msg.reply_to_message() else {
-                ctx.quick_reply(
-                    &msg,
-                    "What do you want me to fact check with 100% accuracy? (you have to reply to the message you want fact checked)",
-                )
-                .await;
+                ctx.reply(&msg, Text::FactCheckWhat).await;
                 return Ok(());
             };
 
@@ -42,14 +41,10 @@ pub async fn handler(
                 FactCheck::FakeNews
             };
 
-            let bytes = match ctx
-                .fact_check_engine
-                .get_random_fact_check_outcome(outcome)
-                .await
-            {
+            let bytes = match engine.get_random_fact_check_outcome(outcome).await {
                 Ok(bytes) => bytes,
                 Err(err) => {
-                    ctx.quick_reply(&msg, format!("something is broken: {err}"))
+                    ctx.reply(&msg, Text::FactCheckBroken(err.to_string()))
                         .await;
                     return Ok(());
                 }
@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use teloxide::{
+    prelude::*,
+    types::{BotCommand, BotCommandScope, Recipient},
+    utils::command::BotCommands,
+};
+
+use super::{admin::AdminCommands, Context};
+use crate::i18n::{Language, Text};
+use crate::roles::{Gate, Role, Roles};
+
+#[derive(BotCommands, Clone, Debug)]
+#[command(
+    rename_rule = "lowercase",
+    description = "These commands are supported:"
+)]
+pub enum Command {
+    #[command(description = "List the commands, or explain one: /help <command>")]
+    Help(String),
+}
+
+/// Arguments and an example per command, the description comes from the command enums
+const USAGE: &[(&str, &str, Option<&str>)] = &[
+    ("aimg", "<prompt>", Some("/aimg a lighthouse in a storm")),
+    ("draw", "<prompt>", Some("/draw a dragon reading a book")),
+    ("gigachad", "<prompt>", Some("/gigachad eating a salad")),
+    ("anime", "<prompt>", Some("/anime a cat samurai")),
+    ("lego", "<prompt>", Some("/lego the eiffel tower")),
+    ("knit", "<prompt>", Some("/knit a cozy cabin")),
+    (
+        "say",
+        "[--voice <name>] <text>, or reply to a message",
+        Some("/say --voice nathalie goeiemorgen iedereen"),
+    ),
+    ("transcribe", "reply to a voice message, audio file or video note", None),
+    (
+        "ask",
+        "[--model <name>] [--temperature <0-2>] <prompt>, or reply to an answer",
+        Some("/ask --temperature 0.2 explain monads"),
+    ),
+    ("hey", "<prompt>", Some("/hey what's the capital of Belgium?")),
+    ("oi", "<prompt>", Some("/oi write a haiku about mondays")),
+    ("tldr", "", None),
+    (
+        "summary",
+        "<url>, or reply to a message with a link",
+        Some("/summary https://example.com/article"),
+    ),
+    (
+        "deepsearch",
+        "<question>",
+        Some("/deepsearch when does the next eclipse happen?"),
+    ),
+    ("factcheck", "reply to the message to check", None),
+    ("status", "", None),
+    ("help", "[command]", Some("/help aimg")),
+    (
+        "clown",
+        "[duration] [prompt], reply to the user",
+        Some("/clown 2h a sad clown"),
+    ),
+    ("unclown", "reply to the user", None),
+    ("drunkllm", "", None),
+    ("defaultllm", "", None),
+    ("customllm", "<system prompt>", Some("/customllm answer like a pirate")),
+    ("overrides", "", None),
+    (
+        "addrule",
+        "[chat=..] [user=..] [family=..] [chance=..] [time=HH:MM-HH:MM] <replace|append|style|refuse> [argument]",
+        Some("/addrule family=image chance=0.1 style lego"),
+    ),
+    ("rules", "", None),
+    ("delrule", "<number>", Some("/delrule 2")),
+    (
+        "role",
+        "<banned|user|moderator|admin>, reply to the user",
+        Some("/role moderator"),
+    ),
+    ("roles", "", None),
+    ("reload", "", None),
+    ("language", "<nl|en|fr|auto>", Some("/language nl")),
+    ("autotranscribe", "<on|off>", Some("/autotranscribe on")),
+];
+
+/// The enabled commands, to answer `/help` and register them with Telegram
+#[derive(Clone)]
+pub struct Help {
+    commands: Arc<[BotCommand]>,
+    admin_commands: Arc<[BotCommand]>,
+}
+
+impl Help {
+    pub fn new(mut commands: Vec<BotCommand>) -> Self {
+        commands.extend(Command::bot_commands());
+
+        Self {
+            commands: Arc::from(commands),
+            admin_commands: Arc::from(AdminCommands::bot_commands()),
+        }
+    }
+
+    /// Every command with its description
+    fn overview(&self, admin: bool, language: Language) -> String {
+        let mut lines: Vec<String> = self.commands.iter().map(Self::line).collect();
+
+        if admin {
+            lines.push(format!("\n{}", Text::AdminCommands.translate(language)));
+            lines.extend(self.admin_commands.iter().map(Self::line));
+        }
+
+        lines.push(format!("\n{}", Text::HelpMore.translate(language)));
+        lines.join("\n")
+    }
+
+    fn line(command: &BotCommand) -> String {
+        format!("{} - {}", command.command, command.description)
+    }
+
+    /// Usage, arguments and an example of a single command
+    fn details(&self, name: &str, admin: bool, language: Language) -> Option<String> {
+        let name = name.trim().trim_start_matches('/').to_lowercase();
+
+        let admin_commands: &[BotCommand] = if admin { &self.admin_commands } else { &[] };
+        let command = self
+            .commands
+            .iter()
+            .chain(admin_commands)
+            .find(|command| command.command.trim_start_matches('/') == name)?;
+
+        let mut details = vec![command.description.clone()];
+
+        let (usage, example) = match USAGE.iter().find(|(usage, _, _)| *usage == name) {
+            Some((_, "", example)) => (command.command.clone(), *example),
+            Some((_, arguments, example)) => (format!("{} {arguments}", command.command), *example),
+            None => (command.command.clone(), None),
+        };
+
+        details.push(Text::Usage(usage).translate(language));
+        details.extend(example.map(|example| Text::Example(example.into()).translate(language)));
+
+        Some(details.join("\n"))
+    }
+
+    /// Register the commands with Telegram, admins also get the admin commands
+    pub async fn register(self, bot: Bot, roles: Roles) {
+        let result = bot.set_my_commands(self.commands.to_vec()).await;
+        if let Err(err) = result {
+            log::error!("failed to register the commands: {err}");
+            return;
+        }
+
+        if roles.grants_chat_admins() && roles.minimum(Gate::Admin) <= Role::Admin {
+            self.register_admin(&bot, BotCommandScope::AllChatAdministrators)
+                .await;
+        }
+
+        if let Some(owner) = roles.owner() {
+            let scope = BotCommandScope::Chat {
+                chat_id: Recipient::Id(ChatId::from(owner)),
+            };
+            self.register_admin(&bot, scope).await;
+        }
+
+        for (chat_id, user_id, role) in roles.granted().await {
+            if role >= roles.minimum(Gate::Admin) {
+                self.register_member(&bot, chat_id, user_id, role, &roles)
+                    .await;
+            }
+        }
+    }
+
+    /// Show or hide the admin commands for a user after their role changed
+    pub async fn register_member(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        user_id: UserId,
+        role: Role,
+        roles: &Roles,
+    ) {
+        let scope = BotCommandScope::ChatMember {
+            chat_id: Recipient::Id(chat_id),
+            user_id,
+        };
+
+        if role >= roles.minimum(Gate::Admin) {
+            self.register_admin(bot, scope).await;
+        } else if let Err(err) = bot.delete_my_commands().scope(scope).await {
+            log::error!("failed to unregister the admin commands: {err}");
+        }
+    }
+
+    async fn register_admin(&self, bot: &Bot, scope: BotCommandScope) {
+        let commands: Vec<BotCommand> = self
+            .commands
+            .iter()
+            .chain(self.admin_commands.iter())
+            .cloned()
+            .collect();
+
+        if let Err(err) = bot.set_my_commands(commands).scope(scope).await {
+            log::error!("failed to register the admin commands: {err}");
+        }
+    }
+}
+
+pub async fn handler(
+    ctx: Context,
+    msg: Message,
+    command: Command,
+    help: Help,
+) -> Result<(), teloxide::RequestError> {
+    log::info!("Received command: {command:?}, Chat ID: {}", msg.chat.id);
+
+    let admin = match msg.from() {
+        Some(user) => ctx
+            .roles
+            .authorize(msg.chat.id, user.id, Gate::Admin)
+            .await
+            .is_ok(),
+        None => false,
+    };
+
+    let language = ctx.locale(&msg).await;
+
+    match command {
+        Command::Help(name) if name.trim().is_empty() => {
+            ctx.quick_reply(&msg, help.overview(admin, language)).await
+        }
+        Command::Help(name) => {
+            let reply = help.details(&name, admin, language).unwrap_or_else(|| {
+                Text::UnknownCommand(name.trim().to_string()).translate(language)
+            });
+
+            ctx.quick_reply(&msg, reply).await
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage() {
+        let help = Help::new(vec![
+            BotCommand::new("/aimg", "Generate a picture"),
+            BotCommand::new("/tldr", "Summarize the chat"),
+        ]);
+
+        assert_eq!(
+            help.details("/aimg", false, Language::En).unwrap(),
+            "Generate a picture\nUsage: /aimg <prompt>\nExample: /aimg a lighthouse in a storm"
+        );
+        assert_eq!(
+            help.details("AIMG", false, Language::Nl),
+            help.details("aimg", true, Language::Nl)
+        );
+        assert_eq!(
+            help.details("tldr", false, Language::Fr).unwrap(),
+            "Summarize the chat\nUtilisation : /tldr"
+        );
+
+        // admin commands are hidden from others
+        assert_eq!(help.details("reload", false, Language::En), None);
+        assert!(help.details("reload", true, Language::En).is_some());
+
+        assert!(!help.overview(false, Language::En).contains("/reload"));
+        assert!(help.overview(true, Language::En).contains("/reload"));
+    }
+
+    #[test]
+    fn test_every_command_is_documented() {
+        let commands = [
+            super::super::invoke_ai::Command::bot_commands(),
+            super::super::local_ai::Command::bot_commands(),
+            super::super::ask::Command::bot_commands(),
+            super::super::ollama::Command::bot_commands(),
+            super::super::fact_check::Command::bot_commands(),
+            super::super::status::Command::bot_commands(),
+            Command::bot_commands(),
+            AdminCommands::bot_commands(),
+        ];
+
+        for command in commands.iter().flatten() {
+            let name = command.command.trim_start_matches('/');
+
+            assert!(
+                USAGE.iter().any(|(usage, _, _)| *usage == name),
+                "{name} has no usage"
+            );
+            // Telegram refuses to register commands without a description
+            assert!(command.description.len() >= 3, "{name} has no description");
+        }
+    }
+}
@@ -1,6 +1,12 @@
 use teloxide::{prelude::*, utils::command::BotCommands};
 
-use crate::{handler::invoke::Update, invoke_ai::models::Enqueue};
+use crate::{
+    handler::invoke::{Notifier, Update},
+    invoke_ai::models::Enqueue,
+    limits::Kind,
+    logging::CorrelationId,
+    rules::{Family, Rewritable, Style},
+};
 
 use super::Context;
 
@@ -25,6 +31,32 @@ pub enum Command {
 }
 
 impl Command {
+    fn style(&self) -> Style {
+        match self {
+            Command::AImg(_) => Style::Photo,
+            Command::Draw(_) => Style::Drawing,
+            Command::Gigachad(_) => Style::Gigachad,
+            Command::Anime(_) => Style::Anime,
+            Command::Lego(_) => Style::Lego,
+            Command::Knit(_) => Style::Knit,
+        }
+    }
+}
+
+impl Rewritable for Command {
+    const FAMILY: Family = Family::Image;
+
+    fn prompt(&self) -> Option<&str> {
+        match self {
+            Command::AImg(prompt)
+            | Command::Draw(prompt)
+            | Command::Gigachad(prompt)
+            | Command::Anime(prompt)
+            | Command::Lego(prompt)
+            | Command::Knit(prompt) => Some(prompt),
+        }
+    }
+
     fn override_prompt(&mut self, prompt: impl ToString) {
         match self {
             Command::AImg(_) => *self = Command::AImg(prompt.to_string()),
@@ -35,13 +67,33 @@ impl Command {
             Command::Knit(_) => *self = Command::Knit(prompt.to_string()),
         }
     }
+
+    fn force_style(&mut self, style: Style) {
+        let prompt = self.prompt().unwrap_or_default().to_string();
+
+        *self = match style {
+            Style::Photo => Command::AImg(prompt),
+            Style::Drawing => Command::Draw(prompt),
+            Style::Gigachad => Command::Gigachad(prompt),
+            Style::Anime => Command::Anime(prompt),
+            Style::Lego => Command::Lego(prompt),
+            Style::Knit => Command::Knit(prompt),
+        };
+    }
+}
+
+impl super::Limited for Command {
+    fn kinds(&self) -> &'static [Kind] {
+        &[Kind::Image]
+    }
 }
 
 pub async fn handler(
     ctx: Context,
     msg: Message,
-    mut command: Command,
-    overrides: super::admin::Overrides,
+    command: Command,
+    correlation_id: CorrelationId,
+    notifier: Notifier,
 ) -> Result<(), teloxide::RequestError> {
     log::info!("Received command: {command:?}, Chat ID: {}", msg.chat.id);
 
@@ -53,15 +105,8 @@ pub async fn handler(
         }
     };
 
-    if let Some(prompt) = overrides.get_override(user.id).await {
-        command.override_prompt(prompt);
-    }
-
-    if user.id == UserId(172179034) {
-        command.override_prompt("man dressed as clown stabbing tire");
-    }
-
-    let enqueue = match command {
+    let style = command.style();
+    let mut enqueue = match command {
         Command::AImg(prompt) => Enqueue::from_prompt(prompt),
         Command::Draw(prompt) => Enqueue::from_prompt(prompt).drawing(),
         Command::Gigachad(prompt) => Enqueue::from_prompt(prompt).gigachad(),
@@ -69,13 +114,18 @@ pub async fn handler(
         Command::Lego(prompt) => Enqueue::from_prompt(prompt).lego(),
         Command::Knit(prompt) => Enqueue::from_prompt(prompt).knit(),
     };
+    ctx.presets.apply(style, &mut enqueue);
 
-    ctx.invoke_notifier.notify(Update::Requested {
+    let result = notifier.try_notify(Update::Requested {
         enqueue: Box::new(enqueue),
         chat_id: msg.chat.id,
         user_id: user.id,
         message_id: msg.id,
+        correlation_id,
+        language: ctx.locale(&msg).await,
+        priority: ctx.priority(msg.chat.id, user.id).await,
     });
+    ctx.report_rejection(&msg, result).await;
 
     Ok(())
 }
@@ -1,6 +1,20 @@
-use teloxide::{prelude::*, utils::command::BotCommands};
+use teloxide::{
+    net::Download,
+    prelude::*,
+    types::{FileMeta, Me},
+    utils::command::BotCommands,
+};
 
-use crate::handler::local::{Identifier, Update};
+use crate::{
+    handler::local::{Identifier, Notifier, Update},
+    i18n::Text,
+    limits::Kind,
+    local_ai::{Recording, Voice},
+    logging::CorrelationId,
+    roles::Gate,
+    rules::{Family, Rewritable},
+    scheduler::Priority,
+};
 
 use super::Context;
 
@@ -10,13 +24,46 @@ use super::Context;
     description = "These commands are supported:"
 )]
 pub enum Command {
+    #[command(description = "Read text out loud")]
     Say(String),
+    #[command(description = "Write out the voice message you reply to")]
+    Transcribe,
 }
 
-impl Command {
+/// Bots can't download files over 20 MB
+const MAX_DOWNLOAD: u32 = 20 * 1024 * 1024;
+
+impl Rewritable for Command {
+    const FAMILY: Family = Family::Tts;
+
+    fn prompt(&self) -> Option<&str> {
+        match self {
+            Command::Say(prompt) => Some(prompt),
+            Command::Transcribe => None,
+        }
+    }
+
     fn override_prompt(&mut self, prompt: impl ToString) {
         match self {
             Command::Say(_) => *self = Command::Say(prompt.to_string()),
+            Command::Transcribe => (),
+        }
+    }
+}
+
+impl super::Limited for Command {
+    fn kinds(&self) -> &'static [Kind] {
+        &[Kind::Tts]
+    }
+
+    fn problem(&self, ctx: &Context, msg: &Message) -> Option<Text> {
+        match self {
+            Command::Say(args) => speech(ctx, msg, args).err(),
+            Command::Transcribe => match msg.reply_to_message().and_then(recording) {
+                None => Some(Text::NotARecording),
+                Some((file, _)) if file.size > MAX_DOWNLOAD => Some(Text::RecordingTooLarge),
+                Some(_) => None,
+            },
         }
     }
 }
@@ -24,8 +71,9 @@ impl Command {
 pub async fn handler(
     ctx: Context,
     msg: Message,
-    mut command: Command,
-    overrides: super::admin::Overrides,
+    command: Command,
+    correlation_id: CorrelationId,
+    notifier: Notifier,
 ) -> Result<(), teloxide::RequestError> {
     log::info!("Received command: {command:?}, Chat ID: {}", msg.chat.id);
 
@@ -37,35 +85,305 @@ pub async fn handler(
         }
     };
 
-    if let Some(prompt) = overrides.get_override(user.id).await {
-        command.override_prompt(prompt);
-    }
-
-    if user.id == UserId(172179034) {
-        command.override_prompt("What are the hazards of driving on a flat tire?");
-    }
-
     match command {
-        Command::Say(prompt) => {
-            let prompt = msg
-                .reply_to_message()
-                .and_then(|message| message.text())
-                .map(ToString::to_string)
-                .unwrap_or(prompt);
+        Command::Say(args) => {
+            let (prompt, voice) = match speech(&ctx, &msg, &args) {
+                Ok(speech) => speech,
+                Err(text) => {
+                    ctx.reply(&msg, text).await;
+                    return Ok(());
+                }
+            };
 
-            let language = ctx.language.detect_language(&prompt);
-
-            ctx.local_notifier.notify(Update::TtsRequest {
+            let result = notifier.try_notify(Update::TtsRequest {
                 identifier: Identifier {
                     chat_id: msg.chat.id,
                     user_id: user.id,
                     message_id: msg.id,
+                    correlation_id,
+                    language: ctx.locale(&msg).await,
                 },
                 prompt,
-                language,
-            })
+                voice,
+                priority: ctx.priority(msg.chat.id, user.id).await,
+            });
+            ctx.report_rejection(&msg, result).await;
+        }
+        Command::Transcribe => {
+            match msg
+                .reply_to_message()
+                .filter(|reply| recording(reply).is_some())
+            {
+                Some(reply) => {
+                    request_transcription(&ctx, &msg, reply, correlation_id, &notifier).await
+                }
+                None => ctx.reply(&msg, Text::NotARecording).await,
+            }
         }
     };
 
     Ok(())
 }
+
+/// The text to read out loud, from the message it replies to or the command, with its voice
+fn speech(ctx: &Context, msg: &Message, args: &str) -> Result<(String, Voice), Text> {
+    let say = Say::parse(args);
+
+    let prompt = msg
+        .reply_to_message()
+        .and_then(|message| message.text())
+        .unwrap_or(say.text)
+        .to_string();
+
+    if prompt.trim().is_empty() {
+        return Err(Text::MissingPrompt);
+    }
+
+    let voice = match say.voice {
+        Some(name) => ctx.voices.named(name).ok_or_else(|| Text::UnknownVoice {
+            voice: name.to_string(),
+            voices: ctx.voices.names().join(", "),
+        })?,
+        None => ctx
+            .voices
+            .for_language(ctx.language.detect_language(&prompt))
+            .ok_or_else(super::something_went_wrong)?,
+    };
+
+    Ok((prompt, voice))
+}
+
+/// Recordings in chats with automatic transcription, from users allowed to use speech commands
+///
+/// Users that aren't allowed are skipped without a reply, they didn't ask for anything.
+pub async fn auto_transcribe(ctx: Context, msg: Message) -> bool {
+    let Some(user) = msg.from() else {
+        return false;
+    };
+
+    recording(&msg).is_some()
+        && ctx.transcription.enabled(msg.chat.id).await
+        && ctx
+            .roles
+            .authorize(msg.chat.id, user.id, Gate::Tts)
+            .await
+            .is_ok()
+        && ctx
+            .rules
+            .evaluate(msg.chat.id, user.id, Family::Tts)
+            .await
+            .refusal
+            .is_none()
+}
+
+pub async fn transcribe_automatically(
+    ctx: Context,
+    msg: Message,
+    correlation_id: CorrelationId,
+    notifier: Notifier,
+) -> Result<(), teloxide::RequestError> {
+    request_transcription(&ctx, &msg, &msg, correlation_id, &notifier).await;
+
+    Ok(())
+}
+
+/// Voice messages that reply to the bot start a spoken conversation
+pub fn is_conversation(msg: Message, me: Me) -> bool {
+    msg.voice().is_some()
+        && msg
+            .reply_to_message()
+            .and_then(|reply| reply.from())
+            .is_some_and(|user| user.id == me.id)
+}
+
+/// Answer a voice message with a voice message
+pub async fn conversation(
+    ctx: Context,
+    msg: Message,
+    correlation_id: CorrelationId,
+    notifier: Notifier,
+) -> Result<(), teloxide::RequestError> {
+    let Some((identifier, recording, priority)) =
+        prepare_recording(&ctx, &msg, &msg, correlation_id).await
+    else {
+        return Ok(());
+    };
+
+    let result = notifier.try_notify(Update::ConversationRequest {
+        identifier,
+        recording,
+        priority,
+    });
+    ctx.report_rejection(&msg, result).await;
+
+    Ok(())
+}
+
+/// Download the recording in `message` and hand it to LocalAI, the transcript replies to `request`
+async fn request_transcription(
+    ctx: &Context,
+    request: &Message,
+    message: &Message,
+    correlation_id: CorrelationId,
+    notifier: &Notifier,
+) {
+    let Some((identifier, recording, priority)) =
+        prepare_recording(ctx, request, message, correlation_id).await
+    else {
+        return;
+    };
+
+    let result = notifier.try_notify(Update::TranscriptionRequest {
+        identifier,
+        recording,
+        priority,
+    });
+    ctx.report_rejection(request, result).await;
+}
+
+/// Download the recording in `message`, replying to `request` when that fails
+async fn prepare_recording(
+    ctx: &Context,
+    request: &Message,
+    message: &Message,
+    correlation_id: CorrelationId,
+) -> Option<(Identifier, Recording, Priority)> {
+    let (Some((file, file_name)), Some(user), Some(author)) =
+        (recording(message), request.from(), message.from())
+    else {
+        return None;
+    };
+
+    if file.size > MAX_DOWNLOAD {
+        ctx.reply(request, Text::RecordingTooLarge).await;
+        return None;
+    }
+
+    let audio = match download(&ctx.bot, file).await {
+        Ok(audio) => audio,
+        Err(err) => {
+            log::error!("failed to download recording: {err}");
+            ctx.reply(request, super::something_went_wrong()).await;
+            return None;
+        }
+    };
+
+    let identifier = Identifier {
+        chat_id: request.chat.id,
+        user_id: user.id,
+        message_id: request.id,
+        correlation_id,
+        language: ctx.locale(request).await,
+    };
+    let recording = Recording {
+        message_id: message.id,
+        user_id: author.id,
+        file_name,
+        audio,
+    };
+    let priority = ctx.priority(request.chat.id, user.id).await;
+
+    Some((identifier, recording, priority))
+}
+
+/// The voice message, video note or audio file in a message, with a name for its format
+fn recording(message: &Message) -> Option<(&FileMeta, String)> {
+    if let Some(voice) = message.voice() {
+        return Some((&voice.file, String::from("voice.ogg")));
+    }
+
+    if let Some(note) = message.video_note() {
+        return Some((&note.file, String::from("video_note.mp4")));
+    }
+
+    message.audio().map(|audio| {
+        let name = audio
+            .file_name
+            .clone()
+            .unwrap_or_else(|| String::from("audio.mp3"));
+        (&audio.file, name)
+    })
+}
+
+async fn download(bot: &Bot, file: &FileMeta) -> Result<Vec<u8>, anyhow::Error> {
+    let file = bot.get_file(file.id.clone()).await?;
+
+    let mut audio = Vec::with_capacity(file.size as usize);
+    bot.download_file(&file.path, &mut audio).await?;
+
+    Ok(audio)
+}
+
+/// Arguments of the say command: `[--voice <name>] [text]`
+#[derive(Debug, PartialEq, Eq)]
+struct Say<'a> {
+    voice: Option<&'a str>,
+    text: &'a str,
+}
+
+impl<'a> Say<'a> {
+    fn parse(args: &'a str) -> Self {
+        let args = args.trim();
+
+        let (first, rest) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+
+        if let Some(voice) = first.strip_prefix("--voice=") {
+            return Self {
+                voice: Some(voice),
+                text: rest.trim(),
+            };
+        }
+
+        if first == "--voice" {
+            let rest = rest.trim();
+            let (voice, text) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+            return Self {
+                voice: Some(voice).filter(|voice| !voice.is_empty()),
+                text: text.trim(),
+            };
+        }
+
+        Self {
+            voice: None,
+            text: args,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_say() {
+        assert_eq!(
+            Say::parse("hallo iedereen"),
+            Say {
+                voice: None,
+                text: "hallo iedereen"
+            }
+        );
+        assert_eq!(
+            Say::parse(" --voice nathalie  hallo iedereen"),
+            Say {
+                voice: Some("nathalie"),
+                text: "hallo iedereen"
+            }
+        );
+        assert_eq!(
+            Say::parse("--voice=libritts"),
+            Say {
+                voice: Some("libritts"),
+                text: ""
+            }
+        );
+        assert_eq!(
+            Say::parse("--voiceover is a job"),
+            Say {
+                voice: None,
+                text: "--voiceover is a job"
+            }
+        );
+    }
+}
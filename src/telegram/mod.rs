@@ -1,37 +1,68 @@
+use std::sync::Arc;
+
+use teloxide::dptree::di::DependencyMap;
 use teloxide::prelude::Update as TelegramUpdate;
 use teloxide::prelude::*;
-use teloxide::types::UserId;
+use teloxide::utils::command::BotCommands;
 
 use crate::handler::invoke;
+use crate::handler::llm as llm_handler;
 use crate::handler::local;
-use crate::local_ai::Prompts;
+use crate::handler::notifier;
+use crate::health::Monitor;
+use crate::i18n::{Language, Locales, Text};
+use crate::invoke_ai::presets::Presets;
+use crate::limits::{self, Kind, Limiter};
+use crate::local_ai::{AutoTranscription, Prompts, Voices};
+use crate::logging::{self, CorrelationId};
+use crate::metrics::{self, Queues};
+use crate::roles::{Gate, Role, Roles};
+use crate::rules::{Family, Rewritable};
+use crate::scheduler::{Priority, Scheduler};
+use crate::settings::Reloader;
 use crate::store::Store;
 use crate::utils::languages::LanguageDetector;
 
 pub mod admin;
+mod ask;
 pub mod fact_check;
+mod help;
 mod invoke_ai;
 mod local_ai;
 mod ollama;
-
-#[derive(Clone, Copy)]
-pub struct Config {
-    pub admin_id: Option<UserId>,
-}
+mod reasoning;
+mod status;
 
 #[derive(Clone)]
 pub struct Context {
-    pub cfg: Config,
     pub bot: Bot,
     pub store: Store,
-    pub invoke_notifier: invoke::Notifier,
-    pub local_notifier: local::Notifier,
-    pub ollama_notifier: crate::handler::ollama::Notifier,
     pub language: LanguageDetector,
     pub prompts: Prompts,
     pub http_client: reqwest::Client,
-    pub searxng: crate::utils::SearXng,
-    pub fact_check_engine: fact_check::Engine,
+    pub searxng: Option<crate::utils::SearXng>,
+    pub rules: crate::rules::Rules,
+    pub roles: Roles,
+    pub limits: Limiter,
+    pub scheduler: Scheduler,
+    pub monitor: Monitor,
+    pub queues: Queues,
+    pub presets: Presets,
+    pub voices: Voices,
+    pub reloader: Reloader,
+    pub locales: Locales,
+    pub transcription: AutoTranscription,
+    pub ask: Arc<crate::local_ai::ask::Config>,
+}
+
+/// Optional integrations, the commands of those that aren't configured aren't registered
+pub struct Backends {
+    pub invoke: Option<invoke::Notifier>,
+    pub local: Option<local::Notifier>,
+    pub llm: Option<llm_handler::Notifier>,
+    /// Only when the LLM answers offer their reasoning
+    pub reasoning: Option<llm_handler::Reasoning>,
+    pub fact_check: Option<fact_check::Engine>,
 }
 
 impl Context {
@@ -43,72 +74,353 @@ impl Context {
             .await
             .inspect_err(|err| {
                 log::error!("failed to send message: {err}");
+                metrics::TELEGRAM_ERRORS.inc("reply");
             })
             .ok();
     }
+
+    /// Reply in the language of the chat, or of the user
+    pub async fn reply(&self, message: &Message, text: Text) {
+        let language = self.locale(message).await;
+        self.quick_reply(message, text.translate(language)).await
+    }
+
+    /// Language to reply to a message in
+    pub async fn locale(&self, message: &Message) -> Language {
+        self.locales.language(message.chat.id, message.from()).await
+    }
+
+    /// Let the user know when their request couldn't be handed to a handler
+    pub async fn report_rejection(&self, message: &Message, result: Result<(), notifier::Error>) {
+        match result {
+            Ok(()) => (),
+            Err(notifier::Error::Busy(_)) => self.reply(message, Text::Busy).await,
+            Err(err) => {
+                log::error!("failed to hand over request: {err}");
+                self.reply(message, something_went_wrong()).await
+            }
+        }
+    }
+
+    /// Scheduling priority of a user, admins go first
+    pub async fn priority(&self, chat_id: ChatId, user_id: UserId) -> Priority {
+        if self.roles.role(chat_id, user_id).await >= Role::Admin {
+            Priority::Admin
+        } else {
+            Priority::Normal
+        }
+    }
 }
 
 pub fn handler(
     context: Context,
+    overrides: admin::Overrides,
+    backends: Backends,
 ) -> Dispatcher<Bot, teloxide::RequestError, teloxide::dispatching::DefaultKey> {
-    let overrides = admin::Overrides::default();
+    let Backends {
+        invoke,
+        local,
+        llm,
+        reasoning,
+        fact_check,
+    } = backends;
 
-    let handler = TelegramUpdate::filter_message()
-        .branch(
-            dptree::filter(|ctx: Context, msg: Message| match ctx.cfg.admin_id {
-                Some(admin_user_id) => msg
-                    .from()
-                    .map(|user| user.id == admin_user_id)
-                    .unwrap_or_default(),
-                None => {
-                    tokio::task::spawn(async move {
-                        ctx.quick_reply(&msg, "this command is only available to administrators")
-                            .await;
-                    });
-                    false
-                }
-            })
+    let mut help = Vec::new();
+    let mut dependencies = dptree::deps![context.clone(), overrides];
+
+    let mut commands = TelegramUpdate::filter_message().branch(
+        dptree::entry()
             .filter_command::<admin::AdminCommands>()
+            .inspect(count_command)
+            .filter_async(|ctx: Context, msg: Message| authorized(ctx, msg, Gate::Admin))
             .endpoint(admin::handler),
-        )
-        .branch(
+    );
+
+    if let Some(notifier) = invoke {
+        dependencies.insert(notifier);
+        help.extend(invoke_ai::Command::bot_commands());
+        commands = commands.branch(
             dptree::entry()
                 .filter_command::<invoke_ai::Command>()
+                .inspect(count_command)
+                .filter_async(|ctx: Context, msg: Message| authorized(ctx, msg, Gate::Image))
+                .filter_map_async(rewrite::<invoke_ai::Command>)
+                .filter_async(admissible::<invoke_ai::Command>)
                 .endpoint(invoke_ai::handler),
-        )
-        .branch(
+        );
+    }
+
+    if let Some(notifier) = local {
+        dependencies.insert(notifier);
+        help.extend(local_ai::Command::bot_commands());
+        help.extend(ask::Command::bot_commands());
+        commands = commands.branch(
             dptree::entry()
                 .filter_command::<local_ai::Command>()
+                .inspect(count_command)
+                .filter_async(|ctx: Context, msg: Message| authorized(ctx, msg, Gate::Tts))
+                .filter_map_async(rewrite::<local_ai::Command>)
+                .filter_async(admissible::<local_ai::Command>)
                 .endpoint(local_ai::handler),
-        )
-        .branch(
+        );
+        commands = commands.branch(
+            dptree::entry()
+                .filter_command::<ask::Command>()
+                .inspect(count_command)
+                .filter_async(|ctx: Context, msg: Message| authorized(ctx, msg, Gate::Llm))
+                .filter_map_async(rewrite::<ask::Command>)
+                .filter_async(admissible::<ask::Command>)
+                .endpoint(ask::handler),
+        );
+        commands = commands.branch(
+            // a conversation is transcribed, answered by the LLM and spoken
+            dptree::filter(local_ai::is_conversation)
+                .filter_async(|ctx: Context, msg: Message| authorized(ctx, msg, Gate::Tts))
+                .filter_async(|ctx: Context, msg: Message| authorized(ctx, msg, Gate::Llm))
+                .filter_async(|ctx: Context, msg: Message| not_refused(ctx, msg, Family::Tts))
+                .filter_async(|ctx: Context, msg: Message| not_refused(ctx, msg, Family::Llm))
+                .filter_async(|ctx: Context, msg: Message| {
+                    within_limits(ctx, msg, &[Kind::Tts, Kind::Llm])
+                })
+                .endpoint(local_ai::conversation),
+        );
+        commands = commands.branch(
+            dptree::filter_async(local_ai::auto_transcribe)
+                .filter_async(|ctx: Context, msg: Message| within_limits(ctx, msg, &[Kind::Tts]))
+                .endpoint(local_ai::transcribe_automatically),
+        );
+    }
+
+    if let Some(notifier) = llm {
+        dependencies.insert(notifier);
+        help.extend(ollama::commands(context.searxng.is_some()));
+        commands = commands.branch(
             dptree::entry()
                 .filter_command::<ollama::Command>()
+                .filter(ollama::is_enabled)
+                .inspect(count_command)
+                .filter_async(|ctx: Context, msg: Message| authorized(ctx, msg, Gate::Llm))
+                .filter_map_async(rewrite::<ollama::Command>)
+                .filter_async(admissible::<ollama::Command>)
                 .endpoint(ollama::handler),
-        )
-        .branch(
+        );
+    }
+
+    if let Some(engine) = fact_check {
+        dependencies.insert(engine);
+        help.extend(fact_check::Command::bot_commands());
+        commands = commands.branch(
             dptree::entry()
                 .filter_command::<fact_check::Command>()
+                .inspect(count_command)
                 .endpoint(fact_check::handler),
+        );
+    }
+
+    help.extend(status::Command::bot_commands());
+    let help = help::Help::new(help);
+    dependencies.insert(help.clone());
+
+    tokio::task::spawn(help.register(context.bot.clone(), context.roles.clone()));
+
+    let commands = commands
+        .branch(
+            dptree::entry()
+                .filter_command::<status::Command>()
+                .inspect(count_command)
+                .endpoint(status::handler),
+        )
+        .branch(
+            dptree::entry()
+                .filter_command::<help::Command>()
+                .inspect(count_command)
+                .endpoint(help::handler),
         )
         .branch(dptree::entry().endpoint(catch_all));
 
-    Dispatcher::builder(context.bot.clone(), handler)
-        .dependencies(dptree::deps![context, overrides])
+    let mut updates = dptree::entry().branch(commands);
+
+    if let Some(reasoning) = reasoning {
+        dependencies.insert(reasoning);
+        updates = updates.branch(
+            TelegramUpdate::filter_callback_query()
+                .filter(reasoning::is_reveal)
+                .endpoint(reasoning::reveal),
+        );
+    }
+
+    // every update gets its own correlation id, available to the endpoints and in their logs
+    let handler = dptree::from_fn(|mut deps: DependencyMap, cont| async move {
+        let correlation_id = CorrelationId::generate();
+        deps.insert(correlation_id);
+        logging::scope(Some(correlation_id), cont(deps)).await
+    })
+    .chain(updates);
+
+    Dispatcher::builder(context.bot, handler)
+        .dependencies(dependencies)
         .default_handler(|_| async {})
+        .error_handler(Arc::new(|err: teloxide::RequestError| async move {
+            log::error!("failed to handle update: {err}");
+            metrics::TELEGRAM_ERRORS.inc("dispatcher");
+        }))
         .build()
 }
 
+/// Generic error for users, with the correlation id so they can report it
+fn something_went_wrong() -> Text {
+    Text::SomethingWentWrong(logging::current())
+}
+
+/// Count received commands by name, without the bot's username
+fn count_command(msg: Message) {
+    let Some(command) = msg.text().and_then(|text| text.split_whitespace().next()) else {
+        return;
+    };
+
+    let name = command.trim_start_matches('/');
+    let name = name.split('@').next().unwrap_or(name);
+
+    metrics::COMMANDS.inc(&name.to_lowercase());
+}
+
+/// Only lets commands through when the user has the role the command family requires,
+/// and tells them when they don't
+async fn authorized(ctx: Context, msg: Message, gate: Gate) -> bool {
+    let Some(user) = msg.from() else {
+        return false;
+    };
+
+    let role = match ctx.roles.authorize(msg.chat.id, user.id, gate).await {
+        Ok(()) => return true,
+        Err(role) => role,
+    };
+
+    log::info!(
+        "Refused {gate:?} command, UserId({}), ChatId({}), Role({role})",
+        user.id,
+        msg.chat.id
+    );
+
+    let reply = match role {
+        Role::Banned => Text::Banned,
+        _ => Text::MinimumRole(ctx.roles.minimum(gate)),
+    };
+
+    ctx.reply(&msg, reply).await;
+
+    false
+}
+
+/// Applies the override of the user and the rules of the command family, the endpoint gets
+/// the rewritten command and refused commands are answered with the refusal
+async fn rewrite<C: Rewritable + Send + Sync + 'static>(
+    ctx: Context,
+    msg: Message,
+    overrides: admin::Overrides,
+    mut command: C,
+) -> Option<C> {
+    let user = msg.from()?;
+
+    if let Some(prompt) = overrides.get_override(msg.chat.id, user.id).await {
+        command.override_prompt(prompt);
+    }
+
+    let outcome = ctx.rules.evaluate(msg.chat.id, user.id, C::FAMILY).await;
+    match outcome.apply(&mut command) {
+        Ok(()) => Some(command),
+        Err(refusal) => {
+            ctx.quick_reply(&msg, refusal).await;
+            None
+        }
+    }
+}
+
+/// Only lets messages without a command through when no rule of the family refuses them
+async fn not_refused(ctx: Context, msg: Message, family: Family) -> bool {
+    let Some(user) = msg.from() else {
+        return false;
+    };
+
+    let outcome = ctx.rules.evaluate(msg.chat.id, user.id, family).await;
+    match outcome.refusal {
+        Some(refusal) => {
+            ctx.quick_reply(&msg, refusal).await;
+            false
+        }
+        None => true,
+    }
+}
+
+/// Commands that count against the limits, once they're known to be valid
+trait Limited {
+    /// The limits the command counts against
+    fn kinds(&self) -> &'static [Kind];
+
+    /// Reply for a command that can't be handled, like one without a prompt
+    fn problem(&self, _ctx: &Context, _msg: &Message) -> Option<Text> {
+        None
+    }
+}
+
+/// Only lets valid commands through, and only while they're within the limits, so invalid
+/// commands don't count against them
+async fn admissible<C: Limited + Send>(ctx: Context, msg: Message, command: C) -> bool {
+    if let Some(problem) = command.problem(&ctx, &msg) {
+        ctx.reply(&msg, problem).await;
+        return false;
+    }
+
+    within_limits(ctx, msg, command.kinds()).await
+}
+
+/// Only lets requests through while the user and the chat are within their limits,
+/// admins are exempt
+async fn within_limits(ctx: Context, msg: Message, kinds: &[Kind]) -> bool {
+    /// Warn users when they're about to run out
+    const REMAINING_WARNING: u32 = 3;
+
+    let Some(user) = msg.from() else {
+        return false;
+    };
+
+    if ctx.roles.role(msg.chat.id, user.id).await >= Role::Admin {
+        return true;
+    }
+
+    match ctx.limits.check(msg.chat.id, user.id, kinds).await {
+        Ok(Some(remaining)) if remaining.today <= REMAINING_WARNING => {
+            ctx.reply(&msg, Text::Remaining(remaining)).await;
+            true
+        }
+        Ok(_) => true,
+        Err(limits::Error::Exceeded(exceeded)) => {
+            log::info!(
+                "Limit exceeded: {exceeded:?}, UserId({}), ChatId({})",
+                user.id,
+                msg.chat.id
+            );
+            ctx.reply(&msg, Text::Exceeded(exceeded)).await;
+            false
+        }
+        Err(err) => {
+            // don't punish users for our own mistakes
+            log::error!("{err}");
+            true
+        }
+    }
+}
+
 async fn catch_all(ctx: Context, msg: Message) -> Result<(), teloxide::RequestError> {
     let (_store, _french) = tokio::join!(
-        tokio::task::spawn(store_message(ctx.clone(), msg.clone())),
-        tokio::task::spawn(detect_french(ctx.clone(), msg.clone()))
+        logging::spawn(store_message(ctx.clone(), msg.clone())),
+        logging::spawn(detect_french(ctx.clone(), msg.clone()))
     );
 
     Ok(())
 }
 
-/// Respond "wablieft?" if the message is french
+/// Respond "wablieft?" if the message is french, unless the chat speaks french
 async fn detect_french(ctx: Context, msg: Message) {
     let Some(txt) = msg.text() else {
         return;
@@ -118,8 +430,11 @@ async fn detect_french(ctx: Context, msg: Message) {
         return;
     }
 
-    if ctx.language.has_french(txt.to_string()) {
-        ctx.quick_reply(&msg, "wablieft").await;
+    // reply in the language of the chat, not the language of the french speaker
+    let language = ctx.locales.chat(msg.chat.id).await;
+    if language != Language::Fr && ctx.language.has_french(txt.to_string()) {
+        ctx.quick_reply(&msg, Text::Wablieft.translate(language))
+            .await;
     }
 }
 
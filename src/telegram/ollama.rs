@@ -1,9 +1,13 @@
-use teloxide::{prelude::*, utils::command::BotCommands};
+use teloxide::{prelude::*, types::BotCommand, utils::command::BotCommands};
 use url::Url;
 
 use crate::{
-    handler::ollama::{Identifier, Update},
+    handler::llm::{Identifier, Notifier, Update},
+    i18n::Text,
+    limits::Kind,
+    logging::{self, CorrelationId},
     ollama,
+    rules::{Family, Rewritable},
 };
 
 use super::Context;
@@ -14,14 +18,28 @@ use super::Context;
     description = "These commands are supported:"
 )]
 pub enum Command {
+    #[command(description = "Ask the LLM anything")]
     Hey(String),
+    #[command(description = "Ask the LLM anything, but louder")]
     Oi(String),
+    #[command(description = "Summarize the recent conversation in this chat")]
     Tldr,
+    #[command(description = "Summarize a web page")]
     Summary(String),
+    #[command(description = "Search the web and answer with sources")]
     DeepSearch(String),
 }
 
-impl Command {
+impl Rewritable for Command {
+    const FAMILY: Family = Family::Llm;
+
+    fn prompt(&self) -> Option<&str> {
+        match self {
+            Command::Hey(prompt) | Command::Oi(prompt) => Some(prompt),
+            Command::Tldr | Command::Summary(_) | Command::DeepSearch(_) => None,
+        }
+    }
+
     fn override_prompt(&mut self, prompt: impl ToString) {
         match self {
             Command::Hey(_) => *self = Command::Hey(prompt.to_string()),
@@ -31,7 +49,23 @@ impl Command {
             Command::DeepSearch(_) => (),
         }
     }
+}
+
+/// Commands to list in the help, deep search needs a search engine
+pub fn commands(search: bool) -> Vec<BotCommand> {
+    Command::bot_commands()
+        .into_iter()
+        .filter(|command| search || command.command != "/deepsearch")
+        .collect()
+}
 
+/// Whether the command is enabled, deep search without a search engine is left to the
+/// other handlers like any unknown command
+pub fn is_enabled(ctx: Context, command: Command) -> bool {
+    ctx.searxng.is_some() || !matches!(command, Command::DeepSearch(_))
+}
+
+impl Command {
     fn misses_prompt(&self) -> bool {
         match self {
             Command::Tldr | Command::Summary(_) => false,
@@ -42,11 +76,32 @@ impl Command {
     }
 }
 
+impl super::Limited for Command {
+    fn kinds(&self) -> &'static [Kind] {
+        match self {
+            Command::Summary(_) | Command::DeepSearch(_) => &[Kind::Llm, Kind::Web],
+            Command::Hey(_) | Command::Oi(_) | Command::Tldr => &[Kind::Llm],
+        }
+    }
+
+    fn problem(&self, _ctx: &Context, msg: &Message) -> Option<Text> {
+        if self.misses_prompt() {
+            return Some(Text::MissingPrompt);
+        }
+
+        match self {
+            Command::Summary(text) => summary_url(msg, text).err(),
+            _ => None,
+        }
+    }
+}
+
 pub async fn handler(
     ctx: Context,
     msg: Message,
-    mut command: Command,
-    overrides: super::admin::Overrides,
+    command: Command,
+    correlation_id: CorrelationId,
+    notifier: Notifier,
 ) -> Result<(), teloxide::RequestError> {
     log::info!("Received command: {command:?}, Chat ID: {}", msg.chat.id);
 
@@ -58,91 +113,74 @@ pub async fn handler(
         }
     };
 
-    if command.misses_prompt() {
-        ctx.quick_reply(&msg, "You need to provide a valid prompt")
-            .await;
-        return Ok(());
-    }
-
-    if let Some(prompt) = overrides.get_override(user.id).await {
-        command.override_prompt(prompt);
-    }
-
-    if user.id == UserId(172179034) {
-        command.override_prompt("What are the hazards of driving on a flat tire?");
-    }
+    let priority = ctx.priority(msg.chat.id, user.id).await;
+    let language = ctx.locale(&msg).await;
 
     match command {
         Command::Hey(prompt) | Command::Oi(prompt) => {
-            ctx.ollama_notifier.notify(Update::Requested {
+            let result = notifier.try_notify(Update::Requested {
                 identifier: Identifier {
                     chat_id: msg.chat.id,
                     user_id: user.id,
                     message_id: msg.id,
+                    correlation_id,
+                    language,
                 },
                 prompt,
-            })
+                priority,
+                sources: Vec::new(),
+            });
+            ctx.report_rejection(&msg, result).await;
         }
         Command::Tldr => {
             let chat_history = match ctx.store.chat_history(msg.chat.id).await {
                 Ok(Some(history)) => history,
                 Ok(None) => {
-                    ctx.bot.send_message(msg.chat.id, "No chat content found. Please let me learn longer or adjust my permissions.")
+                    ctx.bot
+                        .send_message(msg.chat.id, Text::NoChatHistory.translate(language))
                         .await?;
                     return Ok(());
                 }
                 Err(err) => {
                     log::error!("failed to fetch chat history: {err}");
-                    ctx.bot.send_message(msg.chat.id, "failure").await?;
+                    ctx.bot
+                        .send_message(
+                            msg.chat.id,
+                            super::something_went_wrong().translate(language),
+                        )
+                        .await?;
                     return Ok(());
                 }
             };
 
-            ctx.ollama_notifier.notify(Update::Requested {
+            let result = notifier.try_notify(Update::Requested {
                 identifier: Identifier {
                     chat_id: msg.chat.id,
                     user_id: user.id,
                     message_id: msg.id,
+                    correlation_id,
+                    language,
                 },
                 prompt: chat_history,
-            })
+                priority,
+                sources: Vec::new(),
+            });
+            ctx.report_rejection(&msg, result).await;
         }
         Command::Summary(text) => {
-            let url = match Url::parse(&text) {
+            let url = match summary_url(&msg, &text) {
                 Ok(url) => url,
-                Err(_) if text.is_empty() => match find_url_in_reply(&msg) {
-                    Some(url) => url,
-                    None => {
-                        ctx.quick_reply(&msg, "please provide the URL of a page to summarize")
-                            .await;
-                        return Ok(());
-                    }
-                },
-                Err(_) => {
-                    ctx.quick_reply(&msg, "Not a valid URL").await;
+                Err(text) => {
+                    ctx.reply(&msg, text).await;
                     return Ok(());
                 }
             };
 
-            let is_internal_ip = url
-                .host()
-                .map(|host| match host {
-                    url::Host::Ipv6(_) => false,
-                    url::Host::Ipv4(ipv4) => ipv4.is_private(),
-                    url::Host::Domain(domain) => domain.to_lowercase().as_str() == "localhost",
-                })
-                .unwrap_or_default();
-
-            if is_internal_ip {
-                ctx.quick_reply(&msg, "don't hack me hé klet").await;
-                return Ok(());
-            }
-
             let chat_id = msg.chat.id;
             let message_id = msg.id;
             let user_id = user.id;
 
-            tokio::task::spawn(async move {
+            logging::spawn(async move {
                 let website_content = ctx
                     .http_client
                     .get(url.clone())
@@ -165,35 +203,50 @@ pub async fn handler(
                     }
                 };
 
-                ctx.ollama_notifier.notify(Update::Requested {
+                let result = notifier.try_notify(Update::Requested {
                     identifier: Identifier {
                         chat_id,
                         user_id,
                         message_id,
+                        correlation_id,
+                        language,
                     },
                     prompt: ollama::prompts::summary(normalised.text),
+                    priority,
+                    sources: Vec::new(),
                 });
+                ctx.report_rejection(&msg, result).await;
 
                 Result::<(), reqwest::Error>::Ok(())
             });
         }
 
         Command::DeepSearch(query) => {
+            let Some(searxng) = ctx.searxng.clone() else {
+                ctx.reply(&msg, Text::SearchUnavailable).await;
+                return Ok(());
+            };
+
             let chat_id = msg.chat.id;
             let message_id = msg.id;
             let user_id = user.id;
 
-            tokio::task::spawn(async move {
-                let results = ctx.searxng.search(&query).await?;
+            logging::spawn(async move {
+                let results = searxng.search(&query).await?;
 
-                ctx.ollama_notifier.notify(Update::Requested {
+                let result = notifier.try_notify(Update::Requested {
                     identifier: Identifier {
                         chat_id,
                         user_id,
                         message_id,
+                        correlation_id,
+                        language,
                     },
-                    prompt: ollama::prompts::deep_search(query, results),
+                    prompt: ollama::prompts::deep_search(query, &results),
+                    priority,
+                    sources: results,
                 });
+                ctx.report_rejection(&msg, result).await;
 
                 Result::<(), reqwest::Error>::Ok(())
             });
@@ -203,6 +256,30 @@ pub async fn handler(
     Ok(())
 }
 
+/// The page to summarize, from the command or the message it replies to
+fn summary_url(msg: &Message, text: &str) -> Result<Url, Text> {
+    let url = match Url::parse(text) {
+        Ok(url) => url,
+        Err(_) if text.is_empty() => find_url_in_reply(msg).ok_or(Text::MissingUrl)?,
+        Err(_) => return Err(Text::InvalidUrl),
+    };
+
+    let is_internal_ip = url
+        .host()
+        .map(|host| match host {
+            url::Host::Ipv6(_) => false,
+            url::Host::Ipv4(ipv4) => ipv4.is_private(),
+            url::Host::Domain(domain) => domain.to_lowercase().as_str() == "localhost",
+        })
+        .unwrap_or_default();
+
+    if is_internal_ip {
+        return Err(Text::NoHacking);
+    }
+
+    Ok(url)
+}
+
 fn find_url_in_reply(msg: &Message) -> Option<Url> {
     msg.reply_to_message()?
         .text()?
@@ -0,0 +1,57 @@
+use teloxide::{
+    prelude::*,
+    types::{CallbackQuery, MessageEntity},
+};
+
+use crate::{handler::llm::Reasoning, i18n::Text, utils::text};
+
+use super::Context;
+
+/// UTF-16 code units per message, Telegram refuses more than 4096
+const MAX_LENGTH: usize = 4000;
+
+pub fn is_reveal(query: CallbackQuery) -> bool {
+    query.data.as_deref() == Some(Reasoning::BUTTON)
+}
+
+/// Reply to the answer with its reasoning, hidden behind spoilers
+pub async fn reveal(
+    ctx: Context,
+    query: CallbackQuery,
+    reasoning: Reasoning,
+) -> Result<(), teloxide::RequestError> {
+    let Some(answer) = query.message else {
+        ctx.bot.answer_callback_query(query.id).await?;
+        return Ok(());
+    };
+
+    let Some(reasoning) = reasoning.get(answer.chat.id, answer.id).await else {
+        let language = ctx
+            .locales
+            .language(answer.chat.id, Some(&query.from))
+            .await;
+        ctx.bot
+            .answer_callback_query(query.id)
+            .text(Text::ReasoningExpired.translate(language))
+            .show_alert(true)
+            .await?;
+        return Ok(());
+    };
+
+    ctx.bot.answer_callback_query(query.id).await?;
+
+    let mut reply_to = answer.id;
+    for part in text::split_utf16(&reasoning, MAX_LENGTH) {
+        let spoiler = MessageEntity::spoiler(0, part.encode_utf16().count());
+
+        let sent = ctx
+            .bot
+            .send_message(answer.chat.id, part)
+            .entities([spoiler])
+            .reply_to_message_id(reply_to)
+            .await?;
+        reply_to = sent.id;
+    }
+
+    Ok(())
+}
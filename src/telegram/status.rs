@@ -0,0 +1,100 @@
+use teloxide::{prelude::*, utils::command::BotCommands};
+
+use crate::{
+    health::format_uptime,
+    i18n::{Language, Text},
+    scheduler::Backend,
+};
+
+use super::Context;
+
+#[derive(BotCommands, Clone, Debug)]
+#[command(
+    rename_rule = "lowercase",
+    description = "These commands are supported:"
+)]
+pub enum Command {
+    #[command(description = "Show the state of the backends")]
+    Status,
+}
+
+pub async fn handler(
+    ctx: Context,
+    msg: Message,
+    command: Command,
+) -> Result<(), teloxide::RequestError> {
+    log::info!("Received command: {command:?}, Chat ID: {}", msg.chat.id);
+
+    match command {
+        Command::Status => {
+            let language = ctx.locale(&msg).await;
+            ctx.quick_reply(&msg, report(&ctx, language)).await
+        }
+    }
+
+    Ok(())
+}
+
+/// Health that belongs to the queue of a scheduled backend
+///
+/// The LLM jobs share one queue whichever provider answers them, so that queue gets a row of
+/// its own.
+fn health_name(backend: Backend) -> Option<&'static str> {
+    match backend {
+        Backend::InvokeAi | Backend::LocalAi => Some(backend.as_str()),
+        Backend::Llm => None,
+    }
+}
+
+/// Load of a scheduled backend and the updates its handler has yet to process
+fn load(ctx: &Context, backend: Backend, depth: usize, language: Language) -> Vec<String> {
+    let (running, waiting) = ctx.scheduler.load(backend);
+
+    vec![
+        Text::Load { running, waiting }.translate(language),
+        Text::QueuedUpdates(depth).translate(language),
+    ]
+}
+
+/// State of every backend, with the queues of the handlers that use them
+fn report(ctx: &Context, language: Language) -> String {
+    let uptime = format_uptime(ctx.monitor.uptime());
+    let mut lines = vec![Text::Uptime(uptime).translate(language)];
+
+    for backend in ctx.monitor.backends() {
+        let name = backend.health.name();
+        let report = backend.health.report();
+
+        let mut status = Text::BackendStatus(report.status()).translate(language);
+        if let Some(latency) = report.latency {
+            let latency = Text::Latency(latency.as_millis()).translate(language);
+            status = format!("{status} ({latency})");
+        }
+
+        let mut parts = vec![status];
+
+        let queue = ctx
+            .queues
+            .iter()
+            .find(|(queue, _)| health_name(*queue) == Some(name));
+        if let Some((scheduled, depth)) = queue {
+            parts.extend(load(ctx, *scheduled, depth(), language));
+        }
+
+        parts.extend(report.models.map(|models| match models.is_empty() {
+            true => Text::NoModelLoaded.translate(language),
+            false => Text::ModelsLoaded(models.join(", ")).translate(language),
+        }));
+
+        lines.push(format!("{name}: {}", parts.join(", ")));
+    }
+
+    for (scheduled, depth) in ctx.queues.iter() {
+        if health_name(*scheduled).is_none() {
+            let parts = load(ctx, *scheduled, depth(), language);
+            lines.push(format!("{}: {}", scheduled.as_str(), parts.join(", ")));
+        }
+    }
+
+    lines.join("\n")
+}
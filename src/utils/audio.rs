@@ -0,0 +1,243 @@
+//! Turning the WAV output of text to speech into Telegram voice notes
+
+use std::{fmt, io::Cursor, time::Duration};
+
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Bitrate, Channels, Encoder};
+
+/// Opus always decodes at 48 kHz
+const SAMPLE_RATE: u32 = 48_000;
+/// 20 ms frames
+const FRAME_SIZE: usize = 960;
+/// Plenty for speech
+const BITRATE: i32 = 32_000;
+/// Arbitrary, but the same for every page of the stream
+const SERIAL: u32 = 1;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to decode WAV {0}")]
+    Wav(#[from] hound::Error),
+    #[error("Failed to encode Opus {0}")]
+    Opus(#[from] opus::Error),
+    #[error("Failed to write OGG {0}")]
+    Ogg(#[from] std::io::Error),
+    #[error("No audio to encode")]
+    Empty,
+}
+
+/// Mono audio at 48 kHz
+#[derive(Debug, Default)]
+pub struct Pcm {
+    samples: Vec<i16>,
+}
+
+impl Pcm {
+    /// Decode a WAV file, mixing it down to mono and resampling it to 48 kHz
+    pub fn from_wav(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = hound::WavReader::new(Cursor::new(bytes))?;
+        let spec = reader.spec();
+
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+            hound::SampleFormat::Int => {
+                let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|sample| sample as f32 / scale))
+                    .collect::<Result<_, _>>()?
+            }
+        };
+
+        let mono: Vec<f32> = samples
+            .chunks(usize::from(spec.channels.max(1)))
+            .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+            .collect();
+
+        let samples = resample(&mono, spec.sample_rate, SAMPLE_RATE)
+            .into_iter()
+            .map(|sample| (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16)
+            .collect();
+
+        Ok(Self { samples })
+    }
+
+    pub fn append(&mut self, other: Pcm) {
+        self.samples.extend(other.samples);
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.samples.len() as f64 / f64::from(SAMPLE_RATE))
+    }
+}
+
+/// Linear interpolation, good enough for speech
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    let ratio = f64::from(from) / f64::from(to);
+    let length = (samples.len() as f64 / ratio).round() as usize;
+
+    (0..length)
+        .map(|index| {
+            let position = index as f64 * ratio;
+            let before = position.floor() as usize;
+            let after = (before + 1).min(samples.len() - 1);
+            let weight = (position - before as f64) as f32;
+
+            samples[before.min(samples.len() - 1)] * (1.0 - weight) + samples[after] * weight
+        })
+        .collect()
+}
+
+/// OGG/Opus audio, the format Telegram plays as a voice note
+///
+/// There's no waveform, `sendVoice` has no parameter for it and Telegram draws it from the audio.
+pub struct VoiceNote {
+    pub ogg: Vec<u8>,
+    pub duration: Duration,
+}
+
+impl fmt::Debug for VoiceNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "VoiceNote({} bytes, {:?})",
+            self.ogg.len(),
+            self.duration
+        )
+    }
+}
+
+impl VoiceNote {
+    /// Seconds, rounded up as Telegram expects them
+    pub fn seconds(&self) -> u32 {
+        self.duration.as_secs_f64().ceil() as u32
+    }
+}
+
+impl TryFrom<Pcm> for VoiceNote {
+    type Error = Error;
+
+    fn try_from(pcm: Pcm) -> Result<Self, Self::Error> {
+        if pcm.samples.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        let mut encoder = Encoder::new(SAMPLE_RATE, Channels::Mono, Application::Voip)?;
+        encoder.set_bitrate(Bitrate::Bits(BITRATE))?;
+        let pre_skip = encoder.get_lookahead()? as usize;
+
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_packet(
+            head(pre_skip as u16),
+            SERIAL,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )?;
+        writer.write_packet(tags(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        // the encoder delays the audio, so it needs to be fed that much extra silence
+        let mut samples = pcm.samples.clone();
+        samples.resize(samples.len() + pre_skip, 0);
+        let padded = samples.len().div_ceil(FRAME_SIZE) * FRAME_SIZE;
+        samples.resize(padded, 0);
+
+        let frames = samples.len() / FRAME_SIZE;
+        let mut packet = vec![0; 4000];
+
+        for (index, frame) in samples.chunks(FRAME_SIZE).enumerate() {
+            let size = encoder.encode(frame, &mut packet)?;
+
+            let (end, granule) = if index + 1 == frames {
+                // the last granule position trims the padding
+                (
+                    PacketWriteEndInfo::EndStream,
+                    (pre_skip + pcm.samples.len()) as u64,
+                )
+            } else {
+                (
+                    PacketWriteEndInfo::NormalPacket,
+                    ((index + 1) * FRAME_SIZE) as u64,
+                )
+            };
+
+            writer.write_packet(packet[..size].to_vec(), SERIAL, end, granule)?;
+        }
+
+        Ok(Self {
+            ogg: writer.into_inner(),
+            duration: pcm.duration(),
+        })
+    }
+}
+
+/// Identification header, see RFC 7845
+fn head(pre_skip: u16) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1); // version
+    head.push(1); // channels
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    head.extend_from_slice(&0_i16.to_le_bytes()); // output gain
+    head.push(0); // mono/stereo mapping
+    head
+}
+
+/// Comment header, without comments
+fn tags() -> Vec<u8> {
+    let vendor = env!("CARGO_PKG_NAME").as_bytes();
+
+    let mut tags = Vec::new();
+    tags.extend_from_slice(b"OpusTags");
+    tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    tags.extend_from_slice(vendor);
+    tags.extend_from_slice(&0_u32.to_le_bytes());
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(sample_rate: u32, seconds: f32) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for index in 0..(sample_rate as f32 * seconds) as u32 {
+            let tone = (index as f32 * 440.0 * std::f32::consts::TAU / sample_rate as f32).sin();
+            writer.write_sample((tone * 8000.0) as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn test_voice_note() {
+        let mut pcm = Pcm::from_wav(&wav(22_050, 1.0)).unwrap();
+        pcm.append(Pcm::from_wav(&wav(16_000, 0.5)).unwrap());
+
+        assert_eq!(pcm.duration().as_millis(), 1500);
+
+        let note = VoiceNote::try_from(pcm).unwrap();
+
+        assert_eq!(note.seconds(), 2);
+        assert!(note.ogg.starts_with(b"OggS"));
+        assert!(note.ogg.windows(8).any(|window| window == b"OpusHead"));
+
+        assert!(matches!(
+            VoiceNote::try_from(Pcm::default()),
+            Err(Error::Empty)
+        ));
+    }
+}
@@ -19,17 +19,19 @@ impl LanguageDetector {
                 Language::French,
                 Language::English,
                 Language::Dutch,
+                Language::German,
+                Language::Spanish,
+                Language::Italian,
+                Language::Portuguese,
             ])
             .build()
             .into(),
         }
     }
 
-    /// Detects the language, defaults to English if none is found
-    pub fn detect_language(&self, text: &str) -> Language {
-        self.detector
-            .detect_language_of(text)
-            .unwrap_or(Language::English)
+    /// Detects the language, `None` when it's too ambiguous
+    pub fn detect_language(&self, text: &str) -> Option<Language> {
+        self.detector.detect_language_of(text)
     }
 
     pub fn has_french(&self, msg: String) -> bool {
@@ -43,10 +45,7 @@ impl LanguageDetector {
             return false;
         };
 
-        match language {
-            Language::Dutch | Language::English => false,
-            Language::French => true,
-        }
+        language == Language::French
     }
 
     pub fn enable(&self) {
@@ -0,0 +1,337 @@
+//! LLM markdown as Telegram HTML
+//!
+//! Models answer in markdown, Telegram understands a handful of HTML tags. Markdown without
+//! an HTML counterpart, or that isn't closed, stays as it is.
+
+use super::text;
+
+/// Link schemes Telegram accepts
+const SCHEMES: [&str; 4] = ["http://", "https://", "tg://", "mailto:"];
+
+/// Convert markdown to the HTML Telegram accepts, with everything else escaped
+pub fn to_html(markdown: &str) -> String {
+    let mut blocks = Vec::new();
+    let mut lines = markdown.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        let trimmed = line.trim_start();
+
+        if let Some(language) = trimmed.strip_prefix("```") {
+            // an unclosed code block runs until the end
+            let code: Vec<&str> = lines
+                .by_ref()
+                .take_while(|line| !line.trim_start().starts_with("```"))
+                .collect();
+
+            blocks.push(code_block(language.trim(), &code.join("\n")));
+        } else if trimmed.starts_with('>') {
+            let mut quoted = vec![inline(unquote(trimmed))];
+            while let Some(line) = lines.next_if(|line| line.trim_start().starts_with('>')) {
+                quoted.push(inline(unquote(line.trim_start())));
+            }
+
+            blocks.push(format!("<blockquote>{}</blockquote>", quoted.join("\n")));
+        } else {
+            blocks.push(block(line));
+        }
+    }
+
+    blocks.join("\n")
+}
+
+/// Split markdown into parts of at most `max` UTF-16 code units
+///
+/// Parts end between paragraphs or sentences, code blocks cut in two are closed at the end
+/// of one part and opened again at the start of the next, their lines stay whole. Bold text or a link cut in two is
+/// left unclosed, so both parts show its markdown as it is.
+pub fn split(markdown: &str, max: usize) -> Vec<String> {
+    /// Room to close and reopen a code block
+    const FENCES: usize = 32;
+
+    let mut parts = text::split_utf16_verbatim(markdown, max.saturating_sub(FENCES), |done| {
+        unclosed_fence(done).is_some()
+    });
+    let mut open: Option<String> = None;
+
+    for part in &mut parts {
+        if let Some(fence) = open.take() {
+            part.insert_str(0, &format!("{fence}\n"));
+        }
+
+        open = unclosed_fence(part);
+        if open.is_some() {
+            part.push_str("\n```");
+        }
+    }
+
+    parts
+}
+
+/// The opening line of a code block that's still open at the end of the text
+fn unclosed_fence(markdown: &str) -> Option<String> {
+    let mut open = None;
+
+    for line in markdown.lines().map(str::trim_start) {
+        if line.starts_with("```") {
+            open = match open {
+                None => Some(line.chars().take(16).collect()),
+                Some(_) => None,
+            };
+        }
+    }
+
+    open
+}
+
+fn code_block(language: &str, code: &str) -> String {
+    if language.is_empty() {
+        return format!("<pre>{}</pre>", escape(code));
+    }
+
+    format!(
+        "<pre><code class=\"language-{}\">{}</code></pre>",
+        escape(language),
+        escape(code)
+    )
+}
+
+fn unquote(line: &str) -> &str {
+    let line = line.trim_start_matches('>');
+    line.strip_prefix(' ').unwrap_or(line)
+}
+
+/// A line outside code blocks and quotes
+fn block(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) {
+        if let Some(title) = trimmed[hashes..].strip_prefix(' ') {
+            return format!("<b>{}</b>", inline(title.trim()));
+        }
+    }
+
+    let rule = trimmed.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|c| trimmed.chars().all(|other| other == *c));
+    if rule {
+        return String::from("———");
+    }
+
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(item) = trimmed.strip_prefix(bullet) {
+            return format!("{indent}• {}", inline(item));
+        }
+    }
+
+    inline(line)
+}
+
+/// Formatting within a line
+fn inline(text: &str) -> String {
+    let mut html = String::new();
+    let mut rest = text;
+    let mut previous = None;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some((formatted, length)) = formatted(rest, previous) {
+            html.push_str(&formatted);
+            previous = rest[..length].chars().last();
+            rest = &rest[length..];
+            continue;
+        }
+
+        html.push_str(&escape(&rest[..c.len_utf8()]));
+        previous = Some(c);
+        rest = &rest[c.len_utf8()..];
+    }
+
+    html
+}
+
+/// Formatting at the start of the text, with the length of the markdown it replaces
+fn formatted(text: &str, previous: Option<char>) -> Option<(String, usize)> {
+    // underscores in the middle of words, like snake_case, aren't formatting
+    let word = previous.is_some_and(char::is_alphanumeric);
+
+    if let Some(code) = text.strip_prefix('`') {
+        let end = code.find('`').filter(|end| *end > 0)?;
+        return Some((format!("<code>{}</code>", escape(&code[..end])), end + 2));
+    }
+
+    if text.starts_with('[') {
+        return link(text);
+    }
+
+    let tags = [
+        ("**", "b"),
+        ("__", "b"),
+        ("~~", "s"),
+        ("*", "i"),
+        ("_", "i"),
+    ];
+
+    for (marker, tag) in tags {
+        if word && marker.starts_with('_') {
+            continue;
+        }
+
+        let Some((inner, length)) = delimited(text, marker) else {
+            continue;
+        };
+
+        let after = text[length..].chars().next();
+        if marker.starts_with('_') && after.is_some_and(char::is_alphanumeric) {
+            continue;
+        }
+
+        return Some((format!("<{tag}>{}</{tag}>", inline(inner)), length));
+    }
+
+    None
+}
+
+/// The text between a pair of markers, when it doesn't start or end with whitespace
+fn delimited<'a>(text: &'a str, marker: &str) -> Option<(&'a str, usize)> {
+    let body = text.strip_prefix(marker)?;
+    let end = body.find(marker)?;
+    let inner = &body[..end];
+
+    let padded = inner.starts_with(char::is_whitespace) || inner.ends_with(char::is_whitespace);
+    if inner.is_empty() || padded {
+        return None;
+    }
+
+    Some((inner, marker.len() * 2 + end))
+}
+
+/// `[label](url)`, for the schemes Telegram accepts
+fn link(text: &str) -> Option<(String, usize)> {
+    let label_end = closing(text, '[', ']')?;
+    let label = &text[1..label_end];
+
+    let rest = &text[label_end + 1..];
+    if !rest.starts_with('(') {
+        return None;
+    }
+
+    let url_end = label_end + 1 + closing(rest, '(', ')')?;
+    let url = text[label_end + 2..url_end].trim();
+
+    if label.is_empty() || !SCHEMES.iter().any(|scheme| url.starts_with(scheme)) {
+        return None;
+    }
+
+    let html = format!("<a href=\"{}\">{}</a>", escape(url), inline(label));
+    Some((html, url_end + 1))
+}
+
+/// Index of the bracket that closes the one `text` starts with, nested pairs included
+fn closing(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+
+    text.char_indices().find_map(|(index, c)| {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(index);
+            }
+        }
+
+        None
+    })
+}
+
+/// Escape the characters HTML gives a meaning
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline() {
+        assert_eq!(
+            to_html("**Bold**, *italic*, ~~gone~~ and `a < b`"),
+            "<b>Bold</b>, <i>italic</i>, <s>gone</s> and <code>a &lt; b</code>"
+        );
+        assert_eq!(
+            to_html("see [the docs](https://example.com/a_(b)) & more"),
+            "see <a href=\"https://example.com/a_(b)\">the docs</a> &amp; more"
+        );
+        // the link starts at the bracket that its label closes
+        assert_eq!(
+            to_html("[x] and [docs](https://d)"),
+            "[x] and <a href=\"https://d\">docs</a>"
+        );
+
+        // not formatting
+        assert_eq!(to_html("snake_case_name"), "snake_case_name");
+        assert_eq!(to_html("2 * 3 * 4"), "2 * 3 * 4");
+        assert_eq!(to_html("**unclosed"), "**unclosed");
+        assert_eq!(
+            to_html("[x](javascript:alert(1))"),
+            "[x](javascript:alert(1))"
+        );
+        assert_eq!(to_html("<b>html</b>"), "&lt;b&gt;html&lt;/b&gt;");
+    }
+
+    #[test]
+    fn test_blocks() {
+        let markdown = "## Sources\n- [1] **one**\n  * two\n---\n> quoted\n> again\n1. first";
+        assert_eq!(
+            to_html(markdown),
+            "<b>Sources</b>\n• [1] <b>one</b>\n  • two\n———\n<blockquote>quoted\nagain</blockquote>\n1. first"
+        );
+
+        let markdown = "Run:\n```rust\nlet x = *y & 1;\n```\ndone\n```\nunclosed <code>";
+        assert_eq!(
+            to_html(markdown),
+            "Run:\n<pre><code class=\"language-rust\">let x = *y &amp; 1;</code></pre>\ndone\n<pre>unclosed &lt;code&gt;</pre>"
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let markdown = "Intro.\n\n```rust\nfn one() {}\n\nfn two() {}\n```\n\nOutro.";
+        let parts = split(markdown, 32 + 30);
+
+        assert_eq!(
+            parts,
+            vec![
+                "Intro.\n\n```rust\nfn one() {}\n```",
+                "```rust\nfn two() {}\n```\n\nOutro.",
+            ]
+        );
+
+        assert!(parts.iter().all(|part| unclosed_fence(part).is_none()));
+
+        // code is cut between lines, which keep their indentation
+        let markdown = "```rust\nfn main() {\n    let one = 1;\n    let two = 2;\n}\n```";
+        assert_eq!(
+            split(markdown, 32 + 45),
+            vec![
+                "```rust\nfn main() {\n    let one = 1;\n```",
+                "```rust\n    let two = 2;\n}\n```",
+            ]
+        );
+    }
+}
@@ -1,4 +1,7 @@
+pub mod audio;
 pub mod languages;
+pub mod markdown;
 pub mod searxng;
+pub mod text;
 
 pub use searxng::SearXng;
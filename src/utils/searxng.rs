@@ -2,12 +2,17 @@ use std::sync::Arc;
 
 use serde::Deserialize;
 
+use crate::logging::Correlate;
+
+/// Results handed to the model, more don't fit in its context
+const MAX_RESULTS: usize = 6;
+
 #[derive(Deserialize)]
 pub struct Response {
     results: Vec<SearchResult>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Debug, Deserialize)]
 pub struct SearchResult {
     pub url: String,
     pub title: String,
@@ -32,10 +37,14 @@ impl SearXng {
         self.http_client
             .get(format!("{}/search", self.api_url))
             .query(&[("q", query), ("format", "json")])
+            .correlate()
             .send()
             .await?
             .json::<Response>()
             .await
-            .map(|mut response| response.results.drain(0..=5).collect())
+            .map(|mut response| {
+                response.results.truncate(MAX_RESULTS);
+                response.results
+            })
     }
 }
@@ -0,0 +1,124 @@
+/// Characters that end a sentence when followed by whitespace
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', '…'];
+
+/// Split text into chunks of at most `max` characters
+///
+/// Chunks preferably end between paragraphs, then between sentences and then between
+/// words, words longer than a chunk are cut.
+pub fn split(text: &str, max: usize) -> Vec<String> {
+    split_by(text, max, |_| 1, |_| false)
+}
+
+/// Split text into chunks of at most `max` UTF-16 code units, the length Telegram counts
+pub fn split_utf16(text: &str, max: usize) -> Vec<String> {
+    split_by(text, max, char::len_utf16, |_| false)
+}
+
+/// Like [`split_utf16`], but `verbatim` tells whether a cut after the given text falls in
+/// text whose lines keep their indentation, like a code block
+///
+/// Such cuts are made at the end of a line and the next chunk starts with the next line as
+/// it is.
+pub fn split_utf16_verbatim(
+    text: &str,
+    max: usize,
+    verbatim: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    split_by(text, max, char::len_utf16, verbatim)
+}
+
+fn split_by(
+    text: &str,
+    max: usize,
+    size: fn(char) -> usize,
+    verbatim: impl Fn(&str) -> bool,
+) -> Vec<String> {
+    let text = text.trim();
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while rest.chars().map(size).sum::<usize>() > max {
+        let mut length = 0;
+        let limit = rest
+            .char_indices()
+            .find(|(_, c)| {
+                length += size(*c);
+                length > max
+            })
+            .map(|(index, _)| index)
+            .unwrap_or(rest.len());
+        let window = &rest[..limit];
+
+        let mut cut = paragraph_end(window)
+            .or_else(|| sentence_end(window))
+            .or_else(|| window.rfind(char::is_whitespace))
+            .filter(|cut| *cut > 0)
+            .unwrap_or(limit);
+
+        // the rest is a suffix of the text
+        let done = text.len() - rest.len();
+        let keep = verbatim(&text[..done + cut]);
+        if keep && !rest[cut..].starts_with('\n') {
+            cut = window.rfind('\n').filter(|cut| *cut > 0).unwrap_or(cut);
+        }
+
+        chunks.push(rest[..cut].trim_end().to_string());
+        rest = match keep {
+            true => rest[cut..].trim_start_matches('\n'),
+            false => rest[cut..].trim_start(),
+        };
+    }
+
+    if !rest.is_empty() {
+        chunks.push(rest.to_string());
+    }
+
+    chunks
+}
+
+fn paragraph_end(window: &str) -> Option<usize> {
+    window.rfind("\n\n")
+}
+
+/// End of the last complete sentence
+fn sentence_end(window: &str) -> Option<usize> {
+    window
+        .char_indices()
+        .filter(|(_, c)| SENTENCE_ENDS.contains(c))
+        .map(|(index, c)| index + c.len_utf8())
+        .rfind(|end| window[*end..].starts_with(char::is_whitespace))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("  short  ", 10), vec!["short"]);
+        assert!(split(" ", 10).is_empty());
+
+        assert_eq!(
+            split("First one. Second one! Third?", 24),
+            vec!["First one. Second one!", "Third?"]
+        );
+
+        assert_eq!(
+            split("A paragraph.\n\nAnother one. With two sentences.", 40),
+            vec!["A paragraph.", "Another one. With two sentences."]
+        );
+
+        // no sentences, so between words
+        assert_eq!(
+            split("one two three four", 9),
+            vec!["one two", "three", "four"]
+        );
+
+        // words longer than a chunk are cut, without splitting characters
+        assert_eq!(split("ééééé", 2), vec!["éé", "éé", "é"]);
+
+        // emoji are two UTF-16 code units
+        assert_eq!(split_utf16("😀😀😀", 4), vec!["😀😀", "😀"]);
+        assert_eq!(split_utf16("a😀b", 2), vec!["a", "😀", "b"]);
+    }
+}